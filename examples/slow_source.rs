@@ -22,7 +22,7 @@ fn create_number_stream() -> impl futures::Stream<Item = NumberData> {
 
     let mut counter: i32 = 0;
     IntervalStream::new(interval(Duration::from_millis(300)))
-        .take(10 as usize)
+        .take(10)
         .map(move |_| {
             counter += 1;
             log::info!("produce {}", counter);
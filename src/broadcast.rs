@@ -0,0 +1,60 @@
+use futures::Stream;
+use tokio::sync::broadcast;
+
+use crate::{ExternalBuffer, ExternalBufferedStream};
+
+/// Receiver of `ExternalBufferedStream::subscribe`
+pub type BroadcastReceiver<T> = broadcast::Receiver<T>;
+
+/// Items a subscriber may fall behind before it misses some
+pub(crate) const BROADCAST_CAPACITY: usize = 1024;
+
+impl<T, B, S> ExternalBufferedStream<T, B, S>
+where
+    T: Send + Clone,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    /// Receive a copy of every item `broadcast` hands out from now on,
+    /// earlier items are missed. A subscriber more than 1024 items behind
+    /// gets `RecvError::Lagged` and skips ahead.
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        self.broadcaster
+            .get_or_init(|| broadcast::channel(BROADCAST_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Drain the stream to every subscriber, unlike competing consumers
+    /// each one gets all items. Items are dropped while nobody subscribed.
+    /// Subscribers see the channel closed once the stream ends.
+    pub async fn broadcast(mut self) {
+        while let Some(item) = self.recv().await {
+            if let Some(sender) = self.broadcaster.get() {
+                // only fails without subscribers
+                let _ = sender.send(item);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::MemoryBuffer;
+    use crate::ExternalBufferedStream;
+
+    #[tokio::test]
+    async fn test_every_subscriber_gets_every_item() {
+        let stream =
+            ExternalBufferedStream::new(futures::stream::iter(1..=5), MemoryBuffer::default());
+        let receivers = [stream.subscribe(), stream.subscribe()];
+        tokio::spawn(stream.broadcast());
+
+        for mut receiver in receivers {
+            let mut items = Vec::new();
+            while let Ok(item) = receiver.recv().await {
+                items.push(item);
+            }
+            assert_eq!(items, vec![1, 2, 3, 4, 5]);
+        }
+    }
+}
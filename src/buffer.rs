@@ -1,15 +1,128 @@
 #[cfg(feature = "sled")]
 mod sled;
 #[cfg(feature = "sled")]
-pub use sled::ExternalBufferSled;
+pub use sled::{ExternalBufferSled, MergeOrder, OpenMode, RepairReport, SledCursor};
+
+#[cfg(feature = "sled")]
+mod multi_queue;
+#[cfg(feature = "sled")]
+pub use multi_queue::MultiQueueBuffer;
+
+#[cfg(feature = "sled")]
+mod delay_queue;
+#[cfg(feature = "sled")]
+pub use delay_queue::ExternalBufferDelayQueue;
+
+#[cfg(feature = "queue")]
+mod lock;
+#[cfg(feature = "spin-lock")]
+pub use lock::SpinLock;
+#[cfg(feature = "queue")]
+pub use lock::{Lock, LockKind, StdLock};
 
 #[cfg(feature = "queue")]
 mod queue;
 #[cfg(feature = "queue")]
-pub use queue::ExternalBufferQueue;
+pub use queue::{ExternalBufferQueue, KeyOrdered};
+
+#[cfg(feature = "queue")]
+mod vecdeque;
+#[cfg(feature = "queue")]
+pub use vecdeque::ExternalBufferVecDeque;
+
+#[cfg(feature = "queue")]
+mod ring;
+#[cfg(feature = "queue")]
+pub use ring::ExternalBufferRing;
 
+mod tiered;
+pub use tiered::TieredBuffer;
+
+mod bounded;
+pub use bounded::BoundedBuffer;
+
+mod map;
+pub use map::MapBuffer;
+
+mod sampling;
+pub use sampling::SamplingBuffer;
+
+#[cfg(feature = "test-util")]
+mod recording;
+#[cfg(feature = "test-util")]
+pub use recording::{BufferCall, CallLog, RecordingBuffer};
+
+mod sync_buffer;
+pub use sync_buffer::{SyncBuffer, SyncExternalBuffer};
+
+use std::task::{Context, Poll};
+
+use crate::trace::event;
 use crate::Error;
 
+/// An item with the key its buffer stored it under, `None` for buffers
+/// without keys, see `ExternalBuffer::shift_keyed`
+pub type KeyedItem<T> = (Option<u64>, T);
+
+/// How well a buffer keeps its items when the process goes away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Items are lost with the process, e.g. in memory buffers
+    Volatile,
+    /// Items survive a restart, e.g. sled
+    Persistent,
+}
+
+/// What a size limited buffer does with a push that does not fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Fail the push with `Error::BufferFull`
+    Reject,
+    /// Discard the oldest items until the new one fits
+    DropOldest,
+    /// Discard the pushed item, the push still succeeds
+    DropNewest,
+}
+
+/// What became of a pushed item, see `ExternalBuffer::push_outcome`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushOutcome<T> {
+    /// The item is buffered
+    Stored,
+    /// The item was discarded, e.g. by `OverflowPolicy::DropNewest`
+    DroppedNewest,
+    /// The item is buffered, the oldest items were discarded to make room
+    /// for it. A large item may take the room of several.
+    EvictedOldest(Vec<T>),
+}
+
+/// An item made ready to push by `ExternalBuffer::prepare_push`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreparedPush<T> {
+    /// Nothing was prepared, the item is pushed as it is
+    Item(T),
+    /// The item serialized by the buffer that pushes it
+    Serialized(Vec<u8>),
+}
+
+/// What a buffer does with an item pushed under a key already in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Fail the push with `Error::DuplicateKey`
+    Reject,
+    /// Keep both, items under the same key are shifted in push order
+    Suffix,
+}
+
+/// What a buffer does with a stored item that fails to decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// Return the error from `shift`
+    Abort,
+    /// Drop the item and shift the next one
+    Skip,
+}
+
 /// The external buffer here allow us to:
 ///   - save items in an external perssistant storage to achieve crash save
 ///     for data.
@@ -20,4 +133,201 @@ pub trait ExternalBuffer<T: Sized>: Send + Sync {
     async fn push(&self, item: T) -> Result<(), Error>; // to end of buffer
 
     async fn shift(&self) -> Result<Option<T>, Error>; // from head of buffer
+
+    /// Push reporting whether the item was actually buffered, for producers
+    /// counting what overflow policies or sampling drop. By default every
+    /// successful push is reported as stored.
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error>
+    where
+        T: Send + 'async_trait,
+    {
+        self.push(item).await?;
+        Ok(PushOutcome::Stored)
+    }
+
+    /// The part of a push that does not touch the buffered items, e.g.
+    /// serializing. `ExternalBufferedStreamBuilder::ingest_concurrency`
+    /// prepares several items at once off the ingest task, then pushes them
+    /// one at a time in source order with `push_prepared`. By default
+    /// nothing is prepared.
+    fn prepare_push(&self, item: T) -> Result<PreparedPush<T>, Error> {
+        Ok(PreparedPush::Item(item))
+    }
+
+    /// Push an item `prepare_push` of this buffer made ready
+    async fn push_prepared(&self, prepared: PreparedPush<T>) -> Result<(), Error>
+    where
+        T: Send + 'async_trait,
+    {
+        match prepared {
+            PreparedPush::Item(item) => self.push(item).await,
+            PreparedPush::Serialized(_) => Err(Error::Custom(
+                "serialized item pushed to a buffer that does not serialize".into(),
+            )),
+        }
+    }
+
+    /// The synchronous side of buffers that never await anything, the
+    /// stream then pushes and shifts through it without boxing a future per
+    /// item. `None` for genuinely async buffers.
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        None
+    }
+
+    /// Shift without awaiting if the buffer can, `None` means `shift` has
+    /// to be used instead. Wrappers without a synchronous side of their own
+    /// may still forward their inner buffer's here.
+    fn shift_now(&self) -> Option<Result<Option<T>, Error>> {
+        self.as_sync().map(|buffer| buffer.shift_sync())
+    }
+
+    /// Shift along with the key the item was stored under, for buffers
+    /// keeping items under durable keys such as sled. `None` for the rest.
+    async fn shift_keyed(&self) -> Result<Option<KeyedItem<T>>, Error> {
+        Ok(self.shift().await?.map(|item| (None, item)))
+    }
+
+    /// `shift_now` along with the key, see `shift_keyed`
+    fn shift_now_keyed(&self) -> Option<Result<Option<KeyedItem<T>>, Error>> {
+        self.shift_now()
+            .map(|result| result.map(|item| item.map(|item| (None, item))))
+    }
+
+    /// Put a shifted item back where it was shifted from, in front of
+    /// everything else, without awaiting, e.g. one the stream took out but
+    /// never delivered. `Err` hands the item back when the buffer can not,
+    /// the default, the caller then has to push it instead.
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        Err(item)
+    }
+
+    /// When the earliest buffered item that `shift` does not return yet
+    /// becomes ready, e.g. of `ExternalBufferDelayQueue`. The stream then
+    /// waits for that time instead of only for the next push.
+    fn next_ready_at(&self) -> Option<std::time::SystemTime> {
+        None
+    }
+
+    /// Number of items currently buffered, should be cheap to call.
+    /// Buffers that do not count their items keep the default of 0.
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Maximum number of items the buffer can hold, `None` if unbounded
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    /// How many more items fit, e.g. for monitoring across backends,
+    /// `None` if the number of items is unbounded. By default what
+    /// `capacity` leaves of `len`. Always a number of items, a limit in
+    /// bytes like `ExternalBufferSled::with_max_bytes` is reported by
+    /// `ExternalBufferSled::bytes_remaining`.
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        Ok(self
+            .capacity()
+            .map(|capacity| capacity.saturating_sub(self.len())))
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Volatile
+    }
+
+    /// Push-to-shift latency of the items shifted so far, `None` if the
+    /// buffer does not track it
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        None
+    }
+
+    /// Remove the buffered items `pred` matches without shifting the others
+    /// out, e.g. tasks of a cancelled job, returns how many were removed.
+    /// By default every item is shifted and the kept ones pushed back,
+    /// buffers able to remove in place override it.
+    async fn remove_if<F>(&self, pred: F) -> Result<usize, Error>
+    where
+        F: Fn(&T) -> bool + Send + 'async_trait,
+        T: Send,
+        Self: Sized,
+    {
+        let mut kept = Vec::new();
+        let mut removed = 0;
+        while let Some(item) = self.shift().await? {
+            if pred(&item) {
+                removed += 1;
+            } else {
+                kept.push(item);
+            }
+        }
+        for item in kept {
+            self.push(item).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Make sure pushed items are durably persisted. Buffers without a
+    /// persistent storage have nothing to do here.
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Push through the synchronous side of `buffer` when it has one
+pub(crate) async fn push_item<T, B>(buffer: &B, item: T) -> Result<(), Error>
+where
+    B: ExternalBuffer<T> + ?Sized,
+{
+    match buffer.as_sync() {
+        Some(buffer) => buffer.push_sync(item),
+        None => buffer.push(item).await,
+    }
+}
+
+/// Put items shifted but never delivered back in front of `buffer` with
+/// `push_front_now`, keeping their order. Returns, in order, the ones the
+/// buffer could not take back that way.
+pub(crate) fn push_front_now<T, B>(buffer: &B, items: Vec<T>) -> Vec<T>
+where
+    B: ExternalBuffer<T> + ?Sized,
+{
+    // the newest first, so they end up in the order they were shifted
+    let mut rest = Vec::new();
+    for item in items.into_iter().rev() {
+        match buffer.push_front_now(item) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => event!(error; "Failed to restore an undelivered item: {:?}", e),
+            Err(item) => rest.push(item),
+        }
+    }
+    rest.reverse();
+    rest
+}
+
+/// Push an undelivered item back to the tail of `buffer` if it takes it
+/// without waiting, e.g. while dropping where waiting could block the
+/// executor. Logs the item as lost otherwise.
+pub(crate) fn push_now<T, B>(buffer: &B, item: T)
+where
+    B: ExternalBuffer<T> + ?Sized,
+{
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let pushed = match buffer.as_sync() {
+        Some(buffer) => Poll::Ready(buffer.push_sync(item)),
+        None => buffer.push(item).as_mut().poll(&mut cx),
+    };
+    match pushed {
+        Poll::Ready(Ok(())) => {}
+        Poll::Ready(Err(e)) => {
+            event!(error; "Failed to restore an undelivered item: {:?}", e)
+        }
+        Poll::Pending => {
+            event!(error; "Undelivered item lost, the buffer can not take it back without waiting.")
+        }
+    }
 }
@@ -0,0 +1,194 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, KeyedItem, PushOutcome};
+
+/// Limit the number of items an inner buffer may hold, pushes beyond the
+/// capacity are rejected with `Error::BufferFull`.
+pub struct BoundedBuffer<B> {
+    inner: B,
+    capacity: usize,
+    // pushes let through the capacity check that have not finished yet
+    reserved: AtomicUsize,
+}
+
+/// A slot taken by a push in flight, given back once the push is done
+struct Reservation<'a>(&'a AtomicUsize);
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<B> BoundedBuffer<B> {
+    pub fn new(inner: B, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            reserved: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Take a slot for a push, concurrent pushes each count the ones in
+    /// flight before them so they can not all fill the last free slot. A
+    /// finished push briefly counts twice, in `len` and as reserved, which
+    /// only ever rejects early.
+    fn reserve<T>(&self) -> Result<Reservation<'_>, Error>
+    where
+        B: ExternalBuffer<T>,
+    {
+        let in_flight = self.reserved.fetch_add(1, Ordering::AcqRel);
+        let reservation = Reservation(&self.reserved);
+        if self.inner.len() + in_flight >= self.capacity {
+            return Err(Error::BufferFull);
+        }
+        Ok(reservation)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, B> ExternalBuffer<T> for BoundedBuffer<B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T>,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        let _reservation = self.reserve::<T>()?;
+        self.inner.push(item).await
+    }
+
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        let _reservation = self.reserve::<T>()?;
+        self.inner.push_outcome(item).await
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.inner.shift().await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+
+    async fn shift_keyed(&self) -> Result<Option<KeyedItem<T>>, Error> {
+        self.inner.shift_keyed().await
+    }
+
+    fn shift_now(&self) -> Option<Result<Option<T>, Error>> {
+        self.inner.shift_now()
+    }
+
+    fn shift_now_keyed(&self) -> Option<Result<Option<KeyedItem<T>>, Error>> {
+        self.inner.shift_now_keyed()
+    }
+
+    /// Not limited, the item was counted before it was shifted
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        self.inner.push_front_now(item)
+    }
+
+    fn next_ready_at(&self) -> Option<std::time::SystemTime> {
+        self.inner.next_ready_at()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.capacity)
+    }
+
+    /// The tighter of this capacity and the inner buffer's own limit
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        let left = self.capacity.saturating_sub(self.inner.len());
+        Ok(Some(match self.inner.space_remaining().await? {
+            Some(inner_left) => left.min(inner_left),
+            None => left,
+        }))
+    }
+
+    fn durability(&self) -> Durability {
+        self.inner.durability()
+    }
+
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        self.inner.latency_stats()
+    }
+}
+
+#[cfg(all(test, feature = "queue"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferVecDeque;
+
+    #[tokio::test]
+    async fn test_reject_when_full() {
+        let buffer = BoundedBuffer::new(ExternalBufferVecDeque::new(), 2);
+
+        buffer.push(1).await.unwrap();
+        buffer.push(2).await.unwrap();
+        assert!(matches!(buffer.push(3).await, Err(Error::BufferFull)));
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(1));
+        buffer.push(3).await.unwrap();
+        assert_eq!(buffer.len(), 2);
+    }
+
+    /// Yields before every push, so concurrent pushes interleave
+    struct YieldingPush(ExternalBufferVecDeque<i32>);
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for YieldingPush {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            tokio::task::yield_now().await;
+            self.0.push(item).await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            self.0.shift().await
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_pushes_do_not_exceed_capacity() {
+        let buffer = BoundedBuffer::new(YieldingPush(ExternalBufferVecDeque::new()), 3);
+
+        let results = futures::future::join_all((0..10).map(|i| buffer.push(i))).await;
+        let pushed = results.iter().filter(|result| result.is_ok()).count();
+        assert_eq!(pushed, 3);
+        assert_eq!(buffer.len(), 3);
+
+        // the slots of finished pushes are given back
+        buffer.shift().await.unwrap();
+        buffer.push(10).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_space_remaining() {
+        let buffer = BoundedBuffer::new(ExternalBufferVecDeque::new(), 3);
+        for (i, left) in [(1, 2), (2, 1), (3, 0)] {
+            buffer.push(i).await.unwrap();
+            assert_eq!(buffer.space_remaining().await.unwrap(), Some(left));
+        }
+
+        let unbounded = buffer.inner();
+        assert_eq!(unbounded.space_remaining().await.unwrap(), None);
+
+        // an inner limit tighter than the capacity wins
+        let nested = BoundedBuffer::new(BoundedBuffer::new(ExternalBufferVecDeque::new(), 2), 5);
+        nested.push(1).await.unwrap();
+        assert_eq!(nested.space_remaining().await.unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Error, ExternalBufferSerde};
+
+use super::{Durability, ExternalBuffer};
+
+/// Sled buffer whose items only become available at a given time, e.g. for
+/// scheduled tasks. Items are keyed by `ready_at || id`, so they are shifted
+/// in the order they become ready.
+pub struct ExternalBufferDelayQueue {
+    db: sled::Db,
+    len: AtomicUsize,
+}
+
+impl ExternalBufferDelayQueue {
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let db = super::ExternalBufferSled::open_db(path.as_ref(), sled::Config::new())?;
+        let len = AtomicUsize::new(db.len());
+        Ok(Self { db, len })
+    }
+
+    /// Push an item `shift` only returns once `ready_at` has passed
+    pub fn push_at<T: ExternalBufferSerde>(
+        &self,
+        ready_at: SystemTime,
+        item: T,
+    ) -> Result<(), Error> {
+        let serialized = item.into_external_buffer()?;
+        // ids only grow, items ready at the same time stay FIFO
+        let id = self.db.generate_id()?;
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&Self::micros_of(ready_at).to_be_bytes());
+        key[8..].copy_from_slice(&id.to_be_bytes());
+        self.db.insert(key, serialized)?;
+        self.len.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// When the earliest buffered item becomes ready, `None` if empty
+    pub fn next_ready_at(&self) -> Result<Option<SystemTime>, Error> {
+        match self.db.first()? {
+            Some((key, _)) => Ok(Some(Self::ready_at_of(&key)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn shift_item<T: ExternalBufferSerde>(&self) -> Result<Option<T>, Error> {
+        let now = SystemTime::now();
+        loop {
+            let Some((key, _)) = self.db.first()? else {
+                return Ok(None);
+            };
+            if Self::ready_at_of(&key)? > now {
+                return Ok(None);
+            }
+            // only one of concurrent shifts removes the key
+            if let Some(data) = self.db.remove(&key)? {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                return Ok(Some(T::from_external_buffer(&data)?));
+            }
+        }
+    }
+
+    fn micros_of(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64)
+    }
+
+    fn ready_at_of(key: &[u8]) -> Result<SystemTime, Error> {
+        let micros: [u8; 8] = key
+            .get(..8)
+            .and_then(|micros| micros.try_into().ok())
+            .ok_or(Error::InvalidSledKeyFormat)?;
+        Ok(UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(micros)))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ExternalBufferSerde + Send + 'static> ExternalBuffer<T> for ExternalBufferDelayQueue {
+    /// Pushes an item that is ready right away, use `push_at` to delay it
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_at(SystemTime::now(), item)
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_item()
+    }
+
+    fn next_ready_at(&self) -> Option<SystemTime> {
+        ExternalBufferDelayQueue::next_ready_at(self).unwrap_or_default()
+    }
+
+    fn len(&self) -> usize {
+        ExternalBufferDelayQueue::len(self)
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Persistent
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "bincode"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferedStream;
+    use futures::StreamExt;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_shift_waits_for_ready_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferDelayQueue::new(temp_dir.path().join("test_db")).unwrap();
+        let ready_at = SystemTime::now() + Duration::from_millis(100);
+        buffer.push_at(ready_at, 7u32).unwrap();
+
+        assert_eq!(buffer.shift().await.unwrap(), None::<u32>);
+        assert_eq!(buffer.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(buffer.shift().await.unwrap(), Some(7u32));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_wakes_up_when_ready() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferDelayQueue::new(temp_dir.path().join("test_db")).unwrap();
+        buffer
+            .push_at(SystemTime::now() + Duration::from_millis(100), 2u32)
+            .unwrap();
+        buffer.push_at(SystemTime::now(), 1u32).unwrap();
+
+        // the source ends right away, the delayed item is still delivered
+        let stream = ExternalBufferedStream::new(futures::stream::empty::<u32>(), buffer);
+        let items = tokio::time::timeout(Duration::from_secs(5), stream.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::ops::DerefMut;
+
+use crate::Error;
+
+/// A mutex the in-memory buffers keep their items behind, so they don't
+/// depend on `std::sync::Mutex`
+pub trait Lock<V> {
+    type Guard<'a>: DerefMut<Target = V>
+    where
+        Self: 'a;
+
+    fn new(value: V) -> Self;
+
+    /// Fails with `Error::MutexError` if a holder panicked, for locks that
+    /// track it
+    fn lock(&self) -> Result<Self::Guard<'_>, Error>;
+
+    /// Lock even if a holder panicked, for uses that never leave the value
+    /// inconsistent
+    fn lock_anyway(&self) -> Self::Guard<'_>;
+}
+
+/// Picks the `Lock` of a buffer, e.g. `ExternalBufferQueue<T, SpinLock>`
+pub trait LockKind {
+    type Lock<V>: Lock<V>;
+}
+
+/// `std::sync::Mutex`, the default
+pub struct StdLock;
+
+impl LockKind for StdLock {
+    type Lock<V> = std::sync::Mutex<V>;
+}
+
+impl<V> Lock<V> for std::sync::Mutex<V> {
+    type Guard<'a>
+        = std::sync::MutexGuard<'a, V>
+    where
+        V: 'a;
+
+    fn new(value: V) -> Self {
+        std::sync::Mutex::new(value)
+    }
+
+    fn lock(&self) -> Result<Self::Guard<'_>, Error> {
+        Ok(std::sync::Mutex::lock(self)?)
+    }
+
+    fn lock_anyway(&self) -> Self::Guard<'_> {
+        std::sync::Mutex::lock(self).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A spin lock, for targets where blocking on an OS mutex is unwanted. It
+/// never fails, a panicking holder does not poison it.
+#[cfg(feature = "spin-lock")]
+pub struct SpinLock;
+
+#[cfg(feature = "spin-lock")]
+impl LockKind for SpinLock {
+    type Lock<V> = spin::Mutex<V>;
+}
+
+#[cfg(feature = "spin-lock")]
+impl<V> Lock<V> for spin::Mutex<V> {
+    type Guard<'a>
+        = spin::MutexGuard<'a, V>
+    where
+        V: 'a;
+
+    fn new(value: V) -> Self {
+        spin::Mutex::new(value)
+    }
+
+    fn lock(&self) -> Result<Self::Guard<'_>, Error> {
+        Ok(spin::Mutex::lock(self))
+    }
+
+    fn lock_anyway(&self) -> Self::Guard<'_> {
+        spin::Mutex::lock(self)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, KeyedItem};
+
+/// Expose items of type `T` while the inner buffer stores `U`, e.g. keep
+/// compressed bytes on disk but stream structs. `into_stored` is applied on
+/// push and `from_stored` on shift.
+pub struct MapBuffer<B, F, G> {
+    inner: B,
+    into_stored: F,
+    from_stored: G,
+}
+
+impl<B, F, G> MapBuffer<B, F, G> {
+    pub fn new(inner: B, into_stored: F, from_stored: G) -> Self {
+        Self {
+            inner,
+            into_stored,
+            from_stored,
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, U, B, F, G> ExternalBuffer<T> for MapBuffer<B, F, G>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    B: ExternalBuffer<U>,
+    F: Fn(T) -> U + Send + Sync,
+    G: Fn(U) -> T + Send + Sync,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.inner.push((self.into_stored)(item)).await
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        Ok(self.inner.shift().await?.map(&self.from_stored))
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+
+    async fn shift_keyed(&self) -> Result<Option<KeyedItem<T>>, Error> {
+        let item = self.inner.shift_keyed().await?;
+        Ok(item.map(|(key, item)| (key, (self.from_stored)(item))))
+    }
+
+    fn shift_now(&self) -> Option<Result<Option<T>, Error>> {
+        self.inner
+            .shift_now()
+            .map(|result| result.map(|item| item.map(&self.from_stored)))
+    }
+
+    fn shift_now_keyed(&self) -> Option<Result<Option<KeyedItem<T>>, Error>> {
+        self.inner.shift_now_keyed().map(|result| {
+            result.map(|item| item.map(|(key, item)| (key, (self.from_stored)(item))))
+        })
+    }
+
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        self.inner
+            .push_front_now((self.into_stored)(item))
+            .map_err(&self.from_stored)
+    }
+
+    fn next_ready_at(&self) -> Option<std::time::SystemTime> {
+        self.inner.next_ready_at()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        self.inner.space_remaining().await
+    }
+
+    fn durability(&self) -> Durability {
+        self.inner.durability()
+    }
+
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        self.inner.latency_stats()
+    }
+}
+
+#[cfg(all(test, feature = "sled", feature = "bincode"))]
+mod tests {
+    use super::*;
+    use crate::{ExternalBufferSled, ExternalBufferedStream};
+    use futures::StreamExt;
+    use tempfile::TempDir;
+
+    #[derive(Debug, PartialEq)]
+    struct MyStruct {
+        id: u32,
+        name: String,
+    }
+
+    fn to_bytes(item: MyStruct) -> Vec<u8> {
+        let mut bytes = item.id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(item.name.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> MyStruct {
+        let (id, name) = bytes.split_at(4);
+        MyStruct {
+            id: u32::from_be_bytes(id.try_into().unwrap()),
+            name: String::from_utf8(name.to_vec()).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_map_over_sled_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        let sled = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        let buffer = MapBuffer::new(sled, to_bytes, from_bytes);
+
+        let source = futures::stream::iter((0..3).map(|id| MyStruct {
+            id,
+            name: format!("item-{}", id),
+        }));
+        let stream = ExternalBufferedStream::new(source, buffer);
+        let items: Vec<MyStruct> = stream.collect().await;
+
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[2],
+            MyStruct {
+                id: 2,
+                name: "item-2".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::{Error, ExternalBufferSerde};
+
+use super::{Durability, ExternalBuffer};
+
+// prefix of the tree backing each named queue
+const QUEUE_TREE_PREFIX: &str = "__external_buffer_queue_";
+
+/// Several named sled queues drained as one buffer by deficit weighted
+/// round-robin, a queue with weight 3 gives out three items for every one
+/// of a queue with weight 1 while both have items.
+pub struct MultiQueueBuffer {
+    db: sled::Db,
+    queues: Vec<NamedQueue>,
+    len: AtomicUsize,
+    round: Mutex<Round>,
+}
+
+struct NamedQueue {
+    name: String,
+    tree: sled::Tree,
+    weight: u32,
+}
+
+/// Round-robin position and the items each queue may still give out
+struct Round {
+    current: usize,
+    deficits: Vec<u32>,
+}
+
+impl MultiQueueBuffer {
+    /// Open the queues with their weights, a weight of 0 counts as 1.
+    /// Items keep their queue across restarts.
+    pub fn new<'a, P: AsRef<std::path::Path>>(
+        path: P,
+        queues: impl IntoIterator<Item = (&'a str, u32)>,
+    ) -> Result<Self, Error> {
+        let db = super::ExternalBufferSled::open_db(path.as_ref(), sled::Config::new())?;
+        let mut named = Vec::new();
+        let mut len = 0;
+        for (name, weight) in queues {
+            let tree = db.open_tree(format!("{}{}", QUEUE_TREE_PREFIX, name))?;
+            len += tree.len();
+            named.push(NamedQueue {
+                name: name.to_string(),
+                tree,
+                weight: weight.max(1),
+            });
+        }
+
+        let mut deficits = vec![0; named.len()];
+        if let Some(first) = named.first() {
+            deficits[0] = first.weight;
+        }
+        Ok(Self {
+            db,
+            queues: named,
+            len: AtomicUsize::new(len),
+            round: Mutex::new(Round {
+                current: 0,
+                deficits,
+            }),
+        })
+    }
+
+    /// Push an item to the named queue
+    pub fn push_to<T: ExternalBufferSerde>(&self, name: &str, item: T) -> Result<(), Error> {
+        let queue = self
+            .queues
+            .iter()
+            .find(|queue| queue.name == name)
+            .ok_or_else(|| Error::UnknownQueue(name.to_string()))?;
+        self.push_queue(queue, item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push_queue<T: ExternalBufferSerde>(&self, queue: &NamedQueue, item: T) -> Result<(), Error> {
+        let serialized = item.into_external_buffer()?;
+        // ids only grow, also across restarts, so each tree stays FIFO
+        let key = self.db.generate_id()?;
+        queue.tree.insert(key.to_be_bytes(), serialized)?;
+        self.len.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn shift_item<T: ExternalBufferSerde>(&self) -> Result<Option<T>, Error> {
+        let mut round = self.round.lock()?;
+        if self.queues.iter().all(|queue| queue.tree.is_empty()) {
+            return Ok(None);
+        }
+
+        loop {
+            let current = round.current;
+            let queue = &self.queues[current];
+            if round.deficits[current] > 0 {
+                if let Some((_, data)) = queue.tree.pop_min()? {
+                    round.deficits[current] -= 1;
+                    self.len.fetch_sub(1, Ordering::AcqRel);
+                    return Ok(Some(T::from_external_buffer(&data)?));
+                }
+                // an empty queue does not save up its share
+                round.deficits[current] = 0;
+            }
+
+            round.current = (current + 1) % self.queues.len();
+            let next = round.current;
+            round.deficits[next] += self.queues[next].weight;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ExternalBufferSerde + Send + 'static> ExternalBuffer<T> for MultiQueueBuffer {
+    /// Pushes to the first queue, use `push_to` to pick one
+    async fn push(&self, item: T) -> Result<(), Error> {
+        match self.queues.first() {
+            Some(queue) => self.push_queue(queue, item),
+            None => Err(Error::UnknownQueue(String::new())),
+        }
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_item()
+    }
+
+    fn len(&self) -> usize {
+        MultiQueueBuffer::len(self)
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Persistent
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_weighted_draining() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer =
+            MultiQueueBuffer::new(temp_dir.path().join("test_db"), [("high", 3), ("low", 1)])
+                .unwrap();
+        for i in 0..12u32 {
+            buffer.push_to("high", i).unwrap();
+            buffer.push_to("low", 100 + i).unwrap();
+        }
+        assert_eq!(buffer.len(), 24);
+
+        let mut drained: Vec<u32> = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            drained.push(item);
+        }
+        assert_eq!(drained.len(), 24);
+
+        // while both queues have items, high gets three turns for each of low
+        let high = drained[..16].iter().filter(|item| **item < 100).count();
+        assert_eq!(high, 12);
+        assert_eq!(&drained[..4], &[0, 1, 2, 100]);
+        // the rest of low comes out in order once high is empty
+        assert_eq!(&drained[16..], &(104..112).collect::<Vec<_>>()[..]);
+    }
+
+    #[tokio::test]
+    async fn test_push_to_unknown_queue() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = MultiQueueBuffer::new(temp_dir.path().join("test_db"), [("a", 1)]).unwrap();
+        assert!(matches!(
+            buffer.push_to("b", 1u32),
+            Err(Error::UnknownQueue(name)) if name == "b"
+        ));
+    }
+}
@@ -1,34 +1,432 @@
+use std::cmp::Ordering as CmpOrdering;
 use std::collections::BinaryHeap;
-use std::sync::Mutex;
-
-use crate::Error;
-
-use super::ExternalBuffer;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::trace::event;
+use crate::{make_custom_error, Error, ExternalBufferSerde};
+
+use super::lock::{Lock, LockKind, StdLock};
+use super::{Durability, ExternalBuffer, MapBuffer, SyncExternalBuffer};
+
+/// A in memory max binary heap queue as the buffer, behind a
+/// `std::sync::Mutex` unless another `LockKind` is picked, e.g. the
+/// `SpinLock` of the `spin-lock` feature.
+pub struct ExternalBufferQueue<T: Ord, K: LockKind = StdLock> {
+    queue: K::Lock<BinaryHeap<Entry<T>>>,
+    // insertion sequence used to break ties, only present in stable mode
+    seq: Option<AtomicU64>,
+    // explicit priority key ordering items before their `Ord`
+    priority: Option<fn(&T) -> u64>,
+    memory: Option<MemoryLimit<T>>,
+}
 
-/// A in memory max binary heap queue as the buffer
-pub struct ExternalBufferQueue<T: Ord> {
-    queue: Mutex<BinaryHeap<T>>,
+/// Estimated memory of the queued items and the most it may grow to
+struct MemoryLimit<T> {
+    limit: usize,
+    size_of: fn(&T) -> usize,
+    // only updated while holding the queue lock
+    used: AtomicUsize,
 }
 
 impl<T: Ord> ExternalBufferQueue<T> {
     pub fn new() -> Self {
         Self {
             queue: Default::default(),
+            seq: None,
+            priority: None,
+            memory: None,
+        }
+    }
+
+    /// Create a queue with room for `cap` items before the heap reallocates.
+    /// Only a performance hint, the queue still grows past it.
+    pub fn with_preallocated(cap: usize) -> Self {
+        Self {
+            queue: Lock::new(BinaryHeap::with_capacity(cap)),
+            seq: None,
+            priority: None,
+            memory: None,
         }
     }
+
+    /// Create a queue where items of equal priority are shifted in the
+    /// order they were pushed (FIFO within a priority).
+    pub fn new_stable() -> Self {
+        Self {
+            queue: Default::default(),
+            seq: Some(AtomicU64::new(0)),
+            priority: None,
+            memory: None,
+        }
+    }
+
+    /// Create a queue ordered by the `u64` key `priority` computes for each
+    /// item, higher keys first, the item's `Ord` only breaks ties. Keys are
+    /// saved along with the items, so a queue restored by `load` keeps its
+    /// order even if the `Ord` of `T` changed in between.
+    pub fn with_priority_key(priority: fn(&T) -> u64) -> Self {
+        Self {
+            queue: Default::default(),
+            seq: None,
+            priority: Some(priority),
+            memory: None,
+        }
+    }
+
+    /// Create a queue rejecting pushes with `Error::BufferFull` once the
+    /// sizes `size_of` estimates for the queued items would add up to more
+    /// than `bytes`. Unlike an item count this follows how large items are.
+    pub fn with_memory_limit(bytes: usize, size_of: fn(&T) -> usize) -> Self {
+        Self {
+            queue: Default::default(),
+            seq: None,
+            priority: None,
+            memory: Some(MemoryLimit {
+                limit: bytes,
+                size_of,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Estimated memory of the queued items, 0 without `with_memory_limit`
+    pub fn memory_used(&self) -> usize {
+        self.memory
+            .as_ref()
+            .map_or(0, |memory| memory.used.load(Ordering::Relaxed))
+    }
+
+    /// Write every item with its priority key to `path`, the queue itself
+    /// is left untouched
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error>
+    where
+        T: ExternalBufferSerde + Clone,
+    {
+        let queue = self.queue.lock()?;
+        let file = std::fs::File::create(path).map_err(make_custom_error)?;
+        let mut writer = BufWriter::new(file);
+        for entry in queue.iter() {
+            let value = entry.item.clone().into_external_buffer()?;
+            writer
+                .write_all(&entry.key.to_be_bytes())
+                .and_then(|_| writer.write_all(&(value.len() as u64).to_be_bytes()))
+                .and_then(|_| writer.write_all(&value))
+                .map_err(make_custom_error)?;
+        }
+        writer.flush().map_err(make_custom_error)
+    }
+
+    /// Restore a queue written by `save`. The heap is rebuilt from the
+    /// stored keys, `priority` is only used for items pushed from now on.
+    pub fn load<P: AsRef<Path>>(path: P, priority: fn(&T) -> u64) -> Result<Self, Error>
+    where
+        T: ExternalBufferSerde,
+    {
+        let file = std::fs::File::open(path).map_err(make_custom_error)?;
+        let mut reader = BufReader::new(file);
+        let mut entries = Vec::new();
+        let mut header = [0u8; 16];
+        loop {
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(make_custom_error(e)),
+            }
+            let (key, len) = header.split_at(8);
+            let key = u64::from_be_bytes(key.try_into().unwrap());
+            let len = u64::from_be_bytes(len.try_into().unwrap());
+            let mut value = vec![0u8; len as usize];
+            reader.read_exact(&mut value).map_err(make_custom_error)?;
+            let item = T::from_external_buffer(&value)?;
+            entries.push(Entry { item, seq: 0, key });
+        }
+        Ok(Self {
+            queue: Lock::new(BinaryHeap::from(entries)),
+            seq: None,
+            priority: Some(priority),
+            memory: None,
+        })
+    }
 }
 
-#[async_trait::async_trait]
-impl<T: Ord + Send> ExternalBuffer<T> for ExternalBufferQueue<T> {
-    async fn push(&self, item: T) -> Result<(), Error> {
+impl<T: Ord, K: LockKind> ExternalBufferQueue<T, K> {
+    /// Pop every item in priority order, handy for batch post-processing
+    /// after the source has ended.
+    pub fn drain_sorted(&self) -> Vec<T> {
+        // the heap stays consistent even if a holder of the lock panicked
+        let mut queue = self.queue.lock_anyway();
+        let mut items = Vec::with_capacity(queue.len());
+        while let Some(entry) = queue.pop() {
+            items.push(entry.item);
+        }
+        if let Some(memory) = &self.memory {
+            memory.used.store(0, Ordering::Relaxed);
+        }
+        items
+    }
+
+    /// Replace the top item by what `f` makes of it, e.g. with a lower
+    /// priority, in one step under the lock so no shift sees the queue
+    /// without it. `None` if the queue is empty.
+    pub fn replace_top(&self, f: impl FnOnce(T) -> T) -> Result<Option<()>, Error> {
+        let mut queue = self.queue.lock()?;
+        let Some(entry) = queue.pop() else {
+            return Ok(None);
+        };
+        // a replaced item is never rejected, it only changes the estimate
+        let old_size = self
+            .memory
+            .as_ref()
+            .map(|memory| (memory.size_of)(&entry.item));
+        let item = f(entry.item);
+        if let (Some(memory), Some(old_size)) = (&self.memory, old_size) {
+            let used = memory.used.load(Ordering::Relaxed) - old_size;
+            memory
+                .used
+                .store(used + (memory.size_of)(&item), Ordering::Relaxed);
+        }
+        let seq = self.next_seq();
+        let key = self.key_of(&item);
+        queue.push(Entry { item, seq, key });
+        Ok(Some(()))
+    }
+
+    /// Heapify the items in O(n)
+    fn from_items(items: Vec<T>) -> Self {
+        let entries: Vec<_> = items
+            .into_iter()
+            .map(|item| Entry {
+                item,
+                seq: 0,
+                key: 0,
+            })
+            .collect();
+        Self {
+            queue: Lock::new(BinaryHeap::from(entries)),
+            seq: None,
+            priority: None,
+            memory: None,
+        }
+    }
+
+    fn key_of(&self, item: &T) -> u64 {
+        self.priority.map_or(0, |priority| priority(item))
+    }
+
+    fn next_seq(&self) -> u64 {
+        match &self.seq {
+            Some(seq) => seq.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        }
+    }
+}
+
+/// An item stored with the key `ExternalBufferQueue::by_key` extracted
+/// from it, ordered by the key alone
+pub struct KeyOrdered<K, T> {
+    key: K,
+    item: T,
+}
+
+type FromKeyOrdered<K, T> = fn(KeyOrdered<K, T>) -> T;
+
+impl<K: Ord, T> PartialEq for KeyOrdered<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<K: Ord, T> Eq for KeyOrdered<K, T> {}
+
+impl<K: Ord, T> PartialOrd for KeyOrdered<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for KeyOrdered<K, T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl<K: Ord, T> ExternalBufferQueue<KeyOrdered<K, T>> {
+    /// Create a queue of items that are not `Ord` themselves, ordered by
+    /// the key `key_fn` extracts from each instead, higher keys first and
+    /// items with equal keys in push order. The key is stored next to the
+    /// item, so `key_fn` runs once per push.
+    pub fn by_key<F>(
+        key_fn: F,
+    ) -> MapBuffer<Self, impl Fn(T) -> KeyOrdered<K, T> + Send + Sync, FromKeyOrdered<K, T>>
+    where
+        F: Fn(&T) -> K + Send + Sync,
+    {
+        MapBuffer::new(
+            Self::new_stable(),
+            move |item| KeyOrdered {
+                key: key_fn(&item),
+                item,
+            },
+            |stored| stored.item,
+        )
+    }
+}
+
+impl<T: Ord, K: LockKind> Default for ExternalBufferQueue<T, K> {
+    fn default() -> Self {
+        Self::from_items(Vec::new())
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for ExternalBufferQueue<T> {
+    /// Heapify the items in O(n)
+    fn from(items: Vec<T>) -> Self {
+        Self::from_items(items)
+    }
+}
+
+impl<T: Ord, K: LockKind> FromIterator<T> for ExternalBufferQueue<T, K> {
+    /// Heapify the items in O(n)
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from_items(iter.into_iter().collect())
+    }
+}
+
+impl<T: Ord + Send, K: LockKind> Extend<T> for ExternalBufferQueue<T, K>
+where
+    Self: SyncExternalBuffer<T>,
+{
+    /// Push every item, items a memory limit rejects are dropped
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            if let Err(e) = self.push_sync(item) {
+                event!(warn; "Dropped item extending the queue: {}", e);
+            }
+        }
+    }
+}
+
+/// Heap entry keyed by `(key, item, seq)`, a smaller seq wins among equal
+/// items
+struct Entry<T> {
+    item: T,
+    seq: u64,
+    key: u64,
+}
+
+impl<T: Ord> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Entry<T> {}
+
+impl<T: Ord> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| self.item.cmp(&other.item))
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<T, K> SyncExternalBuffer<T> for ExternalBufferQueue<T, K>
+where
+    T: Ord + Send,
+    K: LockKind,
+    Self: Send + Sync,
+{
+    fn push_sync(&self, item: T) -> Result<(), Error> {
         let mut queue = self.queue.lock()?;
-        queue.push(item);
+        let seq = self.next_seq();
+        let key = self.key_of(&item);
+        if let Some(memory) = &self.memory {
+            let size = (memory.size_of)(&item);
+            let used = memory.used.load(Ordering::Relaxed);
+            if used + size > memory.limit {
+                return Err(Error::BufferFull);
+            }
+            memory.used.store(used + size, Ordering::Relaxed);
+        }
+        queue.push(Entry { item, seq, key });
         Ok(())
     }
 
+    fn shift_sync(&self) -> Result<Option<T>, Error> {
+        let mut queue = self.queue.lock()?;
+        let item = queue.pop().map(|entry| entry.item);
+        if let (Some(memory), Some(item)) = (&self.memory, &item) {
+            memory
+                .used
+                .fetch_sub((memory.size_of)(item), Ordering::Relaxed);
+        }
+        Ok(item)
+    }
+
+    fn len_sync(&self) -> usize {
+        self.queue.lock_anyway().len()
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, K> ExternalBuffer<T> for ExternalBufferQueue<T, K>
+where
+    T: Ord + Send,
+    K: LockKind,
+    Self: Send + Sync,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_sync(item)
+    }
+
     async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_sync()
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        Some(self)
+    }
+
+    /// The item's priority decides its place, a push puts it back there
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        Ok(self.push_sync(item))
+    }
+
+    fn len(&self) -> usize {
+        self.len_sync()
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Volatile
+    }
+
+    async fn remove_if<F>(&self, pred: F) -> Result<usize, Error>
+    where
+        F: Fn(&T) -> bool + Send + 'async_trait,
+    {
         let mut queue = self.queue.lock()?;
-        Ok(queue.pop())
+        let before = queue.len();
+        let mut freed = 0;
+        queue.retain(|entry| {
+            let matched = pred(&entry.item);
+            if let (true, Some(memory)) = (matched, &self.memory) {
+                freed += (memory.size_of)(&entry.item);
+            }
+            !matched
+        });
+        if let Some(memory) = &self.memory {
+            memory.used.fetch_sub(freed, Ordering::Relaxed);
+        }
+        Ok(before - queue.len())
     }
 }
 
@@ -233,4 +631,234 @@ mod tests {
         assert!(buffer.push(2).await.is_err());
         assert!(buffer.shift().await.is_err());
     }
+
+    #[tokio::test]
+    async fn test_stable_queue_keeps_insertion_order() {
+        #[derive(Debug, PartialEq, Eq)]
+        struct Task {
+            priority: i32,
+            id: u32,
+        }
+
+        impl PartialOrd for Task {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        // Only the priority takes part in ordering
+        impl Ord for Task {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.priority.cmp(&other.priority)
+            }
+        }
+
+        let buffer = ExternalBufferQueue::new_stable();
+        for id in 0..5 {
+            buffer.push(Task { priority: 5, id }).await.unwrap();
+        }
+
+        for id in 0..5 {
+            let task = buffer.shift().await.unwrap().unwrap();
+            assert_eq!(task.id, id);
+        }
+        assert!(buffer.shift().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drain_sorted() {
+        let buffer = ExternalBufferQueue::new();
+        for num in [3, 1, 4, 1, 5, 9, 2, 6] {
+            buffer.push(num).await.unwrap();
+        }
+
+        assert_eq!(buffer.drain_sorted(), vec![9, 6, 5, 4, 3, 2, 1, 1]);
+        assert!(buffer.shift().await.unwrap().is_none());
+        assert!(buffer.drain_sorted().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_with_preallocated() {
+        let buffer = ExternalBufferQueue::with_preallocated(128);
+        assert!(buffer.queue.lock().unwrap().capacity() >= 128);
+
+        buffer.push(1).await.unwrap();
+        buffer.push(3).await.unwrap();
+        assert_eq!(buffer.shift().await.unwrap(), Some(3));
+        assert_eq!(buffer.shift().await.unwrap(), Some(1));
+        assert!(buffer.shift().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_vec() {
+        let buffer = ExternalBufferQueue::from(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+        assert_eq!(buffer.len(), 8);
+        assert_eq!(buffer.drain_sorted(), vec![9, 6, 5, 4, 3, 2, 1, 1]);
+
+        let buffer = ExternalBufferQueue::<i32>::default();
+        assert!(buffer.shift().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_follows_persisted_keys() {
+        // the same data before and after its `Ord` got reversed
+        #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Before(u32);
+
+        #[derive(Debug, PartialEq, Eq)]
+        struct After(u32);
+
+        impl PartialOrd for After {
+            fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for After {
+            fn cmp(&self, other: &Self) -> CmpOrdering {
+                other.0.cmp(&self.0)
+            }
+        }
+
+        impl ExternalBufferSerde for Before {
+            fn into_external_buffer(self) -> Result<Vec<u8>, Error> {
+                Ok(self.0.to_be_bytes().to_vec())
+            }
+
+            fn from_external_buffer(_: &[u8]) -> Result<Self, Error> {
+                unreachable!()
+            }
+        }
+
+        impl ExternalBufferSerde for After {
+            fn into_external_buffer(self) -> Result<Vec<u8>, Error> {
+                unreachable!()
+            }
+
+            fn from_external_buffer(value: &[u8]) -> Result<Self, Error> {
+                Ok(After(u32::from_be_bytes(value.try_into().unwrap())))
+            }
+        }
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("queue");
+
+        let buffer = ExternalBufferQueue::with_priority_key(|item: &Before| item.0 as u64);
+        for num in [3, 1, 4, 5, 2] {
+            buffer.push(Before(num)).await.unwrap();
+        }
+        buffer.save(&path).unwrap();
+
+        let restored = ExternalBufferQueue::load(&path, |item: &After| item.0 as u64).unwrap();
+        let items: Vec<u32> = restored.drain_sorted().into_iter().map(|a| a.0).collect();
+        assert_eq!(items, vec![5, 4, 3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit() {
+        let buffer = ExternalBufferQueue::with_memory_limit(250, |_: &i32| 100);
+        buffer.push(1).await.unwrap();
+        buffer.push(2).await.unwrap();
+        assert!(matches!(buffer.push(3).await, Err(Error::BufferFull)));
+        assert_eq!(buffer.memory_used(), 200);
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(2));
+        assert_eq!(buffer.memory_used(), 100);
+        buffer.push(3).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_if() {
+        let buffer = ExternalBufferQueue::with_memory_limit(100, |_: &i32| 10);
+        for i in 1..=6 {
+            buffer.push(i).await.unwrap();
+        }
+
+        assert_eq!(
+            buffer.remove_if(|item: &i32| item % 2 == 0).await.unwrap(),
+            3
+        );
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.memory_used(), 30);
+        assert_eq!(buffer.drain_sorted(), vec![5, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_and_extend() {
+        let mut buffer: ExternalBufferQueue<i32> = (1..=5).collect();
+        assert_eq!(buffer.len(), 5);
+        buffer.extend([7, 0]);
+
+        let mut result = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            result.push(item);
+        }
+        assert_eq!(result, vec![7, 5, 4, 3, 2, 1, 0]);
+    }
+
+    #[cfg(feature = "spin-lock")]
+    #[tokio::test]
+    async fn test_spin_lock_queue() {
+        use crate::SpinLock;
+
+        let buffer: ExternalBufferQueue<i32, SpinLock> = (1..=3).collect();
+        buffer.push(5).await.unwrap();
+        buffer.push(0).await.unwrap();
+        assert_eq!(buffer.len(), 5);
+
+        let mut result = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            result.push(item);
+        }
+        assert_eq!(result, vec![5, 3, 2, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_by_key_orders_by_extracted_key() {
+        // not Ord, and the other fields would order differently
+        struct Job {
+            name: &'static str,
+            priority: u32,
+        }
+
+        let buffer = ExternalBufferQueue::by_key(|job: &Job| job.priority);
+        for (name, priority) in [("a", 1), ("b", 7), ("c", 3), ("d", 7), ("e", 5)] {
+            buffer.push(Job { name, priority }).await.unwrap();
+        }
+
+        let mut result = Vec::new();
+        while let Some(job) = buffer.shift().await.unwrap() {
+            result.push((job.priority, job.name));
+        }
+        assert_eq!(
+            result,
+            vec![(7, "b"), (7, "d"), (5, "e"), (3, "c"), (1, "a")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replace_top_reprioritizes() {
+        let buffer = ExternalBufferQueue::new();
+        assert_eq!(buffer.replace_top(|item: TestItem| item).unwrap(), None);
+        buffer.push(TestItem::new(5, 1, "urgent")).await.unwrap();
+        buffer.push(TestItem::new(3, 2, "normal")).await.unwrap();
+
+        let replaced = buffer.replace_top(|mut item| {
+            item.priority = 1;
+            item
+        });
+        assert_eq!(replaced.unwrap(), Some(()));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.shift().await.unwrap().unwrap().name, "normal");
+        assert_eq!(
+            buffer.shift().await.unwrap().unwrap(),
+            TestItem::new(1, 1, "urgent")
+        );
+    }
+
+    #[test]
+    fn test_durability() {
+        let buffer = ExternalBufferQueue::<i32>::new();
+        assert_eq!(buffer.durability(), Durability::Volatile);
+    }
 }
@@ -0,0 +1,162 @@
+use std::sync::{Arc, Mutex};
+
+use crate::Error;
+
+use super::{Durability, ExternalBuffer};
+
+/// A call `RecordingBuffer` made on its inner buffer, items are recorded
+/// in their `Debug` form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferCall {
+    Push(String),
+    /// The shifted item, `None` if the buffer was empty
+    Shift(Option<String>),
+    /// The returned length
+    Len(usize),
+    Flush,
+}
+
+/// The calls recorded by a `RecordingBuffer`, still readable once the
+/// buffer is moved into a stream
+#[derive(Debug, Clone, Default)]
+pub struct CallLog(Arc<Mutex<Vec<BufferCall>>>);
+
+impl CallLog {
+    /// Every call recorded so far, in order
+    pub fn calls(&self) -> Vec<BufferCall> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Number of shifts recorded so far, also the ones finding no item
+    pub fn shifts(&self) -> usize {
+        self.count(|call| matches!(call, BufferCall::Shift(_)))
+    }
+
+    /// Number of pushes recorded so far
+    pub fn pushes(&self) -> usize {
+        self.count(|call| matches!(call, BufferCall::Push(_)))
+    }
+
+    fn count(&self, matches: impl Fn(&BufferCall) -> bool) -> usize {
+        let calls = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        calls.iter().filter(|call| matches(call)).count()
+    }
+
+    fn record(&self, call: BufferCall) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(call);
+    }
+}
+
+/// Test harness recording every `push`, `shift`, `len` and `flush` call
+/// the stream makes on the inner buffer, e.g. to assert that it drains
+/// the buffer once the source ended. Calls all go through the async side,
+/// the inner buffer's synchronous side is never used.
+pub struct RecordingBuffer<B> {
+    inner: B,
+    log: CallLog,
+}
+
+impl<B> RecordingBuffer<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            log: CallLog::default(),
+        }
+    }
+
+    /// Handle to the recorded calls, take it before handing the buffer on
+    pub fn log(&self) -> CallLog {
+        self.log.clone()
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, B> ExternalBuffer<T> for RecordingBuffer<B>
+where
+    T: std::fmt::Debug + Send + 'static,
+    B: ExternalBuffer<T>,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.log.record(BufferCall::Push(format!("{:?}", item)));
+        self.inner.push(item).await
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        let item = self.inner.shift().await?;
+        let shifted = item.as_ref().map(|item| format!("{:?}", item));
+        self.log.record(BufferCall::Shift(shifted));
+        Ok(item)
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.log.record(BufferCall::Flush);
+        self.inner.flush().await
+    }
+
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        self.inner.push_front_now(item)
+    }
+
+    fn next_ready_at(&self) -> Option<std::time::SystemTime> {
+        self.inner.next_ready_at()
+    }
+
+    fn len(&self) -> usize {
+        let len = self.inner.len();
+        self.log.record(BufferCall::Len(len));
+        len
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        self.inner.space_remaining().await
+    }
+
+    fn durability(&self) -> Durability {
+        self.inner.durability()
+    }
+
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        self.inner.latency_stats()
+    }
+}
+
+#[cfg(all(test, feature = "queue"))]
+mod tests {
+    use super::*;
+    use crate::{ExternalBufferQueue, ExternalBufferedStream};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_stream_shifts_once_per_item_and_drains() {
+        let buffer = RecordingBuffer::new(ExternalBufferQueue::new());
+        let log = buffer.log();
+        let stream = ExternalBufferedStream::new(futures::stream::iter(vec![3, 1, 2]), buffer);
+
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items.len(), 3);
+        assert_eq!(log.pushes(), 3);
+
+        let calls = log.calls();
+        let shifted = calls
+            .iter()
+            .filter(|call| matches!(call, BufferCall::Shift(Some(_))))
+            .count();
+        assert_eq!(shifted, 3);
+        // the stream only ends after a shift found the buffer empty
+        let last_shift = calls
+            .iter()
+            .rev()
+            .find(|call| matches!(call, BufferCall::Shift(_)));
+        assert_eq!(last_shift, Some(&BufferCall::Shift(None)));
+        assert!(log.shifts() > shifted);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, PushOutcome, SyncExternalBuffer};
+
+/// A fixed size in memory FIFO ring, a push on a full ring overwrites the
+/// oldest item in O(1). For telemetry like streams where only the most
+/// recent samples matter.
+pub struct ExternalBufferRing<T> {
+    ring: Mutex<VecDeque<T>>,
+    capacity: usize,
+}
+
+impl<T> ExternalBufferRing<T> {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            ring: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+}
+
+impl<T> ExternalBufferRing<T> {
+    /// Push returning the item overwritten to make room, if any
+    fn push_evicting(&self, item: T) -> Result<Option<T>, Error> {
+        let mut ring = self.ring.lock()?;
+        let evicted = match ring.len() == self.capacity {
+            true => ring.pop_front(),
+            false => None,
+        };
+        ring.push_back(item);
+        Ok(evicted)
+    }
+}
+
+impl<T: Send> SyncExternalBuffer<T> for ExternalBufferRing<T> {
+    fn push_sync(&self, item: T) -> Result<(), Error> {
+        self.push_evicting(item).map(|_| ())
+    }
+
+    fn shift_sync(&self) -> Result<Option<T>, Error> {
+        let mut ring = self.ring.lock()?;
+        Ok(ring.pop_front())
+    }
+
+    fn len_sync(&self) -> usize {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Send> ExternalBuffer<T> for ExternalBufferRing<T> {
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_sync(item)
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_sync()
+    }
+
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        Ok(match self.push_evicting(item)? {
+            Some(oldest) => PushOutcome::EvictedOldest(vec![oldest]),
+            None => PushOutcome::Stored,
+        })
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        Some(self)
+    }
+
+    fn len(&self) -> usize {
+        self.len_sync()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.capacity)
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Volatile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_overwrites_oldest() {
+        let buffer = ExternalBufferRing::new(3);
+        for i in [1, 2, 3, 4, 5] {
+            buffer.push(i).await.unwrap();
+        }
+        assert_eq!(buffer.len(), 3);
+
+        let mut result = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            result.push(item);
+        }
+        assert_eq!(result, vec![3, 4, 5]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, KeyedItem, PushOutcome};
+
+/// Shed load of an inner buffer for lossy data such as telemetry: once it
+/// holds `high_water` items only one in every `keep_one_in` pushed items is
+/// kept, until it is drained down to `low_water` again.
+pub struct SamplingBuffer<B> {
+    inner: B,
+    high_water: usize,
+    low_water: usize,
+    keep_one_in: usize,
+    shedding: AtomicBool,
+    // pushes seen while shedding, picks the ones to keep
+    sampled: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl<B> SamplingBuffer<B> {
+    /// Keep every other item while shedding, see `keep_one_in`
+    pub fn new(inner: B, high_water: usize, low_water: usize) -> Self {
+        Self {
+            inner,
+            high_water,
+            low_water,
+            keep_one_in: 2,
+            shedding: AtomicBool::new(false),
+            sampled: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Keep one in `n` pushed items while shedding, 1 keeps all of them
+    pub fn keep_one_in(mut self, n: usize) -> Self {
+        self.keep_one_in = n.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Number of items dropped by sampling so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether the pushed item is dropped, counts it if so
+    fn sample_out<T>(&self) -> bool
+    where
+        B: ExternalBuffer<T>,
+    {
+        let len = self.inner.len();
+        if len >= self.high_water {
+            self.shedding.store(true, Ordering::Relaxed);
+        } else if len <= self.low_water {
+            self.shedding.store(false, Ordering::Relaxed);
+        }
+
+        if self.shedding.load(Ordering::Relaxed)
+            && !self
+                .sampled
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.keep_one_in)
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, B> ExternalBuffer<T> for SamplingBuffer<B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T>,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        if self.sample_out() {
+            return Ok(());
+        }
+        self.inner.push(item).await
+    }
+
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        if self.sample_out() {
+            return Ok(PushOutcome::DroppedNewest);
+        }
+        self.inner.push_outcome(item).await
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.inner.shift().await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+
+    async fn shift_keyed(&self) -> Result<Option<KeyedItem<T>>, Error> {
+        self.inner.shift_keyed().await
+    }
+
+    fn shift_now(&self) -> Option<Result<Option<T>, Error>> {
+        self.inner.shift_now()
+    }
+
+    fn shift_now_keyed(&self) -> Option<Result<Option<KeyedItem<T>>, Error>> {
+        self.inner.shift_now_keyed()
+    }
+
+    /// Never sampled out, the item was kept when it was pushed
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        self.inner.push_front_now(item)
+    }
+
+    fn next_ready_at(&self) -> Option<std::time::SystemTime> {
+        self.inner.next_ready_at()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        self.inner.space_remaining().await
+    }
+
+    fn durability(&self) -> Durability {
+        self.inner.durability()
+    }
+
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        self.inner.latency_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use crate::BoundedBuffer;
+
+    #[tokio::test]
+    async fn test_sheds_half_over_high_water() {
+        let buffer = SamplingBuffer::new(MemoryBuffer::default(), 10, 5);
+        for i in 0..100 {
+            buffer.push(i).await.unwrap();
+        }
+        // the first 10 fill it up, then every other of the 90 is kept
+        assert_eq!(buffer.len(), 55);
+        assert_eq!(buffer.dropped(), 45);
+
+        // drained below the low water mark it keeps everything again
+        while buffer.len() > 5 {
+            buffer.shift().await.unwrap();
+        }
+        buffer.push(100).await.unwrap();
+        buffer.push(101).await.unwrap();
+        assert_eq!(buffer.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_forwards_space_remaining() {
+        let bounded = BoundedBuffer::new(BoundedBuffer::new(MemoryBuffer::default(), 2), 5);
+        let buffer = SamplingBuffer::new(bounded, 10, 5);
+        buffer.push(1).await.unwrap();
+        // the inner buffer's own limit, not what `capacity` leaves
+        assert_eq!(buffer.capacity(), Some(5));
+        assert_eq!(buffer.space_remaining().await.unwrap(), Some(1));
+    }
+}
@@ -1,243 +1,3346 @@
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
 
-use crate::{Error, ExternalBufferSerde};
+use crate::trace::event;
+use crate::{make_custom_error, BufferPool, Error, ExternalBufferSerde};
 
-use super::ExternalBuffer;
+use super::{
+    CollisionPolicy, DecodeErrorPolicy, Durability, ExternalBuffer, KeyedItem, OverflowPolicy,
+    PreparedPush, PushOutcome, SyncExternalBuffer,
+};
 
-/// Sled as the persistent buffer with FIFO queue order
+mod batched;
+#[cfg(feature = "large-values")]
+mod chunked;
+mod dedup;
+
+// tree that records the head/tail counters next to the data keys
+const META_TREE: &[u8] = b"__external_buffer_meta";
+const META_HEAD: &[u8] = b"head";
+const META_TAIL: &[u8] = b"tail";
+// total size of the buffered values, only kept with `with_max_bytes`
+const META_BYTES: &[u8] = b"bytes";
+// tree for items pushed into a partition, keyed by `partition || seq`
+const PARTITION_TREE: &[u8] = b"__external_buffer_partitions";
+// items pushed under a key already in use with `CollisionPolicy::Suffix`,
+// keyed by `key || seq`
+const TIE_TREE: &[u8] = b"__external_buffer_ties";
+// least distance `push_front` moves the items by once the head is at key 0
+const REBASE_OFFSET: u64 = 1 << 32;
+// tree of recently seen dedup keys, only with `with_dedup`
+const DEDUP_TREE: &[u8] = b"__external_buffer_dedup";
+
+/// Sled as the persistent buffer with FIFO queue order, or LIFO when opened
+/// with `new_lifo`.
+///
+/// A path can only be opened by one instance at a time, also within a
+/// single process, opening it again fails with `Error::DatabaseLocked`.
 pub struct ExternalBufferSled {
     db: sled::Db,
+    meta: sled::Tree,
+    // The counters only hand out keys, sled synchronizes the data itself.
+    // Loads are Acquire and updates AcqRel so a thread that sees a counter
+    // move also sees the db operations done before it moved. Nothing needs
+    // a single total order across both counters, so SeqCst is not used.
     head_counter: AtomicU64,
     tail_counter: AtomicU64,
+    // number of items, keys between head and tail may be missing when
+    // pushed with `push_with_key`
+    item_count: AtomicU64,
+    partitions: sled::Tree,
+    ties: sled::Tree,
+    collision_policy: CollisionPolicy,
+    // head and tail seq of each partition
+    partition_counters: Mutex<HashMap<u32, (u32, u32)>>,
+    #[cfg(feature = "large-values")]
+    chunks: Option<chunked::Chunks>,
+    // shift the newest item instead of the oldest
+    lifo: bool,
+    // remove shifted items, otherwise only the head moves past them
+    consume: bool,
+    max_bytes: Option<u64>,
+    overflow_policy: OverflowPolicy,
+    total_bytes: AtomicU64,
+    decode_error_policy: DecodeErrorPolicy,
+    skipped_decodes: AtomicU64,
+    // shifts that found their head key already gone, only for debug logs
+    shift_races: AtomicU64,
+    // held shared by appends from taking a key until it is stored, a shift
+    // finding its key missing takes it exclusively to wait for them
+    appending: RwLock<()>,
+    // last key read at the head by a non consuming `shift`, the main key
+    // or one of its ties, so the ties after it come next. Held across the
+    // read, non consuming shifts run one at a time.
+    read_tie: Mutex<Option<Vec<u8>>>,
+    write_batch: Option<std::sync::Arc<batched::WriteBatch>>,
+    pool: Option<BufferPool>,
+    // largest serialized item a push accepts
+    max_value_bytes: Option<usize>,
+    dedup: Option<dedup::Dedup>,
+    #[cfg(feature = "stats")]
+    latency: Option<crate::stats::LatencyRecorder>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::EncryptionKey>,
+}
+
+/// How `ExternalBufferSled::open_with_mode` treats rows it can not read
+/// while scanning for the head and tail keys. Keys that are not 8 bytes
+/// long are not items and are passed over in either mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Fail the open on the first unreadable row, what `new` does
+    Strict,
+    /// Log and skip unreadable rows, e.g. to salvage a partially corrupt db
+    Lenient,
+}
+
+/// Where `ExternalBufferSled::merge_from` puts the merged items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// Push them after the buffered items, in the order of the other buffer
+    Append,
+    /// Keep the keys they had in the other buffer, so both buffers' items
+    /// are shifted interleaved in key order, e.g. of timestamp keys
+    ByKey,
+}
+
+/// What `ExternalBufferSled::open_and_repair` found and fixed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Head counter recorded in meta before repair
+    pub meta_head: Option<u64>,
+    /// Tail counter recorded in meta before repair
+    pub meta_tail: Option<u64>,
+    /// Head counter derived from the data keys
+    pub head: u64,
+    /// Tail counter derived from the data keys
+    pub tail: u64,
+    /// Whether meta had to be rewritten
+    pub repaired: bool,
 }
 
 impl ExternalBufferSled {
     pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
-        let db = sled::open(path)?;
+        Self::from_db(Self::open_db(path.as_ref(), sled::Config::new())?)
+    }
 
-        // Initialize counters by scanning existing keys
-        let (head, tail) = Self::initialize_counters(&db)?;
+    /// Same as `new`, but the open and the key scan run on a blocking
+    /// thread so an async caller does not stall its executor.
+    pub async fn open_async<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        crate::runtime::spawn_blocking(move || Self::new(path)).await
+    }
 
-        Ok(Self {
-            db,
-            head_counter: AtomicU64::new(head),
-            tail_counter: AtomicU64::new(tail),
-        })
+    /// Open a buffer whose values are compressed by sled with zstd at the
+    /// given `compression_factor` (1 to 22).
+    ///
+    /// Compression changes the on-disk format: a db created by this method
+    /// can not be read by a non-compressed instance and vice versa.
+    #[cfg(feature = "sled-compression")]
+    pub fn new_compressed<P: AsRef<std::path::Path>>(
+        path: P,
+        compression_factor: i32,
+    ) -> Result<Self, Error> {
+        let config = sled::Config::new()
+            .use_compression(true)
+            .compression_factor(compression_factor);
+        let db = Self::open_db(path.as_ref(), config)?;
+        Self::from_db(db)
     }
 
-    fn initialize_counters(db: &sled::Db) -> Result<(u64, u64), Error> {
-        let mut min_key = u64::MAX;
-        let mut max_key = 0u64;
-        let mut has_keys = false;
+    /// Open a buffer that shifts the newest item first, e.g. to process the
+    /// most recent backlog first while recovering.
+    ///
+    /// The storage format is the same as `new`, so a db can be drained in
+    /// either order. Shifting in this mode is meant for a single consumer
+    /// while pushes are quiet, as it takes items from the end pushes write to.
+    pub fn new_lifo<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let mut buffer = Self::new(path)?;
+        buffer.lifo = true;
+        Ok(buffer)
+    }
 
-        for result in db.iter() {
-            let (key, _) = result?;
-            if key.len() == 8 {
-                let key_u64 = u64::from_be_bytes(
-                    key.as_ref()
-                        .try_into()
-                        .map_err(|_| Error::InvalidSledKeyFormat)?,
-                );
-                min_key = min_key.min(key_u64);
-                max_key = max_key.max(key_u64);
-                has_keys = true;
-            }
-        }
+    /// Open a buffer holding at most `max_bytes` of serialized items, pushes
+    /// past it are rejected unless another `overflow_policy` is set.
+    ///
+    /// Items are counted by the size of the value stored under their key,
+    /// e.g. 8 bytes more than the serialized item with latency stats, so
+    /// shifts release exactly what pushes reserved. The running total is
+    /// kept in meta so it survives restarts. Items pushed into partitions
+    /// are not counted, and concurrent pushes may overshoot the limit by
+    /// the items in flight.
+    pub fn with_max_bytes<P: AsRef<std::path::Path>>(
+        path: P,
+        max_bytes: u64,
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::new(path)?;
+        let total = match Self::load_meta(&buffer.meta, META_BYTES)? {
+            Some(total) => total,
+            // first opened with a limit, count what is already buffered
+            None => buffer
+                .db
+                .iter()
+                .values()
+                .chain(buffer.ties.iter().values())
+                .try_fold(0, |total, value| {
+                    value.map(|value| total + value.len() as u64)
+                })?,
+        };
+        buffer.total_bytes = AtomicU64::new(total);
+        buffer.max_bytes = Some(max_bytes);
+        Ok(buffer)
+    }
 
-        if has_keys {
-            Ok((min_key, max_key + 1))
-        } else {
-            Ok((0, 0))
-        }
+    /// What `push_with_key` does with a key already in use, `Reject` by
+    /// default. Tied items are shifted right after the item they are tied
+    /// to, newest first in a LIFO buffer.
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
     }
 
-    fn key_from_u64(value: u64) -> [u8; 8] {
-        value.to_be_bytes()
+    /// How pushes beyond `with_max_bytes` are handled, `Reject` by default
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
     }
-}
 
-#[async_trait::async_trait]
-impl<T: ExternalBufferSerde + Send + 'static> ExternalBuffer<T> for ExternalBufferSled {
-    async fn push(&self, item: T) -> Result<(), Error> {
-        let serialized = item.into_external_buffer()?;
-        let key = self.tail_counter.fetch_add(1, Ordering::SeqCst);
-        let key_bytes = Self::key_from_u64(key);
+    /// What `shift` does with an item that fails to decode, `Abort` by
+    /// default
+    pub fn decode_error_policy(mut self, policy: DecodeErrorPolicy) -> Self {
+        self.decode_error_policy = policy;
+        self
+    }
 
-        self.db.insert(&key_bytes, serialized)?;
-        Ok(())
+    /// With `false`, `shift` moves past items without removing them, so
+    /// `reset_to_head` can replay everything from the start, e.g. to
+    /// reprocess after a bug in the consumer. Nothing is ever deleted in
+    /// this mode, the db keeps growing until the items are removed by
+    /// other means. The read position is not persisted, a reopened buffer
+    /// starts from the oldest item. Has no effect on LIFO buffers.
+    pub fn consume(mut self, consume: bool) -> Self {
+        self.consume = consume;
+        self
     }
 
-    async fn shift(&self) -> Result<Option<T>, Error> {
-        loop {
-            let current_head = self.head_counter.load(Ordering::SeqCst);
-            let current_tail = self.tail_counter.load(Ordering::SeqCst);
+    /// Rewind the head to the oldest stored item, so everything read by a
+    /// non consuming `shift` is delivered again
+    pub fn reset_to_head(&self) -> Result<(), Error> {
+        let mut read_tie = self.read_tie.lock().unwrap_or_else(|e| e.into_inner());
+        let head = match self.next_stored_key_from(0)? {
+            Some(head) => head,
+            None => self.tail_counter.load(Ordering::Acquire),
+        };
+        // Release, a shift that loads the rewound head finds the items
+        // this scan found stored
+        self.head_counter.store(head, Ordering::Release);
+        *read_tie = None;
+        let count = self.db.len() + self.ties.len();
+        self.item_count.store(count as u64, Ordering::Release);
+        self.store_head()
+    }
 
-            // Check if buffer is empty
-            if current_head >= current_tail {
-                return Ok(None);
-            }
+    /// Key of the next item a FIFO `shift` returns, the tail once empty
+    pub fn head(&self) -> u64 {
+        // Acquire, the items below the head seen here are removed already
+        self.head_counter.load(Ordering::Acquire)
+    }
 
-            let key_bytes = Self::key_from_u64(current_head);
+    /// Key the next `push` is stored under
+    pub fn tail(&self) -> u64 {
+        // Acquire, though a push takes its key before storing the item, so
+        // the key below the tail may not be stored yet
+        self.tail_counter.load(Ordering::Acquire)
+    }
 
-            // Try to remove the item atomically
-            match self.db.remove(&key_bytes)? {
-                Some(data) => {
-                    // Successfully removed, update head counter
-                    self.head_counter.fetch_add(1, Ordering::SeqCst);
+    /// Resume strictly after `key`, e.g. the last key a consumer
+    /// checkpointed outside the buffer before a restart. In the default
+    /// consuming mode the items up to `key` are removed, with
+    /// `consume(false)` they are kept and `reset_to_head` still replays
+    /// them. Seeking past the tail also moves the tail, so later pushes
+    /// are still delivered after the checkpoint.
+    pub fn seek_to(&self, key: u64) -> Result<(), Error> {
+        self.apply_batched_writes()?;
+        let mut read_tie = self.read_tie.lock().unwrap_or_else(|e| e.into_inner());
+        *read_tie = None;
+        let target = key.saturating_add(1);
+        let end = Self::key_from_u64(target);
+        // a read-modify-write, a push racing with the seek keeps its key
+        if self.tail_counter.fetch_max(target, Ordering::AcqRel) < target {
+            self.meta.insert(META_TAIL, &target.to_be_bytes())?;
+        }
 
-                    // Deserialize and return the item
-                    let item = T::from_external_buffer(&data)?;
-                    return Ok(Some(item));
+        if self.consume {
+            for entry in self.db.range(..end) {
+                let (key_bytes, _) = entry?;
+                if let Some(value) = self.db.remove(&key_bytes)? {
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    self.release_bytes(value.len())?;
+                    #[cfg(feature = "large-values")]
+                    if let Some(chunks) = &self.chunks {
+                        chunks.discard(&value)?;
+                    }
                 }
-                None => {
-                    // Item was already removed by another thread, try next
-                    self.head_counter.fetch_add(1, Ordering::SeqCst);
-                    continue;
+            }
+            for entry in self.ties.range(..end) {
+                let (tie_key, _) = entry?;
+                if let Some(value) = self.ties.remove(tie_key)? {
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    self.release_bytes(value.len())?;
+                    #[cfg(feature = "large-values")]
+                    if let Some(chunks) = &self.chunks {
+                        chunks.discard(&value)?;
+                    }
                 }
             }
+        } else {
+            let remaining = self.db.range(end..).count() + self.ties.range(end..).count();
+            self.item_count.store(remaining as u64, Ordering::Release);
         }
+
+        // Release publishes the removals above to shifts loading the head
+        self.head_counter.store(target, Ordering::Release);
+        self.store_head()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bincode::{Decode, Encode};
-    use tempfile::TempDir;
+    /// Collect pushes into one sled batch applied once `max_items` are
+    /// waiting or every `interval`, instead of one insert per push. Much
+    /// faster for bursty ingest, but pushes not applied yet are lost if the
+    /// process dies, `flush` applies them right away. Shifts apply them
+    /// first, so items are always shiftable right after their push.
+    pub fn with_write_batching(mut self, max_items: usize, interval: std::time::Duration) -> Self {
+        self.write_batch = Some(batched::WriteBatch::start(
+            self.db.clone(),
+            self.meta.clone(),
+            max_items,
+            interval,
+        ));
+        self
+    }
 
-    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
-    struct TestItem {
-        id: u32,
-        name: String,
+    /// Serialize pushed items into buffers recycled through a pool keeping
+    /// up to `max_buffers` of them, instead of allocating one per push
+    pub fn with_buffer_pool(mut self, max_buffers: usize) -> Self {
+        self.pool = Some(BufferPool::new(max_buffers));
+        self
     }
 
-    #[tokio::test]
-    async fn test_push_and_shift() {
-        let temp_dir = TempDir::new().unwrap();
-        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+    /// Reject pushes of items serializing to more than `limit` bytes with
+    /// `Error::ItemTooLarge`, before anything is written or counted
+    pub fn max_value_bytes(mut self, limit: usize) -> Self {
+        self.max_value_bytes = Some(limit);
+        self
+    }
 
-        let item1 = TestItem {
-            id: 1,
-            name: "first".to_string(),
+    /// Remember the keys given to `push_deduped` for `window`, also across
+    /// restarts, so a source replaying items does not buffer them twice
+    pub fn with_dedup(mut self, window: std::time::Duration) -> Result<Self, Error> {
+        self.dedup = Some(dedup::Dedup::open(&self.db, DEDUP_TREE, window)?);
+        Ok(self)
+    }
+
+    /// Push an item unless an item with the same `dedup_key` was pushed
+    /// within the `with_dedup` window, returns whether it was stored.
+    /// Without `with_dedup` every item is stored.
+    pub fn push_deduped<T: ExternalBufferSerde + Send + 'static>(
+        &self,
+        dedup_key: &[u8],
+        item: T,
+    ) -> Result<bool, Error> {
+        let Some(dedup) = &self.dedup else {
+            self.push_sync(item)?;
+            return Ok(true);
         };
-        let item2 = TestItem {
-            id: 2,
-            name: "second".to_string(),
+        if !dedup.mark(dedup_key)? {
+            return Ok(false);
+        }
+        if let Err(e) = self.push_sync(item) {
+            dedup.unmark(dedup_key)?;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Serialize an item to push, through the pool if there is one
+    fn serialize<T: ExternalBufferSerde>(&self, item: T) -> Result<Vec<u8>, Error> {
+        let serialized = match &self.pool {
+            Some(pool) => item.into_external_buffer_pooled(pool)?,
+            None => item.into_external_buffer()?,
         };
+        match self.max_value_bytes {
+            Some(limit) if serialized.len() > limit => {
+                let size = serialized.len();
+                self.recycle(serialized);
+                Err(Error::ItemTooLarge { size, limit })
+            }
+            _ => Ok(serialized),
+        }
+    }
 
-        // Push items
-        buffer.push(item1.clone()).await.unwrap();
-        buffer.push(item2.clone()).await.unwrap();
+    /// Push an item `serialize` returned to the tail
+    fn push_serialized(&self, serialized: Vec<u8>) -> Result<(), Error> {
+        if !self.reserve_bytes(self.stored_size(serialized.len()))? {
+            return Ok(());
+        }
+        self.append_value(serialized)
+    }
 
-        // Shift items (should come out in FIFO order)
-        let shifted1 = buffer.shift().await.unwrap();
-        assert_eq!(shifted1, Some(item1));
+    /// Hand a value back to the pool once it is copied into sled
+    fn recycle(&self, value: Vec<u8>) {
+        if let Some(pool) = &self.pool {
+            pool.put(value);
+        }
+    }
 
-        let shifted2 = buffer.shift().await.unwrap();
-        assert_eq!(shifted2, Some(item2));
+    /// Apply pushes still waiting in the write batch
+    fn apply_batched_writes(&self) -> Result<(), Error> {
+        match &self.write_batch {
+            Some(write_batch) => write_batch.apply(),
+            None => Ok(()),
+        }
+    }
 
-        // Buffer should be empty now
-        let shifted3: Option<TestItem> = buffer.shift().await.unwrap();
-        assert_eq!(shifted3, None);
+    /// Number of items dropped by `DecodeErrorPolicy::Skip`
+    pub fn skipped_decodes(&self) -> u64 {
+        self.skipped_decodes.load(Ordering::Acquire)
     }
 
-    #[tokio::test]
-    async fn test_empty_buffer() {
-        let temp_dir = TempDir::new().unwrap();
-        let buffer = ExternalBufferSled::new(temp_dir.path().join("empty_db")).unwrap();
+    /// Stamp pushed values with the push time and record how long each
+    /// item stayed buffered when it is shifted.
+    ///
+    /// Values are stored in a different format in this mode, a db written
+    /// with latency stats must always be opened with them.
+    #[cfg(feature = "stats")]
+    pub fn with_latency_stats(mut self) -> Self {
+        self.latency = Some(crate::stats::LatencyRecorder::new());
+        self
+    }
 
-        // Empty buffer should return None
-        let result: Option<TestItem> = buffer.shift().await.unwrap();
-        assert_eq!(result, None);
+    /// Encrypt pushed items with `key` and authenticate the key each one is
+    /// stored under, so items read back out of place fail with
+    /// `Error::DecryptionFailed`. Items pushed into a partition are sealed
+    /// with the key as well.
+    ///
+    /// Values are stored in a different format in this mode, a db written
+    /// with encryption must always be opened with the same key.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, key: crate::EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
     }
 
-    #[tokio::test]
-    async fn test_persistence() {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("persistent_db");
+    /// Total size of the buffered values, only tracked with `with_max_bytes`
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes.load(Ordering::Acquire)
+    }
 
-        let item = TestItem {
-            id: 42,
-            name: "persistent".to_string(),
+    /// Bytes left until the `with_max_bytes` limit, `None` without one.
+    /// The byte based counterpart of `ExternalBuffer::space_remaining`.
+    pub fn bytes_remaining(&self) -> Option<u64> {
+        self.max_bytes
+            .map(|max_bytes| max_bytes.saturating_sub(self.total_bytes()))
+    }
+
+    /// Open the buffer and reconcile the recorded meta counters against the
+    /// data keys actually present, e.g. after a crash in the middle of a push.
+    /// The data keys always win, meta is rewritten to match them.
+    pub fn open_and_repair<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> Result<(Self, RepairReport), Error> {
+        let buffer = Self::new(path)?;
+
+        let meta_head = Self::load_meta(&buffer.meta, META_HEAD)?;
+        let meta_tail = Self::load_meta(&buffer.meta, META_TAIL)?;
+        let head = buffer.head_counter.load(Ordering::Acquire);
+        let tail = buffer.tail_counter.load(Ordering::Acquire);
+
+        let repaired = meta_head != Some(head) || meta_tail != Some(tail);
+        if repaired {
+            event!(warn;
+                "Repair sled buffer meta: head {:?} -> {}, tail {:?} -> {}",
+                meta_head,
+                head,
+                meta_tail,
+                tail
+            );
+            buffer.meta.insert(META_HEAD, &head.to_be_bytes())?;
+            buffer.meta.insert(META_TAIL, &tail.to_be_bytes())?;
+        }
+
+        let report = RepairReport {
+            meta_head,
+            meta_tail,
+            head,
+            tail,
+            repaired,
         };
+        Ok((buffer, report))
+    }
 
-        // Create buffer, push item, and drop it
-        {
-            let buffer = ExternalBufferSled::new(&db_path).unwrap();
-            buffer.push(item.clone()).await.unwrap();
+    /// Open a buffer that splits serialized items larger than `threshold`
+    /// bytes into chunks, as sled struggles with multi-megabyte values.
+    ///
+    /// Values are stored in a different format in this mode, a db created
+    /// by this method must always be opened with it. Items pushed into
+    /// partitions are not chunked.
+    #[cfg(feature = "large-values")]
+    pub fn new_chunked<P: AsRef<std::path::Path>>(
+        path: P,
+        threshold: usize,
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::new(path)?;
+        buffer.chunks = Some(chunked::Chunks::open(&buffer.db, threshold)?);
+        Ok(buffer)
+    }
+
+    /// Open the buffer scanning existing keys according to `mode`, also
+    /// returns how many unreadable rows were skipped.
+    pub fn open_with_mode<P: AsRef<std::path::Path>>(
+        path: P,
+        mode: OpenMode,
+    ) -> Result<(Self, usize), Error> {
+        Self::from_db_with_mode(Self::open_db(path.as_ref(), sled::Config::new())?, mode)
+    }
+
+    /// Open the db at `path`, telling a path locked by another instance
+    /// apart from other failures
+    pub(super) fn open_db(path: &std::path::Path, config: sled::Config) -> Result<sled::Db, Error> {
+        config.path(path).open().map_err(|e| match e {
+            sled::Error::Io(io) if io.to_string().contains("could not acquire lock") => {
+                Error::DatabaseLocked {
+                    path: path.to_path_buf(),
+                }
+            }
+            e => e.into(),
+        })
+    }
+
+    fn from_db(db: sled::Db) -> Result<Self, Error> {
+        Ok(Self::from_db_with_mode(db, OpenMode::Strict)?.0)
+    }
+
+    fn from_db_with_mode(db: sled::Db, mode: OpenMode) -> Result<(Self, usize), Error> {
+        let keys = db.iter().keys();
+        Self::from_db_scanning(db, keys, mode)
+    }
+
+    /// `from_db_with_mode` counting the data `keys` scanned from `db`
+    fn from_db_scanning(
+        db: sled::Db,
+        keys: impl Iterator<Item = sled::Result<sled::IVec>>,
+        mode: OpenMode,
+    ) -> Result<(Self, usize), Error> {
+        let meta = db.open_tree(META_TREE)?;
+        let partitions = db.open_tree(PARTITION_TREE)?;
+        let partition_counters = Self::initialize_partition_counters(&partitions)?;
+        let ties = db.open_tree(TIE_TREE)?;
+
+        // Initialize counters by scanning existing keys
+        let (mut head, mut tail, count, skipped) = Self::initialize_counters(keys, mode)?;
+        if head == tail {
+            // Nothing buffered, continue from the recorded counters so keys
+            // keep increasing across restarts
+            let meta_head = Self::load_meta(&meta, META_HEAD)?.unwrap_or(0);
+            let meta_tail = Self::load_meta(&meta, META_TAIL)?.unwrap_or(0);
+            head = meta_head.max(meta_tail);
+            tail = head;
+        }
+        // tied items keep their key in the buffered range
+        let mut count = count;
+        for key in ties.iter().keys() {
+            let key = Self::u64_from_key(key?.get(..8).unwrap_or_default())?;
+            head = head.min(key);
+            tail = tail.max(key + 1);
+            count += 1;
         }
 
-        // Create new buffer with same path and verify item is still there
-        {
-            let buffer = ExternalBufferSled::new(&db_path).unwrap();
-            let retrieved = buffer.shift().await.unwrap();
-            assert_eq!(retrieved, Some(item));
+        let buffer = Self {
+            db,
+            meta,
+            head_counter: AtomicU64::new(head),
+            tail_counter: AtomicU64::new(tail),
+            item_count: AtomicU64::new(count),
+            partitions,
+            ties,
+            collision_policy: CollisionPolicy::Reject,
+            partition_counters: Mutex::new(partition_counters),
+            #[cfg(feature = "large-values")]
+            chunks: None,
+            lifo: false,
+            max_bytes: None,
+            overflow_policy: OverflowPolicy::Reject,
+            total_bytes: AtomicU64::new(0),
+            consume: true,
+            decode_error_policy: DecodeErrorPolicy::Abort,
+            skipped_decodes: AtomicU64::new(0),
+            shift_races: AtomicU64::new(0),
+            appending: RwLock::new(()),
+            read_tie: Mutex::new(None),
+            write_batch: None,
+            pool: None,
+            max_value_bytes: None,
+            dedup: None,
+            #[cfg(feature = "stats")]
+            latency: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+        };
+        Ok((buffer, skipped))
+    }
+
+    /// Read the serialized bytes out of the value stored under `key`
+    fn load_value(&self, key: u64, value: sled::IVec, consume: bool) -> Result<sled::IVec, Error> {
+        #[cfg(feature = "large-values")]
+        let value = match &self.chunks {
+            Some(chunks) => chunks.load(value, consume)?,
+            None => value,
+        };
+        #[cfg(feature = "encryption")]
+        let value = match &self.encryption {
+            Some(encryption) => sled::IVec::from(encryption.open(&key.to_be_bytes(), &value)?),
+            None => value,
+        };
+        let _ = key;
+        #[cfg(feature = "stats")]
+        if let Some(latency) = &self.latency {
+            let stamp: [u8; 8] = value
+                .get(..8)
+                .and_then(|stamp| stamp.try_into().ok())
+                .ok_or(Error::InvalidSledKeyFormat)?;
+            if consume {
+                let pushed_at = u64::from_be_bytes(stamp);
+                let elapsed = crate::stats::now_micros().saturating_sub(pushed_at);
+                latency.record(std::time::Duration::from_micros(elapsed));
+            }
+            return Ok(sled::IVec::from(&value[8..]));
         }
+        let _ = consume;
+        Ok(value)
     }
 
-    #[tokio::test]
-    async fn test_multiple_pushes_and_shifts() {
-        let temp_dir = TempDir::new().unwrap();
-        let buffer = ExternalBufferSled::new(temp_dir.path().join("multi_db")).unwrap();
+    /// Size of the value `store_value` turns `serialized_len` bytes into,
+    /// what `with_max_bytes` counts for the item
+    fn stored_size(&self, serialized_len: usize) -> usize {
+        #[cfg(feature = "stats")]
+        let serialized_len = match &self.latency {
+            Some(_) => 8 + serialized_len,
+            None => serialized_len,
+        };
+        #[cfg(feature = "encryption")]
+        let serialized_len = match &self.encryption {
+            Some(_) => serialized_len + crate::EncryptionKey::OVERHEAD,
+            None => serialized_len,
+        };
+        #[cfg(feature = "large-values")]
+        if let Some(chunks) = &self.chunks {
+            return chunks.stored_size(serialized_len);
+        }
+        serialized_len
+    }
 
-        let items: Vec<TestItem> = (0..10)
-            .map(|i| TestItem {
-                id: i,
-                name: format!("item_{}", i),
-            })
-            .collect();
+    /// Turn serialized bytes into the value to store under `key`
+    fn store_value(&self, key: u64, serialized: Vec<u8>) -> Result<Vec<u8>, Error> {
+        #[cfg(feature = "stats")]
+        let serialized = match &self.latency {
+            Some(_) => {
+                let mut stamped = Vec::with_capacity(8 + serialized.len());
+                stamped.extend_from_slice(&crate::stats::now_micros().to_be_bytes());
+                stamped.extend_from_slice(&serialized);
+                stamped
+            }
+            None => serialized,
+        };
+        #[cfg(feature = "encryption")]
+        let serialized = match &self.encryption {
+            Some(encryption) => {
+                let sealed = encryption.seal(&key.to_be_bytes(), &serialized)?;
+                self.recycle(serialized);
+                sealed
+            }
+            None => serialized,
+        };
+        let _ = key;
+        #[cfg(feature = "large-values")]
+        if let Some(chunks) = &self.chunks {
+            return chunks.store(serialized);
+        }
+        Ok(serialized)
+    }
 
-        // Push all items
-        for item in &items {
-            buffer.push(item.clone()).await.unwrap();
+    /// Re-encrypt the value stored under `from` for `to`, returns the value
+    /// to store under `to` and the one whose chunks to discard once it is
+    /// stored. Without encryption the value moves as it is.
+    fn move_stored_value(
+        &self,
+        from: u64,
+        to: u64,
+        value: sled::IVec,
+    ) -> Result<(sled::IVec, Option<sled::IVec>), Error> {
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            #[cfg(feature = "large-values")]
+            let loaded = match &self.chunks {
+                Some(chunks) => chunks.load(value.clone(), false)?,
+                None => value.clone(),
+            };
+            #[cfg(not(feature = "large-values"))]
+            let loaded = value.clone();
+            let opened = encryption.open(&from.to_be_bytes(), &loaded)?;
+            let sealed = encryption.seal(&to.to_be_bytes(), &opened)?;
+            #[cfg(feature = "large-values")]
+            let sealed = match &self.chunks {
+                Some(chunks) => chunks.store(sealed)?,
+                None => sealed,
+            };
+            return Ok((sled::IVec::from(sealed), Some(value)));
         }
+        let _ = (from, to);
+        Ok((value, None))
+    }
 
-        // Shift all items and verify order
-        for expected_item in &items {
-            let shifted = buffer.shift().await.unwrap();
-            assert_eq!(shifted, Some(expected_item.clone()));
+    fn initialize_partition_counters(
+        partitions: &sled::Tree,
+    ) -> Result<HashMap<u32, (u32, u32)>, Error> {
+        let mut counters: HashMap<u32, (u32, u32)> = HashMap::new();
+        for result in partitions.iter() {
+            let (key, _) = result?;
+            let (partition, seq) = Self::split_partition_key(&key)?;
+            counters
+                .entry(partition)
+                .and_modify(|(head, tail)| {
+                    *head = (*head).min(seq);
+                    *tail = (*tail).max(seq + 1);
+                })
+                .or_insert((seq, seq + 1));
         }
+        Ok(counters)
+    }
 
-        // Buffer should be empty
-        let result: Option<TestItem> = buffer.shift().await.unwrap();
-        assert_eq!(result, None);
+    fn partition_key(partition: u32, seq: u32) -> [u8; 8] {
+        let mut key = [0u8; 8];
+        key[..4].copy_from_slice(&partition.to_be_bytes());
+        key[4..].copy_from_slice(&seq.to_be_bytes());
+        key
     }
 
-    #[tokio::test]
-    async fn test_interleaved_push_and_shift() {
-        let temp_dir = TempDir::new().unwrap();
-        let buffer = ExternalBufferSled::new(temp_dir.path().join("interleaved_db")).unwrap();
+    fn split_partition_key(key: &[u8]) -> Result<(u32, u32), Error> {
+        if key.len() != 8 {
+            return Err(Error::InvalidSledKeyFormat);
+        }
+        let partition = u32::from_be_bytes(key[..4].try_into().unwrap());
+        let seq = u32::from_be_bytes(key[4..].try_into().unwrap());
+        Ok((partition, seq))
+    }
 
-        let item1 = TestItem {
-            id: 1,
-            name: "first".to_string(),
-        };
-        let item2 = TestItem {
-            id: 2,
-            name: "second".to_string(),
+    /// Push an item to the end of the given partition. Partitions are kept
+    /// apart from the items pushed through `ExternalBuffer::push`.
+    pub fn push_partition<T: ExternalBufferSerde>(
+        &self,
+        partition: u32,
+        item: T,
+    ) -> Result<(), Error> {
+        let serialized = item.into_external_buffer()?;
+        let mut counters = self.partition_counters.lock()?;
+        let (_, tail) = counters.entry(partition).or_insert((0, 0));
+        let key = Self::partition_key(partition, *tail);
+        #[cfg(feature = "encryption")]
+        let serialized = match &self.encryption {
+            Some(encryption) => encryption.seal(&Self::partition_position(key), &serialized)?,
+            None => serialized,
         };
-        let item3 = TestItem {
-            id: 3,
-            name: "third".to_string(),
+        self.partitions.insert(key, serialized)?;
+        *tail += 1;
+        Ok(())
+    }
+
+    /// What a partition item is authenticated with, named apart from the
+    /// keys of the items pushed through `ExternalBuffer::push`
+    #[cfg(feature = "encryption")]
+    fn partition_position(key: [u8; 8]) -> Vec<u8> {
+        [PARTITION_TREE, &key[..]].concat()
+    }
+
+    /// Shift the head item of the given partition only
+    pub fn shift_partition<T: ExternalBufferSerde>(
+        &self,
+        partition: u32,
+    ) -> Result<Option<T>, Error> {
+        let mut counters = self.partition_counters.lock()?;
+        let Some((head, tail)) = counters.get_mut(&partition) else {
+            return Ok(None);
         };
+        while *head < *tail {
+            let key = Self::partition_key(partition, *head);
+            let removed = self.partitions.remove(key)?;
+            *head += 1;
+            if let Some(data) = removed {
+                #[cfg(feature = "encryption")]
+                let data = match &self.encryption {
+                    Some(encryption) => {
+                        sled::IVec::from(encryption.open(&Self::partition_position(key), &data)?)
+                    }
+                    None => data,
+                };
+                return Ok(Some(T::from_external_buffer(&data)?));
+            }
+        }
+        Ok(None)
+    }
 
-        // Push one, shift one
-        buffer.push(item1.clone()).await.unwrap();
-        let shifted1 = buffer.shift().await.unwrap();
-        assert_eq!(shifted1, Some(item1));
+    fn load_meta(meta: &sled::Tree, key: &[u8]) -> Result<Option<u64>, Error> {
+        match meta.get(key)? {
+            Some(value) => Ok(Some(u64::from_be_bytes(
+                value
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| Error::InvalidSledKeyFormat)?,
+            ))),
+            None => Ok(None),
+        }
+    }
 
-        // Push two, shift two
-        buffer.push(item2.clone()).await.unwrap();
-        buffer.push(item3.clone()).await.unwrap();
+    fn store_head(&self) -> Result<(), Error> {
+        // Acquire, a head moved by another shift is stored only after what
+        // that shift removed, so a reopen never finds the head behind it
+        let head = self.head_counter.load(Ordering::Acquire);
+        self.meta.insert(META_HEAD, &head.to_be_bytes())?;
+        Ok(())
+    }
 
-        let shifted2 = buffer.shift().await.unwrap();
-        assert_eq!(shifted2, Some(item2));
+    /// Scan the keys of the default tree for head, tail and the number of
+    /// items, returns them with the number of unreadable rows skipped in
+    /// lenient mode
+    fn initialize_counters(
+        keys: impl Iterator<Item = sled::Result<sled::IVec>>,
+        mode: OpenMode,
+    ) -> Result<(u64, u64, u64, usize), Error> {
+        let mut min_key = u64::MAX;
+        let mut max_key = 0u64;
+        let mut count = 0;
+        let mut skipped = 0;
 
-        let shifted3 = buffer.shift().await.unwrap();
-        assert_eq!(shifted3, Some(item3));
+        for (index, result) in keys.enumerate() {
+            match (result, mode) {
+                (Ok(key), _) => {
+                    // keys of other lengths are not buffer items, skip them
+                    let Ok(bytes) = <[u8; 8]>::try_from(key.as_ref()) else {
+                        continue;
+                    };
+                    let key_u64 = u64::from_be_bytes(bytes);
+                    min_key = min_key.min(key_u64);
+                    max_key = max_key.max(key_u64);
+                    count += 1;
+                }
+                (Err(e), OpenMode::Strict) => {
+                    event!(error; "Unreadable sled row {} while opening: {}", index, e);
+                    return Err(e.into());
+                }
+                (Err(e), OpenMode::Lenient) => {
+                    event!(warn; "Skip unreadable sled row {} while opening: {}", index, e);
+                    skipped += 1;
+                }
+            }
+        }
 
-        // Should be empty
-        let result: Option<TestItem> = buffer.shift().await.unwrap();
-        assert_eq!(result, None);
+        if count > 0 {
+            Ok((min_key, max_key + 1, count, skipped))
+        } else {
+            Ok((0, 0, 0, skipped))
+        }
+    }
+
+    /// The underlying db, e.g. to run a transaction spanning the buffer and
+    /// your own trees.
+    ///
+    /// Items live under 8 byte big-endian keys of the default tree and the
+    /// trees named `__external_buffer_*` hold the buffer's bookkeeping.
+    /// Writing to any of them behind the buffer's back is undefined.
+    pub fn db(&self) -> &sled::Db {
+        &self.db
+    }
+
+    /// Number of buffered items
+    pub fn len(&self) -> usize {
+        self.item_count.load(Ordering::Acquire) as usize
+    }
+
+    /// Push an item under a key of the caller's choosing, e.g. an event
+    /// timestamp to merge several producers in time order. Items are still
+    /// shifted in ascending key order, also when mixed with `push`, and a
+    /// key already in use is rejected with `Error::DuplicateKey`, unless
+    /// the `CollisionPolicy::Suffix` keeps both in push order.
+    pub fn push_with_key<T: ExternalBufferSerde>(&self, key: u64, item: T) -> Result<(), Error> {
+        let serialized = self.serialize(item)?;
+        let size = self.stored_size(serialized.len());
+        if !self.reserve_bytes(size)? {
+            return Ok(());
+        }
+
+        let value = self.store_value(key, serialized)?;
+        let inserted = self.db.compare_and_swap(
+            Self::key_from_u64(key),
+            None as Option<&[u8]>,
+            Some(&value[..]),
+        )?;
+        if inserted.is_err() {
+            if self.collision_policy == CollisionPolicy::Reject {
+                // the chunks are under an id of their own, the item already
+                // stored under `key` keeps its chunks
+                #[cfg(feature = "large-values")]
+                if let Some(chunks) = &self.chunks {
+                    chunks.discard(&value)?;
+                }
+                self.recycle(value);
+                self.release_bytes(size)?;
+                return Err(Error::DuplicateKey(key));
+            }
+            self.push_tie(key, &value)?;
+        }
+        self.recycle(value);
+        self.item_count.fetch_add(1, Ordering::AcqRel);
+
+        // keep the key between head and tail, read-modify-writes so
+        // concurrent keyed pushes never move a counter back past each other
+        if self.head_counter.fetch_min(key, Ordering::AcqRel) > key {
+            self.store_head()?;
+        }
+        let tail = self
+            .tail_counter
+            .fetch_max(key + 1, Ordering::AcqRel)
+            .max(key + 1);
+        self.meta.insert(META_TAIL, &tail.to_be_bytes())?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Push an item in front of everything buffered so it is the very next
+    /// item shifted, e.g. to retry a failed item right away instead of after
+    /// the backlog. It is stored under the key below the head, if the head
+    /// is at key 0 the buffered items are moved up first, which also moves
+    /// the keys given to `push_with_key`.
+    ///
+    /// Meant for the consumer, it must not run concurrently with a shift.
+    /// A LIFO buffer shifts the newest item next, so there it is a plain
+    /// push. With `consume(false)` the key below the head still holds an
+    /// item read before and the push fails with `Error::DuplicateKey`.
+    pub fn push_front<T: ExternalBufferSerde + Send + 'static>(
+        &self,
+        item: T,
+    ) -> Result<(), Error> {
+        if self.lifo {
+            return self.push_sync(item);
+        }
+        self.apply_batched_writes()?;
+        let serialized = self.serialize(item)?;
+        let size = self.stored_size(serialized.len());
+        if !self.reserve_bytes(size)? {
+            return Ok(());
+        }
+
+        if self.head_counter.load(Ordering::Acquire) == 0 {
+            self.rebase()?;
+        }
+        // Acquire, sees the rebase above along with the items it moved
+        let key = self.head_counter.load(Ordering::Acquire) - 1;
+        if self.db.contains_key(Self::key_from_u64(key))? {
+            self.release_bytes(size)?;
+            return Err(Error::DuplicateKey(key));
+        }
+        let value = self.store_value(key, serialized)?;
+        self.db.insert(Self::key_from_u64(key), &value[..])?;
+        self.recycle(value);
+        self.item_count.fetch_add(1, Ordering::AcqRel);
+        // AcqRel publishes the insert above to shifts loading the head
+        self.head_counter.fetch_min(key, Ordering::AcqRel);
+        self.store_head()
+    }
+
+    /// Move every item below the tail, with its ties, up by at least
+    /// `REBASE_OFFSET` keys to make room below the head. Later pushes land
+    /// above the moved items. A push that took its key before and stores
+    /// it only after the move stays below the head until reopened.
+    fn rebase(&self) -> Result<(), Error> {
+        let (tail, offset) = loop {
+            let tail = self.tail_counter.load(Ordering::Acquire);
+            // the moved keys never overlap the ones they are moved from
+            let offset = tail.max(REBASE_OFFSET);
+            // the CAS claims the keys to move, a push taking a key in
+            // between makes it fail and retry with the new tail
+            if self
+                .tail_counter
+                .compare_exchange(tail, tail + offset, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                break (tail, offset);
+            }
+        };
+        self.meta
+            .insert(META_TAIL, &(tail + offset).to_be_bytes())?;
+
+        let end = Self::key_from_u64(tail);
+        let mut moved = 0;
+        // values re-encrypted for their new key, their old chunks go once
+        // the moved values are stored
+        let mut replaced = Vec::new();
+        let mut items = sled::Batch::default();
+        for entry in self.db.range(..end) {
+            let (key_bytes, value) = entry?;
+            let key = Self::u64_from_key(&key_bytes)?;
+            // chunks are stored by id, not by key, they stay where they are
+            let (value, old) = self.move_stored_value(key, key + offset, value)?;
+            replaced.extend(old);
+            items.remove(key_bytes);
+            items.insert(&Self::key_from_u64(key + offset), value);
+            moved += 1;
+        }
+        let mut ties = sled::Batch::default();
+        for entry in self.ties.range(..end) {
+            let (tie_key, value) = entry?;
+            let key = Self::u64_from_key(tie_key.get(..8).ok_or(Error::InvalidSledKeyFormat)?)?;
+            let (value, old) = self.move_stored_value(key, key + offset, value)?;
+            replaced.extend(old);
+            let mut moved_key = tie_key.to_vec();
+            moved_key[..8].copy_from_slice(&Self::key_from_u64(key + offset));
+            ties.remove(tie_key);
+            ties.insert(moved_key, value);
+        }
+        self.db.apply_batch(items)?;
+        self.ties.apply_batch(ties)?;
+        #[cfg(feature = "large-values")]
+        if let Some(chunks) = &self.chunks {
+            for value in &replaced {
+                chunks.discard(value)?;
+            }
+        }
+        event!(debug; "Rebased {} sled buffer items by {} keys", moved, offset);
+
+        // AcqRel, a shift loading the moved head finds the moved items
+        self.head_counter.fetch_add(offset, Ordering::AcqRel);
+        self.store_head()
+    }
+
+    /// Read the item stored under `key` without removing it
+    pub fn peek_at<T: ExternalBufferSerde>(&self, key: u64) -> Result<Option<T>, Error> {
+        self.apply_batched_writes()?;
+        match self.db.get(Self::key_from_u64(key))? {
+            Some(data) => {
+                let data = self.load_value(key, data, false)?;
+                Ok(Some(Self::decode_item(key, &data)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Serialized size of the item the next `shift` returns, without
+    /// removing or decoding it
+    pub fn peek_size(&self) -> Result<Option<usize>, Error> {
+        self.apply_batched_writes()?;
+        let head = self.head_counter.load(Ordering::Acquire);
+        let tail = self.tail_counter.load(Ordering::Acquire);
+        let next = if self.lifo {
+            self.db
+                .range(..Self::key_from_u64(tail))
+                .next_back()
+                .transpose()?
+        } else if let Some(value) = self.db.get(Self::key_from_u64(head))? {
+            return Ok(Some(self.load_value(head, value, false)?.len()));
+        } else if let Some(tie) = self.ties.scan_prefix(Self::key_from_u64(head)).next() {
+            // the head item is shifted, items tied to it come next
+            return Ok(Some(self.load_value(head, tie?.1, false)?.len()));
+        } else {
+            self.db
+                .range(Self::key_from_u64(head)..Self::key_from_u64(tail))
+                .next()
+                .transpose()?
+        };
+
+        match next {
+            Some((key, value)) => {
+                let key = Self::u64_from_key(&key)?;
+                if key < head {
+                    return Ok(None);
+                }
+                Ok(Some(self.load_value(key, value, false)?.len()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Walk the buffered items from head to tail without consuming them
+    pub fn cursor<T: ExternalBufferSerde>(&self) -> SledCursor<'_, T> {
+        self.cursor_from(self.head_counter.load(Ordering::Acquire))
+    }
+
+    /// Resume a walk at a `SledCursor::position` saved earlier, e.g. before
+    /// a restart
+    pub fn cursor_from<T: ExternalBufferSerde>(&self, position: u64) -> SledCursor<'_, T> {
+        SledCursor {
+            buffer: self,
+            position,
+            _item: PhantomData,
+        }
+    }
+
+    /// Discard up to `n` items from the head of the buffer without
+    /// deserializing them, returns how many items were actually skipped.
+    pub fn skip(&self, n: usize) -> Result<usize, Error> {
+        self.apply_batched_writes()?;
+        let mut skipped = 0;
+        while skipped < n {
+            let Some((_, value)) = self.remove_head()? else {
+                break;
+            };
+            #[cfg(feature = "large-values")]
+            if let Some(chunks) = &self.chunks {
+                chunks.discard(&value)?;
+            }
+            let _ = value;
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+
+    /// Remove the items from head on that `pred` matches, also the ones
+    /// tied to a key. A gap left at the tail is skipped by the next shift.
+    fn remove_items<T: ExternalBufferSerde>(
+        &self,
+        pred: impl Fn(&T) -> bool,
+    ) -> Result<usize, Error> {
+        self.apply_batched_writes()?;
+        let start = Self::key_from_u64(self.head_counter.load(Ordering::Acquire));
+        let mut removed = 0;
+        for entry in self.db.range(start..) {
+            let (key_bytes, value) = entry?;
+            let key = Self::u64_from_key(&key_bytes)?;
+            if self.remove_matching(&self.db, key, &key_bytes, value, &pred)? {
+                removed += 1;
+            }
+        }
+        for entry in self.ties.range(start..) {
+            let (tie_key, value) = entry?;
+            let key = Self::u64_from_key(tie_key.get(..8).ok_or(Error::InvalidSledKeyFormat)?)?;
+            if self.remove_matching(&self.ties, key, &tie_key, value, &pred)? {
+                removed += 1;
+            }
+        }
+
+        // move the head past removed items so shifts don't find it missing
+        if removed > 0 && !self.lifo {
+            let head = self.head_counter.load(Ordering::Acquire);
+            if head < self.tail_counter.load(Ordering::Acquire)
+                && self.db.get(Self::key_from_u64(head))?.is_none()
+                && !self.has_ties(head)?
+            {
+                self.advance_head(head)?;
+                self.store_head()?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove `value` stored under `tree_key` if `pred` matches the item,
+    /// unless a shift took it first
+    fn remove_matching<T: ExternalBufferSerde>(
+        &self,
+        tree: &sled::Tree,
+        key: u64,
+        tree_key: &[u8],
+        value: sled::IVec,
+        pred: &impl Fn(&T) -> bool,
+    ) -> Result<bool, Error> {
+        let data = self.load_value(key, value.clone(), false)?;
+        let item = Self::decode_item(key, &data)?;
+        if !pred(&item) {
+            return Ok(false);
+        }
+        self.remove_value(tree, tree_key, value)
+    }
+
+    /// Remove `value` stored under `tree_key` along with its chunks, unless
+    /// a shift took it first
+    fn remove_value(
+        &self,
+        tree: &sled::Tree,
+        tree_key: &[u8],
+        value: sled::IVec,
+    ) -> Result<bool, Error> {
+        if tree
+            .compare_and_swap(tree_key, Some(&value), None as Option<&[u8]>)?
+            .is_err()
+        {
+            return Ok(false);
+        }
+        self.item_count.fetch_sub(1, Ordering::AcqRel);
+        self.release_bytes(value.len())?;
+        #[cfg(feature = "large-values")]
+        if let Some(chunks) = &self.chunks {
+            chunks.discard(&value)?;
+        }
+        Ok(true)
+    }
+
+    /// Synchronously shift every item until the buffer is empty, handy for
+    /// batch post-processing after the source has ended.
+    pub fn drain_all<T: ExternalBufferSerde>(&self) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        while let Some(item) = self.shift_item()? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    /// Move every item of `other` into this buffer, e.g. to combine the
+    /// buffers of two shards, returns how many were moved. Items are read
+    /// from `other` in key order and pushed here, `order` decides where they
+    /// go. An item is removed from `other` only once the push succeeded, so
+    /// a failed merge leaves the items not moved yet in `other`.
+    ///
+    /// With `MergeOrder::ByKey` a key used by both buffers fails the merge
+    /// with `Error::DuplicateKey` before anything is moved, unless this
+    /// buffer's `CollisionPolicy::Suffix` keeps both. Neither buffer should
+    /// be pushed to or shifted from while merging.
+    pub fn merge_from<T: ExternalBufferSerde + Send + 'static>(
+        &self,
+        other: &ExternalBufferSled,
+        order: MergeOrder,
+    ) -> Result<usize, Error> {
+        other.apply_batched_writes()?;
+        if order == MergeOrder::ByKey && self.collision_policy == CollisionPolicy::Reject {
+            let head = other.head_counter.load(Ordering::Acquire);
+            for key in other.db.range(Self::key_from_u64(head)..).keys() {
+                let key = key?;
+                if self.db.contains_key(&key)? {
+                    return Err(Error::DuplicateKey(Self::u64_from_key(&key)?));
+                }
+            }
+        }
+
+        let head = other.head_counter.load(Ordering::Acquire);
+        let mut merged = 0;
+        // items tied to the head key whose own item is shifted already
+        if other.db.get(Self::key_from_u64(head))?.is_none() {
+            for tie in other.ties.scan_prefix(Self::key_from_u64(head)) {
+                let (tie_key, value) = tie?;
+                merged += other.move_value::<T>(self, order, &other.ties, head, &tie_key, value)?;
+            }
+        }
+        for entry in other.db.range(Self::key_from_u64(head)..) {
+            let (key_bytes, value) = entry?;
+            let key = Self::u64_from_key(&key_bytes)?;
+            merged += other.move_value::<T>(self, order, &other.db, key, &key_bytes, value)?;
+            for tie in other.ties.scan_prefix(&key_bytes) {
+                let (tie_key, value) = tie?;
+                merged += other.move_value::<T>(self, order, &other.ties, key, &tie_key, value)?;
+            }
+        }
+
+        // every item from head on is gone, don't leave the head on a gap
+        if !other.lifo && head < other.tail_counter.load(Ordering::Acquire) {
+            other.advance_head(head)?;
+            other.store_head()?;
+        }
+        Ok(merged)
+    }
+
+    /// Push the item `value` stored under `tree_key` holds into `into`, then
+    /// remove it here. Returns how many items moved, an item failing to
+    /// decode is only removed if `DecodeErrorPolicy::Skip` allows it.
+    fn move_value<T: ExternalBufferSerde + Send + 'static>(
+        &self,
+        into: &ExternalBufferSled,
+        order: MergeOrder,
+        tree: &sled::Tree,
+        key: u64,
+        tree_key: &[u8],
+        value: sled::IVec,
+    ) -> Result<usize, Error> {
+        let data = self.load_value(key, value.clone(), false)?;
+        let moved = match self.decode_shifted::<T>(key, &data)? {
+            Some(item) => {
+                match order {
+                    MergeOrder::Append => into.push_sync(item)?,
+                    MergeOrder::ByKey => into.push_with_key(key, item)?,
+                }
+                1
+            }
+            None => 0,
+        };
+        self.remove_value(tree, tree_key, value)?;
+        Ok(moved)
+    }
+
+    /// Write the pending items and the head and tail counters to `writer`,
+    /// e.g. for a backup or to move the buffer to another machine. The
+    /// format only frames the serialized items, it does not depend on the
+    /// sled version or on how this buffer stores values. Items pushed into
+    /// a partition are not included.
+    ///
+    /// The counters come first, then every item as its key, length and
+    /// serialized bytes, each number a big endian u64.
+    pub fn export(&self, writer: impl Write) -> Result<(), Error> {
+        self.apply_batched_writes()?;
+        let mut writer = BufWriter::new(writer);
+        // Acquire, the counters exported match the items they point at
+        // unless a push or shift runs at the same time
+        let head = self.head_counter.load(Ordering::Acquire);
+        let tail = self.tail_counter.load(Ordering::Acquire);
+        write_u64s(&mut writer, &[head, tail])?;
+        for entry in self.db.range(Self::key_from_u64(head)..) {
+            let (key, value) = entry?;
+            let key = Self::u64_from_key(&key)?;
+            let ties = self.ties.scan_prefix(Self::key_from_u64(key)).values();
+            for value in std::iter::once(Ok(value)).chain(ties) {
+                let value = self.load_value(key, value?, false)?;
+                write_u64s(&mut writer, &[key, value.len() as u64])?;
+                writer.write_all(&value).map_err(make_custom_error)?;
+            }
+        }
+        writer.flush().map_err(make_custom_error)
+    }
+
+    /// Open a buffer at `path` with the items and counters written by
+    /// `export`. Meant for a fresh path, items under a key already in use
+    /// are kept as ties. Values are stored the way `new` stores them.
+    pub fn import<P: AsRef<std::path::Path>>(path: P, reader: impl Read) -> Result<Self, Error> {
+        let buffer = Self::new(path)?;
+        let mut reader = BufReader::new(reader);
+        let mut counters = [0u8; 16];
+        reader
+            .read_exact(&mut counters)
+            .map_err(make_custom_error)?;
+        let (head, tail) = counters.split_at(8);
+        let head = u64::from_be_bytes(head.try_into().unwrap());
+        let tail = u64::from_be_bytes(tail.try_into().unwrap());
+
+        let was_empty = buffer.is_empty();
+        let mut header = [0u8; 16];
+        // the file may only end between two items
+        while read_or_end(&mut reader, &mut header)? {
+            let (key, len) = header.split_at(8);
+            let key = u64::from_be_bytes(key.try_into().unwrap());
+            let len = u64::from_be_bytes(len.try_into().unwrap());
+            // read as it arrives, a corrupt length must not allocate it all
+            let mut serialized = Vec::new();
+            (&mut reader)
+                .take(len)
+                .read_to_end(&mut serialized)
+                .map_err(make_custom_error)?;
+            if (serialized.len() as u64) < len {
+                return Err(make_custom_error(std::io::Error::from(
+                    std::io::ErrorKind::UnexpectedEof,
+                )));
+            }
+
+            let value = buffer.store_value(key, serialized)?;
+            buffer.insert_imported(key, &value)?;
+            buffer.item_count.fetch_add(1, Ordering::AcqRel);
+        }
+
+        // Release and AcqRel publish the imported items to shifts
+        if was_empty {
+            buffer.head_counter.store(head, Ordering::Release);
+        } else {
+            buffer.head_counter.fetch_min(head, Ordering::AcqRel);
+        }
+        buffer.store_head()?;
+        let tail = buffer
+            .tail_counter
+            .fetch_max(tail, Ordering::AcqRel)
+            .max(tail);
+        buffer.meta.insert(META_TAIL, &tail.to_be_bytes())?;
+        Ok(buffer)
+    }
+
+    /// Store an imported value under `key`, after the ones already there.
+    /// Its chunks are discarded if that fails.
+    fn insert_imported(&self, key: u64, value: &[u8]) -> Result<(), Error> {
+        let inserted = self
+            .db
+            .compare_and_swap(Self::key_from_u64(key), None as Option<&[u8]>, Some(value))
+            .map_err(Error::from)
+            .and_then(|inserted| match inserted {
+                Ok(()) => Ok(()),
+                Err(_) => self.push_tie(key, value),
+            });
+        #[cfg(feature = "large-values")]
+        if inserted.is_err()
+            && let Some(chunks) = &self.chunks
+        {
+            chunks.discard(value)?;
+        }
+        inserted
+    }
+
+    fn shift_item<T: ExternalBufferSerde>(&self) -> Result<Option<T>, Error> {
+        Ok(self.shift_keyed_item()?.map(|(_, item)| item))
+    }
+
+    /// Shift the next item along with the key it was stored under
+    fn shift_keyed_item<T: ExternalBufferSerde>(&self) -> Result<Option<(u64, T)>, Error> {
+        self.apply_batched_writes()?;
+        if self.lifo {
+            return self.shift_newest_item();
+        }
+        if !self.consume {
+            return self.read_next_item();
+        }
+
+        while let Some((key, data)) = self.remove_head()? {
+            let data = self.load_value(key, data, true)?;
+            if let Some(item) = self.decode_shifted(key, &data)? {
+                return Ok(Some((key, item)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove the stored value at the head and move the head past it,
+    /// along with its key, shared by the consuming FIFO `shift` and `skip`.
+    /// Its chunks are left for the caller.
+    fn remove_head(&self) -> Result<Option<(u64, sled::IVec)>, Error> {
+        loop {
+            // Acquire pairs with the AcqRel moves of the head, the keys
+            // below a head loaded here are removed already. Two shifts may
+            // load the same head, the `remove` below picks the one winner.
+            let current_head = self.head_counter.load(Ordering::Acquire);
+            let current_tail = self.tail_counter.load(Ordering::Acquire);
+
+            // Check if buffer is empty
+            if current_head >= current_tail {
+                return Ok(None);
+            }
+
+            let key_bytes = Self::key_from_u64(current_head);
+
+            // Try to remove the item atomically
+            match self.db.remove(key_bytes)? {
+                Some(data) => {
+                    // Successfully removed, update head counter, a shift
+                    // may have already moved it past a gap. Items tied to
+                    // the key are shifted before moving on.
+                    // fetch_max, never moves the head back past another
+                    // shift that got further
+                    if !self.has_ties(current_head)? {
+                        self.head_counter
+                            .fetch_max(current_head + 1, Ordering::AcqRel);
+                    }
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    self.store_head()?;
+                    self.release_bytes(data.len())?;
+                    return Ok(Some((current_head, data)));
+                }
+                None => {
+                    if let Some(data) = self.pop_tie(current_head)? {
+                        self.item_count.fetch_sub(1, Ordering::AcqRel);
+                        self.release_bytes(data.len())?;
+                        return Ok(Some((current_head, data)));
+                    }
+                    // A push may have taken the key without storing it
+                    // yet, wait for the pushes in flight before taking it
+                    // for a gap
+                    drop(self.appending.write().unwrap_or_else(|e| e.into_inner()));
+                    self.apply_batched_writes()?;
+                    if self.db.contains_key(key_bytes)? {
+                        continue;
+                    }
+                    // Removed by another thread or never pushed under a
+                    // caller's key, try the next key present
+                    // Relaxed, a statistic that orders nothing else
+                    let races = self.shift_races.fetch_add(1, Ordering::Relaxed) + 1;
+                    event!(debug, item_key = current_head;
+                        "Sled buffer key {} is already gone, {} such shifts so far",
+                        current_head,
+                        races
+                    );
+                    self.advance_head(current_head)?;
+                    self.store_head()?;
+                }
+            }
+        }
+    }
+
+    /// Move the head past the next item and return it, keeping it stored.
+    /// The head stays past a key while its ties are read, `read_tie`
+    /// remembers which of them were.
+    fn read_next_item<T: ExternalBufferSerde>(&self) -> Result<Option<(u64, T)>, Error> {
+        let mut read_tie = self.read_tie.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(last) = read_tie.take() {
+                let key = Self::u64_from_key(last.get(..8).ok_or(Error::InvalidSledKeyFormat)?)?;
+                let next_tie = self
+                    .ties
+                    .range((std::ops::Bound::Excluded(last), std::ops::Bound::Unbounded))
+                    .next()
+                    .transpose()?
+                    .filter(|(tie_key, _)| tie_key.starts_with(&Self::key_from_u64(key)));
+                if let Some((tie_key, data)) = next_tie {
+                    *read_tie = Some(tie_key.to_vec());
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    let data = self.load_value(key, data, false)?;
+                    if let Some(item) = self.decode_shifted(key, &data)? {
+                        return Ok(Some((key, item)));
+                    }
+                    continue;
+                }
+            }
+
+            let current_head = self.head_counter.load(Ordering::Acquire);
+            let current_tail = self.tail_counter.load(Ordering::Acquire);
+            let key = match self.next_stored_key_from(current_head)? {
+                Some(key) if key < current_tail => key,
+                _ => return Ok(None),
+            };
+
+            // claim the key, retry if another shift read past it first.
+            // AcqRel, the winner of the CAS owns the key exclusively
+            if self
+                .head_counter
+                .compare_exchange(current_head, key + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                continue;
+            }
+            self.store_head()?;
+            // the ties sort after the key itself
+            *read_tie = Some(Self::key_from_u64(key).to_vec());
+
+            if let Some(data) = self.db.get(Self::key_from_u64(key))? {
+                self.item_count.fetch_sub(1, Ordering::AcqRel);
+                let data = self.load_value(key, data, false)?;
+                if let Some(item) = self.decode_shifted(key, &data)? {
+                    return Ok(Some((key, item)));
+                }
+            }
+        }
+    }
+
+    fn shift_newest_item<T: ExternalBufferSerde>(&self) -> Result<Option<(u64, T)>, Error> {
+        loop {
+            let current_head = self.head_counter.load(Ordering::Acquire);
+            let current_tail = self.tail_counter.load(Ordering::Acquire);
+
+            if current_head >= current_tail {
+                return Ok(None);
+            }
+
+            // the ties of the last key were pushed after it, newest first
+            let key = current_tail - 1;
+            if let Some(data) = self.pop_last_tie(key)? {
+                self.item_count.fetch_sub(1, Ordering::AcqRel);
+                self.release_bytes(data.len())?;
+                let data = self.load_value(key, data, true)?;
+                if let Some(item) = self.decode_shifted(key, &data)? {
+                    return Ok(Some((key, item)));
+                }
+                continue;
+            }
+
+            // Claim the last key, retry if a push or shift moved the tail.
+            // AcqRel, pushes take keys with read-modify-writes on the same
+            // counter, so the CAS fails if one took a key after the load
+            if self
+                .tail_counter
+                .compare_exchange(current_tail, key, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                continue;
+            }
+            self.meta.insert(META_TAIL, &key.to_be_bytes())?;
+
+            match self.db.remove(Self::key_from_u64(key))? {
+                Some(data) => {
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    self.release_bytes(data.len())?;
+                    let data = self.load_value(key, data, true)?;
+                    if let Some(item) = self.decode_shifted(key, &data)? {
+                        return Ok(Some((key, item)));
+                    }
+                }
+                None => {
+                    // jump over a gap left by caller's keys, to the key
+                    // below holding an item or only ties
+                    let end = Self::key_from_u64(key);
+                    let item = match self.db.range(..end).keys().next_back() {
+                        Some(prev) => Some(Self::u64_from_key(&prev?)?),
+                        None => None,
+                    };
+                    let tied = match self.ties.range(..end).keys().next_back() {
+                        Some(prev) => {
+                            let prev = prev?;
+                            Some(Self::u64_from_key(
+                                prev.get(..8).ok_or(Error::InvalidSledKeyFormat)?,
+                            )?)
+                        }
+                        None => None,
+                    };
+                    let below = item.max(tied).map_or(current_head, |prev| prev + 1);
+                    // fetch_min, a push moving the tail up meanwhile is
+                    // not undone
+                    self.tail_counter
+                        .fetch_min(below.max(current_head), Ordering::AcqRel);
+                }
+            }
+        }
+    }
+
+    /// Move head from the missing `from` key to the next key present, or
+    /// to the tail if there is none. Head never passes the tail, a key
+    /// beyond it belongs to a push that has not moved the tail yet. Every
+    /// call moves head forward by at least one, so callers retrying after
+    /// it always make progress.
+    fn advance_head(&self, from: u64) -> Result<(), Error> {
+        // Acquire, the keys below the tail seen here are taken, stored or
+        // about to be
+        let tail = self.tail_counter.load(Ordering::Acquire);
+        let next = match self.next_key_from(from + 1)? {
+            Some(next) => next.min(tail),
+            None => tail,
+        };
+        self.head_counter
+            .fetch_max(next.max(from + 1), Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Store a value pushed under a key in use after the ones tied to it
+    fn push_tie(&self, key: u64, value: &[u8]) -> Result<(), Error> {
+        loop {
+            let seq = match self
+                .ties
+                .scan_prefix(Self::key_from_u64(key))
+                .keys()
+                .next_back()
+            {
+                Some(last) => {
+                    let last = last?;
+                    let seq: [u8; 4] = last
+                        .get(8..)
+                        .and_then(|seq| seq.try_into().ok())
+                        .ok_or(Error::InvalidSledKeyFormat)?;
+                    u32::from_be_bytes(seq) + 1
+                }
+                None => 0,
+            };
+            let mut tie_key = [0u8; 12];
+            tie_key[..8].copy_from_slice(&Self::key_from_u64(key));
+            tie_key[8..].copy_from_slice(&seq.to_be_bytes());
+            // a racing push may take the same seq, retry with the next
+            let inserted =
+                self.ties
+                    .compare_and_swap(tie_key, None as Option<&[u8]>, Some(value))?;
+            if inserted.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Remove the first value tied to `key`
+    fn pop_tie(&self, key: u64) -> Result<Option<sled::IVec>, Error> {
+        loop {
+            let Some(tie_key) = self.ties.scan_prefix(Self::key_from_u64(key)).keys().next() else {
+                return Ok(None);
+            };
+            if let Some(data) = self.ties.remove(tie_key?)? {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    /// Remove the last value tied to `key`
+    fn pop_last_tie(&self, key: u64) -> Result<Option<sled::IVec>, Error> {
+        loop {
+            let Some(tie_key) = self
+                .ties
+                .scan_prefix(Self::key_from_u64(key))
+                .keys()
+                .next_back()
+            else {
+                return Ok(None);
+            };
+            if let Some(data) = self.ties.remove(tie_key?)? {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn has_ties(&self, key: u64) -> Result<bool, Error> {
+        Ok(self
+            .ties
+            .scan_prefix(Self::key_from_u64(key))
+            .keys()
+            .next()
+            .transpose()?
+            .is_some())
+    }
+
+    /// First data key at or after `from`
+    fn next_key_from(&self, from: u64) -> Result<Option<u64>, Error> {
+        match self.db.range(Self::key_from_u64(from)..).keys().next() {
+            Some(key) => Ok(Some(Self::u64_from_key(&key?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// First key at or after `from` holding an item or only ties, the
+    /// item under a key may be gone while its ties are left
+    fn next_stored_key_from(&self, from: u64) -> Result<Option<u64>, Error> {
+        let tied = match self.ties.range(Self::key_from_u64(from)..).keys().next() {
+            Some(tie_key) => {
+                let tie_key = tie_key?;
+                Some(Self::u64_from_key(
+                    tie_key.get(..8).ok_or(Error::InvalidSledKeyFormat)?,
+                )?)
+            }
+            None => None,
+        };
+        Ok(match (self.next_key_from(from)?, tied) {
+            (Some(key), Some(tied)) => Some(key.min(tied)),
+            (key, tied) => key.or(tied),
+        })
+    }
+
+    /// Make room for a stored value of `size` bytes according to the
+    /// overflow policy, returns false if the value must be dropped instead.
+    /// Removing the value releases its stored length again.
+    fn reserve_bytes(&self, size: usize) -> Result<bool, Error> {
+        self.reserve_bytes_with(size, || Ok(self.skip(1)? > 0))
+    }
+
+    /// `reserve_bytes` removing the oldest item with `evict_oldest`, which
+    /// returns false once there is nothing left to remove
+    fn reserve_bytes_with(
+        &self,
+        size: usize,
+        mut evict_oldest: impl FnMut() -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(true);
+        };
+
+        let size = size as u64;
+        if size > max_bytes && self.overflow_policy != OverflowPolicy::DropNewest {
+            // it can never fit, don't evict anything for it
+            return Err(Error::BufferFull);
+        }
+        while self.total_bytes() + size > max_bytes {
+            match self.overflow_policy {
+                OverflowPolicy::Reject => return Err(Error::BufferFull),
+                OverflowPolicy::DropNewest => {
+                    event!(debug; "Sled buffer is full, dropping the pushed item.");
+                    return Ok(false);
+                }
+                OverflowPolicy::DropOldest => {
+                    // one small item may not free enough for a large one
+                    if !evict_oldest()? {
+                        return Err(Error::BufferFull);
+                    }
+                }
+            }
+        }
+        self.add_bytes(size as i64)?;
+        Ok(true)
+    }
+
+    fn release_bytes(&self, size: usize) -> Result<(), Error> {
+        self.add_bytes(-(size as i64))
+    }
+
+    fn add_bytes(&self, delta: i64) -> Result<(), Error> {
+        if self.max_bytes.is_none() {
+            return Ok(());
+        }
+        let previous = self
+            .total_bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                Some(total.saturating_add_signed(delta))
+            })
+            .unwrap_or_default();
+        let total = previous.saturating_add_signed(delta);
+        self.meta.insert(META_BYTES, &total.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Decode a shifted item, `None` if it failed and is skipped by policy
+    fn decode_shifted<T: ExternalBufferSerde>(
+        &self,
+        key: u64,
+        data: &[u8],
+    ) -> Result<Option<T>, Error> {
+        match Self::decode_item(key, data) {
+            Ok(item) => Ok(Some(item)),
+            Err(e) if self.decode_error_policy == DecodeErrorPolicy::Skip => {
+                event!(warn, item_key = key; "Skip sled item that failed to decode: {}", e);
+                self.skipped_decodes.fetch_add(1, Ordering::AcqRel);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deserialize the item stored under `key`, naming the key on failure
+    fn decode_item<T: ExternalBufferSerde>(key: u64, data: &[u8]) -> Result<T, Error> {
+        T::from_external_buffer(data).map_err(|e| match e {
+            #[cfg(feature = "bincode")]
+            Error::DecodeError(source) => Error::ItemDecode { key, source },
+            e => {
+                let _ = key;
+                e
+            }
+        })
+    }
+
+    fn key_from_u64(value: u64) -> [u8; 8] {
+        value.to_be_bytes()
+    }
+
+    fn u64_from_key(key: &[u8]) -> Result<u64, Error> {
+        let bytes: [u8; 8] = key.try_into().map_err(|_| Error::InvalidSledKeyFormat)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+/// Reads items of an `ExternalBufferSled` in order without removing them,
+/// keeping its own position apart from the buffer head.
+pub struct SledCursor<'a, T> {
+    buffer: &'a ExternalBufferSled,
+    position: u64,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T: ExternalBufferSerde> SledCursor<'_, T> {
+    /// Read the item at the position and move past it, `None` once the
+    /// tail is reached. Items shifted meanwhile are skipped.
+    pub async fn next(&mut self) -> Result<Option<T>, Error> {
+        loop {
+            // Acquire, same as the shifts, whatever is below the head
+            // loaded is gone already
+            let head = self.buffer.head_counter.load(Ordering::Acquire);
+            let tail = self.buffer.tail_counter.load(Ordering::Acquire);
+            // items before head are shifted already
+            self.position = self.position.max(head);
+            if self.position >= tail {
+                return Ok(None);
+            }
+
+            let key = self.position;
+            self.position += 1;
+            if let Some(item) = self.buffer.peek_at(key)? {
+                return Ok(Some(item));
+            }
+            // jump over a gap left by caller's keys
+            if let Some(next) = self.buffer.next_key_from(self.position)? {
+                self.position = next;
+            }
+        }
+    }
+
+    /// Key of the next item to read, pass it to
+    /// `ExternalBufferSled::cursor_from` to resume from here
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<T: ExternalBufferSerde + Send + 'static> SyncExternalBuffer<T> for ExternalBufferSled {
+    fn push_sync(&self, item: T) -> Result<(), Error> {
+        self.push_serialized(self.serialize(item)?)
+    }
+
+    fn shift_sync(&self) -> Result<Option<T>, Error> {
+        self.shift_item()
+    }
+
+    fn len_sync(&self) -> usize {
+        ExternalBufferSled::len(self)
+    }
+}
+
+impl ExternalBufferSled {
+    /// Store a serialized item with room reserved for it under the next key
+    fn append_value(&self, serialized: Vec<u8>) -> Result<(), Error> {
+        let _appending = self.appending.read().unwrap_or_else(|e| e.into_inner());
+        // a read-modify-write, concurrent pushes never get the same key
+        let key = self.tail_counter.fetch_add(1, Ordering::AcqRel);
+        let key_bytes = Self::key_from_u64(key);
+
+        let value = self.store_value(key, serialized)?;
+        // counted before a shift can find it, so the count never drops
+        // below zero
+        self.item_count.fetch_add(1, Ordering::AcqRel);
+        match &self.write_batch {
+            // kept pending by a failed apply, so counted either way
+            Some(write_batch) => write_batch.add(key_bytes, &value, key + 1)?,
+            None => {
+                if let Err(e) = self.db.insert(key_bytes, &value[..]) {
+                    self.item_count.fetch_sub(1, Ordering::AcqRel);
+                    return Err(e.into());
+                }
+                self.meta.insert(META_TAIL, &(key + 1).to_be_bytes())?;
+            }
+        }
+        self.recycle(value);
+        Ok(())
+    }
+
+    /// `push_sync` reporting what the overflow policy did, the items
+    /// evicted to make room are decoded and handed back
+    fn push_reporting<T: ExternalBufferSerde>(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        let serialized = self.serialize(item)?;
+        let mut evicted = Vec::new();
+        let reserved = self.reserve_bytes_with(self.stored_size(serialized.len()), || {
+            let head = self.head_counter.load(Ordering::Acquire);
+            let oldest = match self.next_key_from(head)? {
+                Some(key) => self.peek_at::<T>(key)?,
+                None => None,
+            };
+            let removed = self.skip(1)? > 0;
+            evicted.extend(oldest.filter(|_| removed));
+            Ok(removed)
+        })?;
+        if !reserved {
+            self.recycle(serialized);
+            return Ok(PushOutcome::DroppedNewest);
+        }
+        self.append_value(serialized)?;
+        Ok(match evicted.is_empty() {
+            true => PushOutcome::Stored,
+            false => PushOutcome::EvictedOldest(evicted),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ExternalBufferSerde + Send + 'static> ExternalBuffer<T> for ExternalBufferSled {
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_sync(item)
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_item()
+    }
+
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        self.push_reporting(item)
+    }
+
+    fn prepare_push(&self, item: T) -> Result<PreparedPush<T>, Error> {
+        self.serialize(item).map(PreparedPush::Serialized)
+    }
+
+    async fn push_prepared(&self, prepared: PreparedPush<T>) -> Result<(), Error> {
+        match prepared {
+            PreparedPush::Item(item) => self.push_sync(item),
+            PreparedPush::Serialized(serialized) => self.push_serialized(serialized),
+        }
+    }
+
+    async fn shift_keyed(&self) -> Result<Option<KeyedItem<T>>, Error> {
+        Ok(self
+            .shift_keyed_item()?
+            .map(|(key, item)| (Some(key), item)))
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        Some(self)
+    }
+
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        Ok(self.push_front(item))
+    }
+
+    fn shift_now_keyed(&self) -> Option<Result<Option<KeyedItem<T>>, Error>> {
+        Some(
+            self.shift_keyed_item()
+                .map(|item| item.map(|(key, item)| (Some(key), item))),
+        )
+    }
+
+    fn len(&self) -> usize {
+        ExternalBufferSled::len(self)
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Persistent
+    }
+
+    #[cfg(feature = "stats")]
+    fn latency_stats(&self) -> Option<crate::LatencyStats> {
+        self.latency.as_ref().map(|latency| latency.snapshot())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.apply_batched_writes()?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    async fn remove_if<F>(&self, pred: F) -> Result<usize, Error>
+    where
+        F: Fn(&T) -> bool + Send + 'async_trait,
+    {
+        self.remove_items(pred)
+    }
+}
+
+/// Fill `buf` from `reader`, false if the reader ended before its first
+/// byte. Ending anywhere after that is an error.
+fn read_or_end(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(make_custom_error(std::io::Error::from(
+                    std::io::ErrorKind::UnexpectedEof,
+                )))
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(make_custom_error(e)),
+        }
+    }
+    Ok(true)
+}
+
+fn write_u64s(writer: &mut impl Write, values: &[u64]) -> Result<(), Error> {
+    for value in values {
+        writer
+            .write_all(&value.to_be_bytes())
+            .map_err(make_custom_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::{Decode, Encode};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct TestItem {
+        id: u32,
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn test_push_and_shift() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+
+        let item1 = TestItem {
+            id: 1,
+            name: "first".to_string(),
+        };
+        let item2 = TestItem {
+            id: 2,
+            name: "second".to_string(),
+        };
+
+        // Push items
+        buffer.push(item1.clone()).await.unwrap();
+        buffer.push(item2.clone()).await.unwrap();
+
+        // Shift items (should come out in FIFO order)
+        let shifted1 = buffer.shift().await.unwrap();
+        assert_eq!(shifted1, Some(item1));
+
+        let shifted2 = buffer.shift().await.unwrap();
+        assert_eq!(shifted2, Some(item2));
+
+        // Buffer should be empty now
+        let shifted3: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(shifted3, None);
+    }
+
+    #[tokio::test]
+    async fn test_empty_buffer() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("empty_db")).unwrap();
+
+        // Empty buffer should return None
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("persistent_db");
+
+        let item = TestItem {
+            id: 42,
+            name: "persistent".to_string(),
+        };
+
+        // Create buffer, push item, and drop it
+        {
+            let buffer = ExternalBufferSled::new(&db_path).unwrap();
+            buffer.push(item.clone()).await.unwrap();
+        }
+
+        // Create new buffer with same path and verify item is still there
+        {
+            let buffer = reopen(&db_path);
+            let retrieved = buffer.shift().await.unwrap();
+            assert_eq!(retrieved, Some(item));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multiple_pushes_and_shifts() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("multi_db")).unwrap();
+
+        let items: Vec<TestItem> = (0..10)
+            .map(|i| TestItem {
+                id: i,
+                name: format!("item_{}", i),
+            })
+            .collect();
+
+        // Push all items
+        for item in &items {
+            buffer.push(item.clone()).await.unwrap();
+        }
+
+        // Shift all items and verify order
+        for expected_item in &items {
+            let shifted = buffer.shift().await.unwrap();
+            assert_eq!(shifted, Some(expected_item.clone()));
+        }
+
+        // Buffer should be empty
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_push_and_shift() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("interleaved_db")).unwrap();
+
+        let item1 = TestItem {
+            id: 1,
+            name: "first".to_string(),
+        };
+        let item2 = TestItem {
+            id: 2,
+            name: "second".to_string(),
+        };
+        let item3 = TestItem {
+            id: 3,
+            name: "third".to_string(),
+        };
+
+        // Push one, shift one
+        buffer.push(item1.clone()).await.unwrap();
+        let shifted1 = buffer.shift().await.unwrap();
+        assert_eq!(shifted1, Some(item1));
+
+        // Push two, shift two
+        buffer.push(item2.clone()).await.unwrap();
+        buffer.push(item3.clone()).await.unwrap();
+
+        let shifted2 = buffer.shift().await.unwrap();
+        assert_eq!(shifted2, Some(item2));
+
+        let shifted3 = buffer.shift().await.unwrap();
+        assert_eq!(shifted3, Some(item3));
+
+        // Should be empty
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    // sled releases its file lock from a background thread, so a reopen right
+    // after drop may briefly fail
+    fn retry_open<R, E: std::fmt::Debug>(open: impl Fn() -> Result<R, E>) -> R {
+        for _ in 0..50 {
+            if let Ok(opened) = open() {
+                return opened;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        open().unwrap()
+    }
+
+    async fn retry_open_async(path: &std::path::Path) -> ExternalBufferSled {
+        for _ in 0..50 {
+            if let Ok(buffer) = ExternalBufferSled::open_async(path).await {
+                return buffer;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("failed to reopen {:?}", path);
+    }
+
+    fn reopen(path: &std::path::Path) -> ExternalBufferSled {
+        retry_open(|| ExternalBufferSled::new(path))
+    }
+
+    #[tokio::test]
+    async fn test_flush_and_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("flush_db");
+
+        let items: Vec<TestItem> = (0..5)
+            .map(|i| TestItem {
+                id: i,
+                name: format!("item_{}", i),
+            })
+            .collect();
+
+        // Push items and flush them before the buffer goes away
+        {
+            let buffer = ExternalBufferSled::new(&db_path).unwrap();
+            for item in &items {
+                buffer.push(item.clone()).await.unwrap();
+            }
+            ExternalBuffer::<TestItem>::flush(&buffer).await.unwrap();
+        }
+
+        // Reopen and verify every item survived
+        let buffer = reopen(&db_path);
+        for expected_item in &items {
+            let shifted = buffer.shift().await.unwrap();
+            assert_eq!(shifted, Some(expected_item.clone()));
+        }
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn test_skip() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("skip_db")).unwrap();
+
+        for i in 0..10 {
+            buffer
+                .push(TestItem {
+                    id: i,
+                    name: format!("item_{}", i),
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(buffer.skip(4).unwrap(), 4);
+        let shifted: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(shifted.map(|item| item.id), Some(4));
+
+        // Only 5 items are left, so skipping more stops at the end
+        assert_eq!(buffer.skip(100).unwrap(), 5);
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[cfg(feature = "sled-compression")]
+    #[tokio::test]
+    async fn test_compressed_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer =
+            ExternalBufferSled::new_compressed(temp_dir.path().join("compressed_db"), 5).unwrap();
+
+        let items: Vec<TestItem> = (0..10)
+            .map(|i| TestItem {
+                id: i,
+                name: "a".repeat(10000),
+            })
+            .collect();
+
+        for item in &items {
+            buffer.push(item.clone()).await.unwrap();
+        }
+
+        for expected_item in &items {
+            let shifted = buffer.shift().await.unwrap();
+            assert_eq!(shifted, Some(expected_item.clone()));
+        }
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn test_drain_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("drain_db")).unwrap();
+
+        let items: Vec<TestItem> = (0..5)
+            .map(|i| TestItem {
+                id: i,
+                name: format!("item_{}", i),
+            })
+            .collect();
+        for item in &items {
+            buffer.push(item.clone()).await.unwrap();
+        }
+
+        let drained: Vec<TestItem> = buffer.drain_all().unwrap();
+        assert_eq!(drained, items);
+
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn test_open_and_repair() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("repair_db");
+
+        let items: Vec<TestItem> = (0..3)
+            .map(|i| TestItem {
+                id: i,
+                name: format!("item_{}", i),
+            })
+            .collect();
+        {
+            let buffer = ExternalBufferSled::new(&db_path).unwrap();
+            for item in &items {
+                buffer.push(item.clone()).await.unwrap();
+            }
+        }
+
+        // Simulate a crash that left meta behind the data keys
+        {
+            let db = retry_open(|| sled::open(&db_path));
+            let meta = db.open_tree(META_TREE).unwrap();
+            meta.insert(META_HEAD, &7u64.to_be_bytes()).unwrap();
+            meta.insert(META_TAIL, &1u64.to_be_bytes()).unwrap();
+            db.flush().unwrap();
+        }
+
+        let (buffer, report) = retry_open(|| ExternalBufferSled::open_and_repair(&db_path));
+        assert_eq!(
+            report,
+            RepairReport {
+                meta_head: Some(7),
+                meta_tail: Some(1),
+                head: 0,
+                tail: 3,
+                repaired: true,
+            }
+        );
+        assert_eq!(
+            ExternalBufferSled::load_meta(&buffer.meta, META_TAIL).unwrap(),
+            Some(3)
+        );
+
+        let drained: Vec<TestItem> = buffer.drain_all().unwrap();
+        assert_eq!(drained, items);
+        drop(buffer);
+
+        // A consistent buffer needs no repair
+        let (_, report) = retry_open(|| ExternalBufferSled::open_and_repair(&db_path));
+        assert!(!report.repaired);
+    }
+
+    #[tokio::test]
+    async fn test_shift_partition() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("partition_db");
+
+        {
+            let buffer = ExternalBufferSled::new(&db_path).unwrap();
+            for i in 0..6 {
+                let item = TestItem {
+                    id: i,
+                    name: format!("item_{}", i),
+                };
+                buffer.push_partition(i % 2, item).unwrap();
+            }
+
+            let first: Option<TestItem> = buffer.shift_partition(1).unwrap();
+            assert_eq!(first.map(|item| item.id), Some(1));
+
+            // The main FIFO is not affected by partitions
+            let result: Option<TestItem> = buffer.shift().await.unwrap();
+            assert_eq!(result, None);
+        }
+
+        // Partition counters are restored on reopen
+        let buffer = reopen(&db_path);
+        for expected in [3, 5] {
+            let item: Option<TestItem> = buffer.shift_partition(1).unwrap();
+            assert_eq!(item.map(|item| item.id), Some(expected));
+        }
+        let item: Option<TestItem> = buffer.shift_partition(1).unwrap();
+        assert_eq!(item, None);
+
+        for expected in [0, 2, 4] {
+            let item: Option<TestItem> = buffer.shift_partition(0).unwrap();
+            assert_eq!(item.map(|item| item.id), Some(expected));
+        }
+        let item: Option<TestItem> = buffer.shift_partition(7).unwrap();
+        assert_eq!(item, None);
+    }
+
+    #[test]
+    fn test_durability() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("durability_db")).unwrap();
+        assert_eq!(
+            ExternalBuffer::<TestItem>::durability(&buffer),
+            Durability::Persistent
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cursor_does_not_consume() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("cursor_db")).unwrap();
+
+        let items: Vec<TestItem> = (0..5)
+            .map(|i| TestItem {
+                id: i,
+                name: format!("item_{}", i),
+            })
+            .collect();
+        for item in &items {
+            buffer.push(item.clone()).await.unwrap();
+        }
+
+        let mut cursor = buffer.cursor::<TestItem>();
+        let mut seen = Vec::new();
+        while let Some(item) = cursor.next().await.unwrap() {
+            seen.push(item);
+        }
+        assert_eq!(seen, items);
+
+        // Resume from a saved position
+        let mut resumed = buffer.cursor_from::<TestItem>(3);
+        assert_eq!(resumed.next().await.unwrap(), Some(items[3].clone()));
+        assert_eq!(resumed.position(), 4);
+
+        // Every item is still there
+        for expected_item in &items {
+            let shifted = buffer.shift().await.unwrap();
+            assert_eq!(shifted, Some(expected_item.clone()));
+        }
+        let result: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[cfg(feature = "large-values")]
+    #[tokio::test]
+    async fn test_chunked_large_value() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer =
+            ExternalBufferSled::new_chunked(temp_dir.path().join("chunked_db"), 1024 * 1024)
+                .unwrap();
+
+        let large: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        buffer.push(vec![1u8, 2, 3]).await.unwrap();
+        buffer.push(large.clone()).await.unwrap();
+
+        let mut cursor = buffer.cursor::<Vec<u8>>();
+        cursor.next().await.unwrap();
+        assert_eq!(cursor.next().await.unwrap(), Some(large.clone()));
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(vec![1u8, 2, 3]));
+        assert_eq!(buffer.shift().await.unwrap(), Some(large));
+        assert_eq!(buffer.chunks.as_ref().unwrap().tree.len(), 0);
+    }
+
+    #[cfg(feature = "large-values")]
+    #[tokio::test]
+    async fn test_chunked_duplicate_key_keeps_stored_item() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer =
+            ExternalBufferSled::new_chunked(temp_dir.path().join("chunked_db"), 16).unwrap();
+
+        let first = vec![1u8; 100];
+        buffer.push_with_key(7, first.clone()).unwrap();
+        assert!(matches!(
+            buffer.push_with_key(7, vec![2u8; 100]),
+            Err(Error::DuplicateKey(7))
+        ));
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(first));
+        assert_eq!(buffer.chunks.as_ref().unwrap().tree.len(), 0);
+    }
+
+    #[cfg(feature = "large-values")]
+    #[tokio::test]
+    async fn test_chunked_ties_keep_their_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new_chunked(temp_dir.path().join("chunked_db"), 16)
+            .unwrap()
+            .collision_policy(CollisionPolicy::Suffix);
+
+        buffer.push_with_key(3, vec![1u8; 100]).unwrap();
+        buffer.push_with_key(3, vec![2u8; 100]).unwrap();
+        buffer.push_with_key(5, vec![3u8; 100]).unwrap();
+        buffer.push_with_key(5, vec![4u8; 100]).unwrap();
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(vec![1u8; 100]));
+        assert_eq!(buffer.shift().await.unwrap(), Some(vec![2u8; 100]));
+
+        // seeking past a tie removes its chunks as well
+        buffer.seek_to(5).unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.chunks.as_ref().unwrap().tree.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_decode_error_reports_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        for id in 0..3 {
+            buffer
+                .push(TestItem {
+                    id,
+                    name: format!("item{}", id),
+                })
+                .await
+                .unwrap();
+        }
+
+        // A string length far past the end of the value can not be decoded
+        buffer
+            .db
+            .insert(ExternalBufferSled::key_from_u64(1), vec![1, 251, 255, 255])
+            .unwrap();
+
+        let first: Option<TestItem> = buffer.shift().await.unwrap();
+        assert_eq!(first.unwrap().id, 0);
+        let second: Result<Option<TestItem>, Error> = buffer.shift().await;
+        match second {
+            Err(Error::ItemDecode { key, .. }) => assert_eq!(key, 1),
+            other => panic!("Expected ItemDecode, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifo_order_across_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+
+        {
+            let buffer = ExternalBufferSled::new_lifo(&path).unwrap();
+            for i in [1u32, 2, 3] {
+                buffer.push(i).await.unwrap();
+            }
+            let item: Option<u32> = buffer.shift().await.unwrap();
+            assert_eq!(item, Some(3));
+            ExternalBuffer::<u32>::flush(&buffer).await.unwrap();
+        }
+
+        let buffer = retry_open(|| ExternalBufferSled::new_lifo(&path));
+        buffer.push(4u32).await.unwrap();
+        let mut items: Vec<u32> = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            items.push(item);
+        }
+        assert_eq!(items, vec![4u32, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_db_exposes_raw_items() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        let item = TestItem {
+            id: 7,
+            name: "raw".to_string(),
+        };
+        buffer.push(item.clone()).await.unwrap();
+
+        let raw = buffer.db().get(0u64.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(raw.as_ref(), &item.into_external_buffer().unwrap()[..]);
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes_rejects_when_full() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+
+        {
+            // 10 bytes and a length prefix make 11 bytes per item
+            let buffer = ExternalBufferSled::with_max_bytes(&path, 50).unwrap();
+            for _ in 0..4 {
+                buffer.push(vec![7u8; 10]).await.unwrap();
+            }
+            assert_eq!(buffer.total_bytes(), 44);
+            assert!(matches!(
+                buffer.push(vec![7u8; 10]).await,
+                Err(Error::BufferFull)
+            ));
+
+            let item: Option<Vec<u8>> = buffer.shift().await.unwrap();
+            assert!(item.is_some());
+            assert_eq!(buffer.total_bytes(), 33);
+            ExternalBuffer::<Vec<u8>>::flush(&buffer).await.unwrap();
+        }
+
+        let buffer = retry_open(|| ExternalBufferSled::with_max_bytes(&path, 50));
+        assert_eq!(buffer.total_bytes(), 33);
+        buffer.push(vec![7u8; 10]).await.unwrap();
+        assert!(matches!(
+            buffer.push(vec![7u8; 10]).await,
+            Err(Error::BufferFull)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes_drop_oldest() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::with_max_bytes(temp_dir.path().join("test_db"), 30)
+            .unwrap()
+            .overflow_policy(OverflowPolicy::DropOldest);
+        for i in 0..4u8 {
+            buffer.push(vec![i; 10]).await.unwrap();
+        }
+
+        let items: Vec<Vec<u8>> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![vec![2u8; 10], vec![3u8; 10]]);
+        assert_eq!(buffer.total_bytes(), 0);
+    }
+
+    #[cfg(feature = "stats")]
+    #[tokio::test]
+    async fn test_max_bytes_counts_stored_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        {
+            let buffer = ExternalBufferSled::with_max_bytes(&path, 50)
+                .unwrap()
+                .with_latency_stats();
+            // 11 serialized bytes behind an 8 byte push time stamp
+            for _ in 0..2 {
+                buffer.push(vec![7u8; 10]).await.unwrap();
+            }
+            assert_eq!(buffer.total_bytes(), 38);
+            assert!(matches!(
+                buffer.push(vec![7u8; 10]).await,
+                Err(Error::BufferFull)
+            ));
+            let _: Option<Vec<u8>> = buffer.shift().await.unwrap();
+            assert_eq!(buffer.total_bytes(), 19);
+            buffer.meta.remove(META_BYTES).unwrap();
+            ExternalBuffer::<Vec<u8>>::flush(&buffer).await.unwrap();
+        }
+
+        // the scan of a buffer first opened with a limit agrees
+        let buffer = retry_open(|| ExternalBufferSled::with_max_bytes(&path, 50));
+        assert_eq!(buffer.total_bytes(), 19);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_remaining() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer =
+            ExternalBufferSled::with_max_bytes(temp_dir.path().join("test_db"), 30).unwrap();
+        assert_eq!(buffer.bytes_remaining(), Some(30));
+        buffer.push(vec![0u8; 10]).await.unwrap();
+        assert_eq!(buffer.bytes_remaining(), Some(19));
+        // no item limit
+        let space = ExternalBuffer::<Vec<u8>>::space_remaining(&buffer).await;
+        assert_eq!(space.unwrap(), None);
+
+        let unlimited = ExternalBufferSled::new(temp_dir.path().join("unlimited")).unwrap();
+        assert_eq!(unlimited.bytes_remaining(), None);
+    }
+
+    #[tokio::test]
+    async fn test_push_outcome_reports_drops() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::with_max_bytes(temp_dir.path().join("test_db"), 30)
+            .unwrap()
+            .overflow_policy(OverflowPolicy::DropNewest);
+        for i in 0..2u8 {
+            let outcome = buffer.push_outcome(vec![i; 10]).await.unwrap();
+            assert_eq!(outcome, PushOutcome::Stored);
+        }
+        let outcome = buffer.push_outcome(vec![2u8; 10]).await.unwrap();
+        assert_eq!(outcome, PushOutcome::DroppedNewest);
+        assert_eq!(buffer.len(), 2);
+
+        let buffer = ExternalBufferSled::with_max_bytes(temp_dir.path().join("evict_db"), 30)
+            .unwrap()
+            .overflow_policy(OverflowPolicy::DropOldest);
+        for i in 0..2u8 {
+            buffer.push(vec![i; 10]).await.unwrap();
+        }
+        let outcome = buffer.push_outcome(vec![2u8; 10]).await.unwrap();
+        assert_eq!(outcome, PushOutcome::EvictedOldest(vec![vec![0u8; 10]]));
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes_drop_oldest_makes_room_for_large_item() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::with_max_bytes(temp_dir.path().join("test_db"), 50)
+            .unwrap()
+            .overflow_policy(OverflowPolicy::DropOldest);
+        for i in 0..4u8 {
+            buffer.push(vec![i; 10]).await.unwrap();
+        }
+        assert_eq!(buffer.total_bytes(), 44);
+
+        // 31 bytes only fit once three of the 11 byte items are gone
+        buffer.push(vec![9u8; 30]).await.unwrap();
+        assert_eq!(buffer.total_bytes(), 42);
+
+        // an item larger than the whole buffer evicts nothing
+        assert!(matches!(
+            buffer.push(vec![0u8; 60]).await,
+            Err(Error::BufferFull)
+        ));
+
+        let items: Vec<Vec<u8>> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![vec![3u8; 10], vec![9u8; 30]]);
+    }
+
+    #[test]
+    fn test_open_async_does_not_stall_runtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        {
+            let buffer = ExternalBufferSled::new(&path).unwrap();
+            let mut batch = sled::Batch::default();
+            for key in 0..20_000u64 {
+                batch.insert(&key.to_be_bytes(), &[0u8; 16]);
+            }
+            buffer.db().apply_batch(batch).unwrap();
+            buffer.db().flush().unwrap();
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let ticked = async {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                std::time::Instant::now()
+            };
+            let opened = async {
+                let buffer = retry_open_async(&path).await;
+                (std::time::Instant::now(), buffer.len())
+            };
+            let (ticked_at, (opened_at, len)) = tokio::join!(ticked, opened);
+
+            assert_eq!(len, 20_000);
+            // the timer fired while the scan was still running
+            assert!(ticked_at < opened_at);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_open_skips_non_data_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        {
+            let buffer = ExternalBufferSled::new(&path).unwrap();
+            for i in 0..3u32 {
+                buffer.push(i).await.unwrap();
+            }
+            buffer.db().insert(b"bad", b"row").unwrap();
+            buffer.db().flush().unwrap();
+        }
+
+        {
+            let buffer = retry_open(|| ExternalBufferSled::new(&path));
+            assert_eq!(buffer.len(), 3);
+        }
+
+        // not an unreadable row, nothing is reported as skipped
+        let (buffer, skipped) =
+            retry_open(|| ExternalBufferSled::open_with_mode(&path, OpenMode::Lenient));
+        assert_eq!(skipped, 0);
+        assert_eq!(buffer.len(), 3);
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_lenient_open_skips_unreadable_rows() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        {
+            let buffer = ExternalBufferSled::new(&path).unwrap();
+            for i in 0..3u32 {
+                buffer.push(i).await.unwrap();
+            }
+            buffer.db().flush().unwrap();
+        }
+
+        // sled checks what it reads, a corrupt row fails the scan, here
+        // the row after the first item
+        let db = retry_open(|| ExternalBufferSled::open_db(&path, sled::Config::new()));
+        let scan = || {
+            let mut keys: Vec<_> = db.iter().keys().collect();
+            keys.insert(1, Err(sled::Error::Unsupported("corrupt row".to_string())));
+            keys.into_iter()
+        };
+        assert!(matches!(
+            ExternalBufferSled::from_db_scanning(db.clone(), scan(), OpenMode::Strict),
+            Err(Error::SledError(sled::Error::Unsupported(_)))
+        ));
+
+        let (buffer, skipped) =
+            ExternalBufferSled::from_db_scanning(db.clone(), scan(), OpenMode::Lenient).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!((buffer.head(), buffer.tail(), buffer.len()), (0, 3, 3));
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_unit_items_store_empty_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        for _ in 0..1000 {
+            buffer.push(()).await.unwrap();
+        }
+        assert_eq!(buffer.len(), 1000);
+
+        // presence-only keys
+        for value in buffer.db().iter().values() {
+            assert!(value.unwrap().is_empty());
+        }
+
+        let items: Vec<()> = buffer.drain_all().unwrap();
+        assert_eq!(items.len(), 1000);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_open_locked_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        let _buffer = ExternalBufferSled::new(&path).unwrap();
+
+        match ExternalBufferSled::new(&path) {
+            Err(Error::DatabaseLocked { path: locked }) => assert_eq!(locked, path),
+            other => panic!("Expected DatabaseLocked, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_items_failing_to_decode() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .decode_error_policy(DecodeErrorPolicy::Skip);
+        for id in 0..4 {
+            buffer
+                .push(TestItem {
+                    id,
+                    name: format!("item{}", id),
+                })
+                .await
+                .unwrap();
+        }
+        buffer
+            .db
+            .insert(ExternalBufferSled::key_from_u64(2), vec![1, 251, 255, 255])
+            .unwrap();
+
+        let items: Vec<TestItem> = buffer.drain_all().unwrap();
+        let ids: Vec<u32> = items.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![0, 1, 3]);
+        assert_eq!(buffer.skipped_decodes(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_with_key_shifts_in_key_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+
+        for (key, item) in [
+            (1_700_000_300u64, 3u32),
+            (1_700_000_100, 1),
+            (1_700_000_200, 2),
+        ] {
+            buffer.push_with_key(key, item).unwrap();
+        }
+        assert!(matches!(
+            buffer.push_with_key(1_700_000_100, 9u32),
+            Err(Error::DuplicateKey(1_700_000_100))
+        ));
+        assert_eq!(buffer.len(), 3);
+
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reset_to_head_replays_items() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .consume(false);
+
+        for i in 0..5u32 {
+            buffer.push(i).await.unwrap();
+        }
+        let first: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(first, vec![0, 1, 2, 3, 4]);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.db().len(), 5);
+
+        buffer.reset_to_head().unwrap();
+        assert_eq!(buffer.len(), 5);
+        let second: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn test_concurrent_shifts_deliver_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = Arc::new(ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap());
+        for i in 0..1000u32 {
+            futures::executor::block_on(buffer.push(i)).unwrap();
+        }
+
+        let shifters: Vec<_> = (0..2)
+            .map(|_| {
+                let buffer = buffer.clone();
+                std::thread::spawn(move || buffer.drain_all::<u32>().unwrap())
+            })
+            .collect();
+        let mut items: Vec<u32> = shifters
+            .into_iter()
+            .flat_map(|shifter| shifter.join().unwrap())
+            .collect();
+        items.sort();
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+
+        // head stopped at the tail, the next push is not skipped
+        let head = buffer.head_counter.load(Ordering::Acquire);
+        let tail = buffer.tail_counter.load(Ordering::Acquire);
+        assert!(head <= tail);
+        futures::executor::block_on(buffer.push(1000u32)).unwrap();
+        assert_eq!(buffer.drain_all::<u32>().unwrap(), vec![1000]);
+    }
+
+    #[test]
+    fn test_shift_racing_push_keeps_its_item() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = Arc::new(ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap());
+        let pusher = {
+            let buffer = buffer.clone();
+            std::thread::spawn(move || {
+                for i in 0..1000u32 {
+                    futures::executor::block_on(buffer.push(i)).unwrap();
+                }
+            })
+        };
+
+        // shifting right behind the pushes, a key taken but not stored yet
+        // must not be skipped
+        let mut items = Vec::new();
+        let started = std::time::Instant::now();
+        while items.len() < 1000 && started.elapsed() < std::time::Duration::from_secs(10) {
+            items.extend(buffer.drain_all::<u32>().unwrap());
+        }
+        pusher.join().unwrap();
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn test_skip_racing_push_keeps_its_item() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = Arc::new(ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap());
+        let pusher = {
+            let buffer = buffer.clone();
+            std::thread::spawn(move || {
+                for i in 0..1000u32 {
+                    futures::executor::block_on(buffer.push(i)).unwrap();
+                }
+            })
+        };
+
+        // skipping right behind the pushes, what `DropOldest` does, must
+        // skip every item instead of passing over keys not stored yet
+        let mut skipped = 0;
+        let started = std::time::Instant::now();
+        while skipped < 1000 && started.elapsed() < std::time::Duration::from_secs(10) {
+            skipped += buffer.skip(1000).unwrap();
+        }
+        pusher.join().unwrap();
+        assert_eq!(skipped, 1000);
+        assert_eq!(buffer.len(), 0);
+        assert!(buffer.db().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_batching_coalesces_pushes() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_write_batching(100, std::time::Duration::from_secs(60));
+
+        for i in 0..1000u32 {
+            buffer.push(i).await.unwrap();
+        }
+        let write_batch = buffer.write_batch.as_ref().unwrap();
+        assert_eq!(write_batch.applied.load(Ordering::Relaxed), 10);
+
+        // a few more than a batch holds are applied by the shift
+        for i in 1000..1005u32 {
+            buffer.push(i).await.unwrap();
+        }
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, (0..1005).collect::<Vec<_>>());
+        assert_eq!(write_batch.applied.load(Ordering::Relaxed), 11);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_pool_bounds_allocations() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_buffer_pool(4);
+
+        for i in 0..1000u32 {
+            buffer.push(format!("item {}", i)).await.unwrap();
+        }
+        let pool = buffer.pool.as_ref().unwrap();
+        assert!(pool.allocations() <= 4, "{}", pool.allocations());
+
+        let items: Vec<String> = buffer.drain_all().unwrap();
+        assert_eq!(items.len(), 1000);
+        assert_eq!(items[999], "item 999");
+    }
+
+    #[tokio::test]
+    async fn test_collision_suffix_keeps_both_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        {
+            let buffer = ExternalBufferSled::new(&path)
+                .unwrap()
+                .collision_policy(CollisionPolicy::Suffix);
+            buffer.push_with_key(20, "b".to_string()).unwrap();
+            buffer.push_with_key(10, "a1".to_string()).unwrap();
+            buffer.push_with_key(10, "a2".to_string()).unwrap();
+            buffer.push_with_key(10, "a3".to_string()).unwrap();
+            assert_eq!(buffer.len(), 4);
+
+            let item: Option<String> = buffer.shift().await.unwrap();
+            assert_eq!(item.as_deref(), Some("a1"));
+            ExternalBuffer::<String>::flush(&buffer).await.unwrap();
+        }
+
+        // tied items survive a restart
+        let buffer = retry_open(|| ExternalBufferSled::new(&path));
+        assert_eq!(buffer.len(), 3);
+        let items: Vec<String> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec!["a2", "a3", "b"]);
+
+        assert!(matches!(
+            buffer
+                .push_with_key(30, 1u32)
+                .and_then(|_| buffer.push_with_key(30, 2u32)),
+            Err(Error::DuplicateKey(30))
+        ));
+    }
+
+    fn tied_buffer(path: std::path::PathBuf) -> ExternalBufferSled {
+        let buffer = ExternalBufferSled::new(path)
+            .unwrap()
+            .collision_policy(CollisionPolicy::Suffix);
+        buffer.push_with_key(5, 1u32).unwrap();
+        buffer.push_with_key(5, 2u32).unwrap();
+        buffer.push_with_key(5, 3u32).unwrap();
+        buffer.push_with_key(6, 4u32).unwrap();
+        buffer
+    }
+
+    #[tokio::test]
+    async fn test_skip_tied_head() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = tied_buffer(temp_dir.path().join("test_db"));
+
+        assert_eq!(buffer.skip(2).unwrap(), 2);
+        assert_eq!(buffer.len(), 2);
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![3, 4]);
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_non_consuming_shift_reads_ties() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = tied_buffer(temp_dir.path().join("test_db")).consume(false);
+
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4]);
+        assert!(buffer.is_empty());
+
+        // replayed with the ties, which are counted again
+        buffer.reset_to_head().unwrap();
+        assert_eq!(buffer.len(), 4);
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_lifo_shifts_ties_newest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test_db");
+        drop(tied_buffer(path.clone()));
+
+        let buffer = retry_open(|| ExternalBufferSled::new_lifo(&path));
+        assert_eq!(buffer.len(), 4);
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![4, 3, 2, 1]);
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_of_ties_ends() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = tied_buffer(temp_dir.path().join("test_db")).consume(false);
+
+        let stream = crate::ExternalBufferedStream::new(futures::stream::empty::<u32>(), buffer);
+        let items: Vec<u32> = futures::StreamExt::collect(stream).await;
+        assert_eq!(items, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_peek_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        assert_eq!(buffer.peek_size().unwrap(), None);
+
+        let items = [vec![1u8; 3], vec![2u8; 200], Vec::new()];
+        for item in items.clone() {
+            buffer.push(item).await.unwrap();
+        }
+        for item in items {
+            let size = item.clone().into_external_buffer().unwrap().len();
+            assert_eq!(buffer.peek_size().unwrap(), Some(size));
+            let shifted: Option<Vec<u8>> = buffer.shift().await.unwrap();
+            assert_eq!(shifted, Some(item));
+        }
+        assert_eq!(buffer.peek_size().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_max_value_bytes_rejects_before_counting() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .max_value_bytes(16);
+        buffer.push(vec![1u8; 8]).await.unwrap();
+        let tail = buffer.tail_counter.load(Ordering::Acquire);
+
+        assert!(matches!(
+            buffer.push(vec![2u8; 100]).await,
+            Err(Error::ItemTooLarge {
+                size: 101,
+                limit: 16
+            })
+        ));
+        assert_eq!(buffer.tail_counter.load(Ordering::Acquire), tail);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_deduped_within_window() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_dedup(std::time::Duration::from_millis(100))
+            .unwrap();
+
+        assert!(buffer.push_deduped(b"order-1", 1u32).unwrap());
+        assert!(!buffer.push_deduped(b"order-1", 1u32).unwrap());
+        assert!(buffer.push_deduped(b"order-2", 2u32).unwrap());
+        assert_eq!(buffer.len(), 2);
+
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        assert!(buffer.push_deduped(b"order-1", 1u32).unwrap());
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 1]);
+        // the expired order-2 was purged by that push
+        assert_eq!(buffer.dedup.as_ref().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_remove_if() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .collision_policy(CollisionPolicy::Suffix);
+        for i in 0..10u32 {
+            buffer.push(i).await.unwrap();
+        }
+        buffer.push_with_key(9, 12u32).unwrap();
+        buffer.push_with_key(9, 13u32).unwrap();
+
+        let removed = buffer
+            .remove_if(|item: &u32| item.is_multiple_of(2))
+            .await
+            .unwrap();
+        assert_eq!(removed, 6);
+        assert_eq!(buffer.len(), 6);
+        // the removed head item is not left for a shift to trip over
+        assert_eq!(buffer.head_counter.load(Ordering::Acquire), 1);
+
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 3, 5, 7, 9, 13]);
+    }
+
+    #[tokio::test]
+    async fn test_push_front_is_shifted_next() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        for i in 0..3u32 {
+            buffer.push(i).await.unwrap();
+        }
+
+        let failed: Option<u32> = buffer.shift().await.unwrap();
+        assert_eq!(failed, Some(0));
+        buffer.push_front(failed.unwrap()).unwrap();
+        // the head is at key 0 now, so this one rebases the items
+        buffer.push_front(9u32).unwrap();
+        buffer.push(3u32).await.unwrap();
+        assert_eq!(buffer.len(), 5);
+
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![9, 0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_from() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("a")).unwrap();
+        let other = ExternalBufferSled::new(temp_dir.path().join("b")).unwrap();
+        for i in [1u32, 2] {
+            buffer.push(i).await.unwrap();
+        }
+        for i in [3u32, 4] {
+            other.push(i).await.unwrap();
+        }
+
+        // both used keys 0 and 1
+        let result = buffer.merge_from::<u32>(&other, MergeOrder::ByKey);
+        assert!(matches!(result, Err(Error::DuplicateKey(0))));
+        assert_eq!(other.len(), 2);
+
+        assert_eq!(
+            buffer
+                .merge_from::<u32>(&other, MergeOrder::Append)
+                .unwrap(),
+            2
+        );
+        assert!(other.is_empty());
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4]);
+
+        for (key, i) in [(10, 1u32), (30, 3)] {
+            buffer.push_with_key(key, i).unwrap();
+        }
+        for (key, i) in [(20, 2u32), (40, 4)] {
+            other.push_with_key(key, i).unwrap();
+        }
+        assert_eq!(
+            buffer.merge_from::<u32>(&other, MergeOrder::ByKey).unwrap(),
+            2
+        );
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_merge_keeps_items() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::with_max_bytes(temp_dir.path().join("a"), 30).unwrap();
+        let other = ExternalBufferSled::new(temp_dir.path().join("b")).unwrap();
+        for i in 0..3u8 {
+            other.push(vec![i; 10]).await.unwrap();
+        }
+
+        // room for two of them
+        let result = buffer.merge_from::<Vec<u8>>(&other, MergeOrder::Append);
+        assert!(matches!(result, Err(Error::BufferFull)));
+        assert_eq!(buffer.len(), 2);
+        let items: Vec<Vec<u8>> = other.drain_all().unwrap();
+        assert_eq!(items, vec![vec![2u8; 10]]);
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("a"))
+            .unwrap()
+            .collision_policy(CollisionPolicy::Suffix);
+        for i in 0..5u32 {
+            buffer.push(i).await.unwrap();
+        }
+        buffer.push_with_key(3, 30u32).unwrap();
+        assert_eq!(buffer.shift().await.unwrap(), Some(0u32));
+
+        let mut exported = Vec::new();
+        buffer.export(&mut exported).unwrap();
+        let imported =
+            ExternalBufferSled::import(temp_dir.path().join("b"), &exported[..]).unwrap();
+        assert_eq!((imported.head(), imported.tail()), (1, 5));
+        assert_eq!(imported.len(), buffer.len());
+
+        let expected: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(expected, vec![1, 2, 3, 30, 4]);
+        let items: Vec<u32> = imported.drain_all().unwrap();
+        assert_eq!(items, expected);
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_truncated_export() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("a")).unwrap();
+        buffer.push(7u32).await.unwrap();
+        let mut exported = Vec::new();
+        buffer.export(&mut exported).unwrap();
+
+        // cut inside the item header, inside the item, and an item claiming
+        // more bytes than the file holds
+        let mut huge = exported[..16].to_vec();
+        huge.extend_from_slice(&0u64.to_be_bytes());
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        let broken = [
+            exported[..20].to_vec(),
+            exported[..exported.len() - 1].to_vec(),
+            huge,
+        ];
+        for (i, broken) in broken.iter().enumerate() {
+            let path = temp_dir.path().join(format!("b{}", i));
+            assert!(ExternalBufferSled::import(path, &broken[..]).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seek_to_resumes_after_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        for i in 0..10u32 {
+            buffer.push(i).await.unwrap();
+        }
+        assert_eq!((buffer.head(), buffer.tail()), (0, 10));
+
+        buffer.seek_to(4).unwrap();
+        assert_eq!(buffer.head(), 5);
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.db().len(), 5);
+        let item: Option<u32> = buffer.shift().await.unwrap();
+        assert_eq!(item, Some(5));
+
+        // a checkpoint past the tail keeps later pushes after it
+        buffer.seek_to(19).unwrap();
+        assert!(buffer.is_empty());
+        buffer.push(20u32).await.unwrap();
+        assert_eq!(buffer.tail(), 21);
+        let items: Vec<u32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![20]);
     }
 }
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::trace::event;
+use crate::Error;
+
+use super::META_TAIL;
+
+/// Pushes collected into one `sled::Batch`, applied once `max_items` are
+/// waiting or by a background thread every `interval`, whichever is first.
+pub(super) struct WriteBatch {
+    db: sled::Db,
+    meta: sled::Tree,
+    max_items: usize,
+    pending: Mutex<Pending>,
+    // number of batches applied, only read by tests
+    pub(super) applied: AtomicU64,
+}
+
+#[derive(Default)]
+struct Pending {
+    batch: sled::Batch,
+    items: usize,
+    tail: u64,
+}
+
+impl WriteBatch {
+    pub(super) fn start(
+        db: sled::Db,
+        meta: sled::Tree,
+        max_items: usize,
+        interval: Duration,
+    ) -> Arc<Self> {
+        let write_batch = Arc::new(Self {
+            db,
+            meta,
+            max_items: max_items.max(1),
+            pending: Default::default(),
+            applied: AtomicU64::new(0),
+        });
+
+        // holds a weak reference only, so it ends with the buffer
+        let weak = Arc::downgrade(&write_batch);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(write_batch) = weak.upgrade() else {
+                break;
+            };
+            if let Err(e) = write_batch.apply() {
+                event!(error; "Failed to apply batched sled writes: {}", e);
+            }
+        });
+        write_batch
+    }
+
+    /// Add the value of a push, `tail` is the tail once it is applied
+    pub(super) fn add(&self, key: [u8; 8], value: &[u8], tail: u64) -> Result<(), Error> {
+        let mut pending = self.pending.lock()?;
+        pending.batch.insert(&key, value);
+        pending.items += 1;
+        pending.tail = pending.tail.max(tail);
+        if pending.items >= self.max_items {
+            self.apply_pending(&mut pending)?;
+        }
+        Ok(())
+    }
+
+    /// Apply whatever is waiting, e.g. before reading the data keys
+    pub(super) fn apply(&self) -> Result<(), Error> {
+        let mut pending = self.pending.lock()?;
+        self.apply_pending(&mut pending)
+    }
+
+    fn apply_pending(&self, pending: &mut Pending) -> Result<(), Error> {
+        if pending.items == 0 {
+            return Ok(());
+        }
+        // kept until applied, a failed apply is retried by the next one
+        self.db.apply_batch(pending.batch.clone())?;
+        self.meta.insert(META_TAIL, &pending.tail.to_be_bytes())?;
+        *pending = Pending::default();
+        self.applied.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for WriteBatch {
+    fn drop(&mut self) {
+        if let Err(e) = self.apply() {
+            event!(error; "Failed to apply batched sled writes on drop: {}", e);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::Error;
+
+// tree holding the chunks of large values, keyed by `chunk id || index`
+const CHUNK_TREE: &[u8] = b"__external_buffer_chunks";
+
+// first byte of every value stored in chunked mode
+const TAG_INLINE: u8 = 0;
+const TAG_CHUNKED: u8 = 1;
+// tag, chunk count and chunk id
+const MANIFEST_LEN: usize = 1 + 4 + 8;
+
+/// Splits values larger than `threshold` into chunks stored in their own
+/// tree, the data key then only holds a manifest with the chunk count and
+/// the id the chunks are stored under.
+///
+/// Ids come from `sled::Db::generate_id`, so the chunks of a value never
+/// depend on the key it ends up under. A push losing the race for a key,
+/// a tied item and a moved item all keep chunks no other value uses.
+pub(super) struct Chunks {
+    pub(super) tree: sled::Tree,
+    db: sled::Db,
+    threshold: usize,
+}
+
+impl Chunks {
+    pub(super) fn open(db: &sled::Db, threshold: usize) -> Result<Self, Error> {
+        Ok(Self {
+            tree: db.open_tree(CHUNK_TREE)?,
+            db: db.clone(),
+            threshold: threshold.max(1),
+        })
+    }
+
+    fn chunk_key(id: u64, index: u32) -> [u8; 12] {
+        let mut chunk_key = [0u8; 12];
+        chunk_key[..8].copy_from_slice(&id.to_be_bytes());
+        chunk_key[8..].copy_from_slice(&index.to_be_bytes());
+        chunk_key
+    }
+
+    /// Length of the value `store` returns for `len` serialized bytes
+    pub(super) fn stored_size(&self, len: usize) -> usize {
+        if len <= self.threshold {
+            len + 1
+        } else {
+            MANIFEST_LEN
+        }
+    }
+
+    /// Returns the value to store under the data key
+    pub(super) fn store(&self, serialized: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if serialized.len() <= self.threshold {
+            let mut value = Vec::with_capacity(serialized.len() + 1);
+            value.push(TAG_INLINE);
+            value.extend_from_slice(&serialized);
+            return Ok(value);
+        }
+
+        let id = self.db.generate_id()?;
+        let mut batch = sled::Batch::default();
+        let mut count = 0u32;
+        for chunk in serialized.chunks(self.threshold) {
+            batch.insert(&Self::chunk_key(id, count), chunk);
+            count += 1;
+        }
+        self.tree.apply_batch(batch)?;
+
+        let mut manifest = Vec::with_capacity(MANIFEST_LEN);
+        manifest.push(TAG_CHUNKED);
+        manifest.extend_from_slice(&count.to_be_bytes());
+        manifest.extend_from_slice(&id.to_be_bytes());
+        Ok(manifest)
+    }
+
+    /// Reassemble the serialized bytes from the value under the data key,
+    /// chunks are removed as well when `consume` is set.
+    pub(super) fn load(&self, value: sled::IVec, consume: bool) -> Result<sled::IVec, Error> {
+        match value.first() {
+            Some(&TAG_INLINE) => Ok(value.subslice(1, value.len() - 1)),
+            Some(&TAG_CHUNKED) => {
+                let (count, id) = Self::manifest(&value)?;
+                let mut serialized = Vec::new();
+                for index in 0..count {
+                    let chunk_key = Self::chunk_key(id, index);
+                    let chunk = if consume {
+                        self.tree.remove(chunk_key)?
+                    } else {
+                        self.tree.get(chunk_key)?
+                    };
+                    serialized.extend_from_slice(&chunk.ok_or(Error::MissingValueChunk)?);
+                }
+                Ok(serialized.into())
+            }
+            _ => Err(Error::MissingValueChunk),
+        }
+    }
+
+    /// Remove the chunks of a value without reassembling it
+    pub(super) fn discard(&self, value: &[u8]) -> Result<(), Error> {
+        if value.first() == Some(&TAG_CHUNKED) {
+            let (count, id) = Self::manifest(value)?;
+            for index in 0..count {
+                self.tree.remove(Self::chunk_key(id, index))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Chunk count and id recorded in a manifest
+    fn manifest(value: &[u8]) -> Result<(u32, u64), Error> {
+        let count = value
+            .get(1..5)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(Error::MissingValueChunk)?;
+        let id = value
+            .get(5..13)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(Error::MissingValueChunk)?;
+        Ok((u32::from_be_bytes(count), u64::from_be_bytes(id)))
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// Dedup keys seen recently with when they were seen, kept in their own
+/// tree so they survive restarts along with the items. Expired keys are
+/// purged on open and by the first mark once a window passed since the
+/// last purge.
+pub(super) struct Dedup {
+    tree: sled::Tree,
+    window: Duration,
+    // micros of the last purge
+    purged_at: AtomicU64,
+}
+
+impl Dedup {
+    pub(super) fn open(db: &sled::Db, name: &[u8], window: Duration) -> Result<Self, Error> {
+        let dedup = Self {
+            tree: db.open_tree(name)?,
+            window,
+            purged_at: AtomicU64::new(0),
+        };
+        dedup.purge_expired(Self::now_micros())?;
+        Ok(dedup)
+    }
+
+    /// Record `key` as seen now, `false` if it was seen within the window
+    /// already. Racing pushes of the same key only let one of them in.
+    pub(super) fn mark(&self, key: &[u8]) -> Result<bool, Error> {
+        let now = Self::now_micros();
+        let purged_at = self.purged_at.load(Ordering::Acquire);
+        // one mark per window pays for the purge
+        if now.saturating_sub(purged_at) >= self.window_micros()
+            && self
+                .purged_at
+                .compare_exchange(purged_at, now, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            self.purge_expired(now)?;
+        }
+        loop {
+            let seen = self.tree.get(key)?;
+            if seen.as_deref().is_some_and(|seen| self.is_fresh(seen, now)) {
+                return Ok(false);
+            }
+            let swapped = self
+                .tree
+                .compare_and_swap(key, seen, Some(&now.to_be_bytes()))?;
+            if swapped.is_ok() {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Forget `key` again, e.g. when pushing its item failed
+    pub(super) fn unmark(&self, key: &[u8]) -> Result<(), Error> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    /// Drop the keys seen longer than the window before `now`, unless a
+    /// mark saw them again meanwhile
+    fn purge_expired(&self, now: u64) -> Result<(), Error> {
+        self.purged_at.store(now, Ordering::Release);
+        for entry in self.tree.iter() {
+            let (key, seen) = entry?;
+            if !self.is_fresh(&seen, now) {
+                let _ = self
+                    .tree
+                    .compare_and_swap(key, Some(seen), None as Option<&[u8]>)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a key seen at `seen` is still within the window at `now`
+    fn is_fresh(&self, seen: &[u8], now: u64) -> bool {
+        let seen = seen.try_into().map(u64::from_be_bytes).unwrap_or_default();
+        now.saturating_sub(seen) < self.window_micros()
+    }
+
+    fn window_micros(&self) -> u64 {
+        self.window.as_micros() as u64
+    }
+
+    /// Number of keys remembered, only read by tests
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn now_micros() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,138 @@
+use crate::Error;
+
+use super::{Durability, ExternalBuffer};
+
+/// A buffer whose push and shift never await anything, like sled or the in
+/// memory queues. `ExternalBuffer` stays the interface for genuinely async
+/// backends, e.g. one talking to redis, a synchronous buffer is used in its
+/// place by wrapping it in a `SyncBuffer`.
+pub trait SyncExternalBuffer<T>: Send + Sync {
+    fn push_sync(&self, item: T) -> Result<(), Error>; // to end of buffer
+
+    fn shift_sync(&self) -> Result<Option<T>, Error>; // from head of buffer
+
+    /// Number of items currently buffered, should be cheap to call
+    fn len_sync(&self) -> usize;
+}
+
+/// Use a `SyncExternalBuffer` as an `ExternalBuffer`, the stream pushes and
+/// shifts through its synchronous side without boxing a future per item.
+pub struct SyncBuffer<B> {
+    inner: B,
+    durability: Durability,
+}
+
+impl<B> SyncBuffer<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            durability: Durability::Volatile,
+        }
+    }
+
+    /// Report the inner buffer as persistent instead of volatile
+    pub fn persistent(mut self) -> Self {
+        self.durability = Durability::Persistent;
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, B> ExternalBuffer<T> for SyncBuffer<B>
+where
+    T: Send + 'static,
+    B: SyncExternalBuffer<T>,
+{
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.inner.push_sync(item)
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.inner.shift_sync()
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        Some(&self.inner)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len_sync()
+    }
+
+    fn durability(&self) -> Durability {
+        self.durability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferedStream;
+    use futures::StreamExt;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Only has a synchronous side
+    #[derive(Default)]
+    struct SyncOnly(Mutex<VecDeque<i32>>);
+
+    impl SyncExternalBuffer<i32> for SyncOnly {
+        fn push_sync(&self, item: i32) -> Result<(), Error> {
+            self.0.lock()?.push_back(item);
+            Ok(())
+        }
+
+        fn shift_sync(&self) -> Result<Option<i32>, Error> {
+            Ok(self.0.lock()?.pop_front())
+        }
+
+        fn len_sync(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+    }
+
+    /// Only has an async side, every call yields once
+    #[derive(Default)]
+    struct AsyncOnly(SyncOnly);
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for AsyncOnly {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            tokio::task::yield_now().await;
+            self.0.push_sync(item)
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            tokio::task::yield_now().await;
+            self.0.shift_sync()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len_sync()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_buffer_through_stream() {
+        let buffer = SyncBuffer::new(SyncOnly::default());
+        assert!(buffer.as_sync().is_some());
+
+        let stream = ExternalBufferedStream::new(futures::stream::iter(1..=100), buffer);
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, (1..=100).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_async_buffer_through_stream() {
+        let buffer = AsyncOnly::default();
+        assert!(buffer.as_sync().is_none());
+
+        let stream = ExternalBufferedStream::new(futures::stream::iter(1..=100), buffer);
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, (1..=100).collect::<Vec<_>>());
+    }
+}
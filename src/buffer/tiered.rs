@@ -0,0 +1,465 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use crate::trace::event;
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, PushOutcome};
+
+/// Compose a fast hot buffer with a cold one (e.g. an in memory queue with
+/// sled): items go to the hot buffer until it holds `high_water_mark` items,
+/// then spill to the cold buffer. Shift drains the hot buffer first.
+///
+/// Once items spilled, pushes keep going to the cold buffer until it is
+/// drained, so a FIFO hot buffer keeps the overall FIFO order.
+pub struct TieredBuffer<H, C> {
+    tiers: Arc<Tiers<H, C>>,
+}
+
+struct Tiers<H, C> {
+    hot: H,
+    cold: C,
+    high_water_mark: usize,
+    hot_len: AtomicUsize,
+    cold_len: AtomicUsize,
+    // push that found the hot buffer empty, the age of its oldest item
+    hot_since: Mutex<Option<Instant>>,
+    max_hot_age: Arc<HotAge>,
+    sweeping: AtomicBool,
+    // set while a spill moves the hot items, pushes then go to the cold
+    // buffer and shifts take from it first
+    spilling: AtomicBool,
+    // pushes into the hot buffer not complete yet, a spill waits for them
+    hot_pushes: AtomicUsize,
+}
+
+/// The `max_hot_age` the sweep checks, it wakes up when the age changes
+#[derive(Default)]
+struct HotAge {
+    age: Mutex<Option<Duration>>,
+    changed: Condvar,
+}
+
+impl<H, C> TieredBuffer<H, C> {
+    pub fn new(hot: H, cold: C, high_water_mark: usize) -> Self {
+        Self {
+            tiers: Arc::new(Tiers {
+                hot,
+                cold,
+                high_water_mark,
+                hot_len: AtomicUsize::new(0),
+                cold_len: AtomicUsize::new(0),
+                hot_since: Mutex::new(None),
+                max_hot_age: Default::default(),
+                sweeping: AtomicBool::new(false),
+                spilling: AtomicBool::new(false),
+                hot_pushes: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Spill the hot buffer to the cold one once its oldest item is older
+    /// than `max_age`, even below the high water mark, which bounds what a
+    /// crash loses of a volatile hot buffer. A background thread checks
+    /// every half `max_age` and ends with the buffer, setting the age again
+    /// changes what the same thread checks.
+    ///
+    /// Spilled items are pushed after those the cold buffer still holds, so
+    /// they are shifted after them. An item the cold buffer fails to take
+    /// is put back into the hot one, which is why it has to be `Clone`.
+    pub fn max_hot_age<T>(self, max_age: Duration) -> Self
+    where
+        T: Clone + Send + 'static,
+        H: ExternalBuffer<T> + 'static,
+        C: ExternalBuffer<T> + 'static,
+    {
+        *self.tiers.max_hot_age() = Some(max_age);
+        self.tiers.max_hot_age.changed.notify_all();
+        if self.tiers.sweeping.swap(true, Ordering::AcqRel) {
+            return self;
+        }
+
+        // holds a weak reference only, so it ends with the buffer
+        let weak = Arc::downgrade(&self.tiers);
+        let hot_age = self.tiers.max_hot_age.clone();
+        std::thread::spawn(move || loop {
+            let age = hot_age.age.lock().unwrap_or_else(|e| e.into_inner());
+            let interval = (age.unwrap_or(max_age) / 2).max(Duration::from_millis(1));
+            drop(hot_age.changed.wait_timeout(age, interval));
+            let Some(tiers) = weak.upgrade() else {
+                break;
+            };
+            if let Err(e) = futures::executor::block_on(tiers.spill_aged()) {
+                event!(error; "Failed to spill aged hot items: {}", e);
+            }
+        });
+        self
+    }
+
+    pub fn hot(&self) -> &H {
+        &self.tiers.hot
+    }
+
+    pub fn cold(&self) -> &C {
+        &self.tiers.cold
+    }
+
+    /// Number of items pushed into the hot buffer and not shifted yet
+    pub fn hot_len(&self) -> usize {
+        self.tiers.hot_len.load(Ordering::SeqCst)
+    }
+
+    /// Number of items spilled into the cold buffer and not shifted yet
+    pub fn cold_len(&self) -> usize {
+        self.tiers.cold_len.load(Ordering::SeqCst)
+    }
+
+    /// Spill the hot buffer now if its oldest item is older than the
+    /// `max_hot_age`, what the background sweep does, returns how many
+    /// items were spilled
+    pub async fn spill_aged<T>(&self) -> Result<usize, Error>
+    where
+        T: Clone + Send + 'static,
+        H: ExternalBuffer<T>,
+        C: ExternalBuffer<T>,
+    {
+        self.tiers.spill_aged().await
+    }
+}
+
+/// A push into the hot buffer, counted in `Tiers::hot_pushes` until dropped
+struct HotPush<'a>(&'a AtomicUsize);
+
+impl Drop for HotPush<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<H, C> Tiers<H, C> {
+    fn decrease(len: &AtomicUsize) {
+        let _ = len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            Some(n.saturating_sub(1))
+        });
+    }
+
+    fn count<T>(len: &AtomicUsize, outcome: &PushOutcome<T>) {
+        match outcome {
+            PushOutcome::Stored => {
+                len.fetch_add(1, Ordering::SeqCst);
+            }
+            PushOutcome::DroppedNewest => {}
+            PushOutcome::EvictedOldest(evicted) => {
+                len.fetch_add(1, Ordering::SeqCst);
+                for _ in evicted {
+                    Self::decrease(len);
+                }
+            }
+        }
+    }
+
+    fn hot_since(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.hot_since.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn max_hot_age(&self) -> std::sync::MutexGuard<'_, Option<Duration>> {
+        self.max_hot_age
+            .age
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Where a push goes, `Some` for the hot buffer
+    fn hot_push(&self) -> Option<HotPush<'_>> {
+        if self.cold_len.load(Ordering::SeqCst) > 0
+            || self.hot_len.load(Ordering::SeqCst) >= self.high_water_mark
+        {
+            return None;
+        }
+        // SeqCst pairs with the spill setting `spilling` before it reads
+        // `hot_pushes`, one of the two sees the other
+        let push = HotPush(&self.hot_pushes);
+        self.hot_pushes.fetch_add(1, Ordering::SeqCst);
+        if self.spilling.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.hot_since().get_or_insert_with(Instant::now);
+        Some(push)
+    }
+
+    async fn spill_aged<T>(&self) -> Result<usize, Error>
+    where
+        T: Clone + Send + 'static,
+        H: ExternalBuffer<T>,
+        C: ExternalBuffer<T>,
+    {
+        let (Some(max_age), Some(since)) = (*self.max_hot_age(), *self.hot_since()) else {
+            return Ok(0);
+        };
+        if since.elapsed() < max_age {
+            return Ok(0);
+        }
+        // another spill is moving the items already
+        if self.spilling.swap(true, Ordering::SeqCst) {
+            return Ok(0);
+        }
+        let spilled = self.spill_hot().await;
+        self.spilling.store(false, Ordering::SeqCst);
+        let spilled = spilled?;
+        event!(debug; "Spilled {} hot items older than {:?}", spilled, max_age);
+        Ok(spilled)
+    }
+
+    /// Move the whole hot buffer, what remains would be shifted before the
+    /// spilled items otherwise, including what pushes still running add
+    async fn spill_hot<T>(&self) -> Result<usize, Error>
+    where
+        T: Clone + Send + 'static,
+        H: ExternalBuffer<T>,
+        C: ExternalBuffer<T>,
+    {
+        let mut spilled = 0;
+        loop {
+            while let Some(item) = self.hot.shift().await? {
+                if let Err(e) = self.cold.push(item.clone()).await {
+                    self.put_back(item).await;
+                    return Err(e);
+                }
+                Self::decrease(&self.hot_len);
+                self.cold_len.fetch_add(1, Ordering::SeqCst);
+                spilled += 1;
+            }
+            // a push counts its item before it stops counting as running
+            if self.hot_pushes.load(Ordering::SeqCst) == 0
+                && self.hot_len.load(Ordering::SeqCst) == 0
+            {
+                break;
+            }
+            yield_now().await;
+        }
+        *self.hot_since() = None;
+        Ok(spilled)
+    }
+
+    /// Return an item the cold buffer failed to take to the hot one
+    async fn put_back<T>(&self, item: T)
+    where
+        T: Send + 'static,
+        H: ExternalBuffer<T>,
+    {
+        let restored = match self.hot.push_front_now(item) {
+            Ok(restored) => restored,
+            Err(item) => self.hot.push(item).await,
+        };
+        if let Err(e) = restored {
+            event!(error; "Failed to put back a hot item that failed to spill: {}", e);
+        }
+    }
+}
+
+/// Let the pushes a spill waits for make progress
+async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[async_trait::async_trait]
+impl<T, H, C> ExternalBuffer<T> for TieredBuffer<H, C>
+where
+    T: Send + 'static,
+    H: ExternalBuffer<T>,
+    C: ExternalBuffer<T>,
+{
+    /// Counted by what the tier reports, an overflowing tier may have
+    /// dropped the item or evicted others for it
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_outcome(item).await.map(drop)
+    }
+
+    async fn push_outcome(&self, item: T) -> Result<PushOutcome<T>, Error> {
+        let tiers = &self.tiers;
+        match tiers.hot_push() {
+            // counted before the push stops running, see `spill_hot`
+            Some(_push) => {
+                let outcome = tiers.hot.push_outcome(item).await?;
+                Tiers::<H, C>::count(&tiers.hot_len, &outcome);
+                Ok(outcome)
+            }
+            None => {
+                let outcome = tiers.cold.push_outcome(item).await?;
+                Tiers::<H, C>::count(&tiers.cold_len, &outcome);
+                Ok(outcome)
+            }
+        }
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        let tiers = &self.tiers;
+        // the spilled items are older than what is left in the hot buffer
+        if tiers.spilling.load(Ordering::SeqCst)
+            && let Some(item) = tiers.cold.shift().await?
+        {
+            Tiers::<H, C>::decrease(&tiers.cold_len);
+            return Ok(Some(item));
+        }
+        if let Some(item) = tiers.hot.shift().await? {
+            Tiers::<H, C>::decrease(&tiers.hot_len);
+            if tiers.hot_len.load(Ordering::SeqCst) == 0 {
+                *tiers.hot_since() = None;
+            }
+            return Ok(Some(item));
+        }
+
+        let item = tiers.cold.shift().await?;
+        if item.is_some() {
+            Tiers::<H, C>::decrease(&tiers.cold_len);
+        }
+        Ok(item)
+    }
+
+    /// Into the hot buffer, which is shifted first
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        let tiers = &self.tiers;
+        let restored = tiers.hot.push_front_now(item)?;
+        if restored.is_ok() {
+            tiers.hot_len.fetch_add(1, Ordering::SeqCst);
+        }
+        tiers.hot_since().get_or_insert_with(Instant::now);
+        Ok(restored)
+    }
+
+    fn len(&self) -> usize {
+        self.hot_len() + self.cold_len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.tiers.hot.capacity()? + self.tiers.cold.capacity()?)
+    }
+
+    async fn space_remaining(&self) -> Result<Option<usize>, Error> {
+        let hot = self.tiers.hot.space_remaining().await?;
+        let cold = self.tiers.cold.space_remaining().await?;
+        Ok(hot.zip(cold).map(|(hot, cold)| hot + cold))
+    }
+
+    // only as durable as the weakest tier
+    fn durability(&self) -> Durability {
+        match (self.tiers.hot.durability(), self.tiers.cold.durability()) {
+            (Durability::Persistent, Durability::Persistent) => Durability::Persistent,
+            _ => Durability::Volatile,
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.tiers.hot.flush().await?;
+        self.tiers.cold.flush().await
+    }
+}
+
+#[cfg(all(test, feature = "queue", feature = "sled"))]
+mod tests {
+    use super::*;
+    use crate::{ExternalBufferSled, ExternalBufferVecDeque};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_spill_to_cold_buffer() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = TieredBuffer::new(
+            ExternalBufferVecDeque::new(),
+            ExternalBufferSled::new(temp_dir.path().join("cold_db")).unwrap(),
+            3,
+        );
+
+        for i in 1..=6 {
+            buffer.push(i).await.unwrap();
+        }
+        assert_eq!(buffer.hot_len(), 3);
+        assert_eq!(buffer.cold_len(), 3);
+
+        // The first 3 items come from memory
+        for i in 1..=3 {
+            assert_eq!(buffer.shift().await.unwrap(), Some(i));
+        }
+        assert_eq!(buffer.hot_len(), 0);
+
+        // Pushes keep going to disk while it still holds items
+        buffer.push(7).await.unwrap();
+        assert_eq!(buffer.cold_len(), 4);
+
+        for i in 4..=7 {
+            assert_eq!(buffer.shift().await.unwrap(), Some(i));
+        }
+        assert_eq!(buffer.shift().await.unwrap(), None);
+        assert_eq!(buffer.durability(), Durability::Volatile);
+    }
+
+    #[tokio::test]
+    async fn test_max_hot_age_spills_idle_items() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = TieredBuffer::new(
+            ExternalBufferVecDeque::new(),
+            ExternalBufferSled::new(temp_dir.path().join("cold_db")).unwrap(),
+            10,
+        )
+        .max_hot_age::<i32>(Duration::from_millis(50));
+
+        buffer.push(1).await.unwrap();
+        assert_eq!(buffer.hot_len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(buffer.hot_len(), 0);
+        assert_eq!(buffer.cold_len(), 1);
+        assert_eq!(buffer.shift().await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_max_hot_age_can_be_set_again() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = TieredBuffer::new(
+            ExternalBufferVecDeque::new(),
+            ExternalBufferSled::new(temp_dir.path().join("cold_db")).unwrap(),
+            10,
+        )
+        .max_hot_age::<i32>(Duration::from_secs(60));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the sweep waiting for the first age checks the new one
+        let buffer = buffer.max_hot_age::<i32>(Duration::from_millis(50));
+
+        buffer.push(1).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(buffer.hot_len(), 0);
+        assert_eq!(buffer.cold_len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_spill_keeps_hot_items() {
+        let buffer = TieredBuffer::new(
+            ExternalBufferVecDeque::new(),
+            crate::BoundedBuffer::new(ExternalBufferVecDeque::new(), 0),
+            10,
+        );
+        // no sweep, spilled by hand below
+        *buffer.tiers.max_hot_age() = Some(Duration::from_millis(1));
+        for i in 1..=2 {
+            buffer.push(i).await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let result = buffer.spill_aged::<i32>().await;
+        assert!(matches!(result, Err(Error::BufferFull)));
+        assert_eq!(buffer.hot_len(), 2);
+        assert_eq!(buffer.shift().await.unwrap(), Some(1));
+        assert_eq!(buffer.shift().await.unwrap(), Some(2));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::Error;
+
+use super::{Durability, ExternalBuffer, SyncExternalBuffer};
+
+/// A in memory FIFO queue as the buffer
+pub struct ExternalBufferVecDeque<T> {
+    queue: Mutex<VecDeque<T>>,
+}
+
+impl<T> ExternalBufferVecDeque<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Default::default(),
+        }
+    }
+
+    /// Shift the most recently pushed item instead of the oldest, the rest
+    /// keep their FIFO order
+    pub fn shift_back(&self) -> Result<Option<T>, Error> {
+        let mut queue = self.queue.lock()?;
+        Ok(queue.pop_back())
+    }
+}
+
+impl<T> Default for ExternalBufferVecDeque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<VecDeque<T>> for ExternalBufferVecDeque<T> {
+    fn from(items: VecDeque<T>) -> Self {
+        Self {
+            queue: Mutex::new(items),
+        }
+    }
+}
+
+impl<T> FromIterator<T> for ExternalBufferVecDeque<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<VecDeque<_>>())
+    }
+}
+
+impl<T> Extend<T> for ExternalBufferVecDeque<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        // no lock needed with exclusive access
+        let queue = self.queue.get_mut().unwrap_or_else(|e| e.into_inner());
+        queue.extend(iter);
+    }
+}
+
+impl<T: Send> SyncExternalBuffer<T> for ExternalBufferVecDeque<T> {
+    fn push_sync(&self, item: T) -> Result<(), Error> {
+        let mut queue = self.queue.lock()?;
+        queue.push_back(item);
+        Ok(())
+    }
+
+    fn shift_sync(&self) -> Result<Option<T>, Error> {
+        let mut queue = self.queue.lock()?;
+        Ok(queue.pop_front())
+    }
+
+    fn len_sync(&self) -> usize {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Send> ExternalBuffer<T> for ExternalBufferVecDeque<T> {
+    async fn push(&self, item: T) -> Result<(), Error> {
+        self.push_sync(item)
+    }
+
+    async fn shift(&self) -> Result<Option<T>, Error> {
+        self.shift_sync()
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<T>> {
+        Some(self)
+    }
+
+    fn push_front_now(&self, item: T) -> Result<Result<(), Error>, T> {
+        Ok(self
+            .queue
+            .lock()
+            .map(|mut queue| queue.push_front(item))
+            .map_err(Error::from))
+    }
+
+    fn len(&self) -> usize {
+        self.len_sync()
+    }
+
+    fn durability(&self) -> Durability {
+        Durability::Volatile
+    }
+
+    async fn remove_if<F>(&self, pred: F) -> Result<usize, Error>
+    where
+        F: Fn(&T) -> bool + Send + 'async_trait,
+    {
+        let mut queue = self.queue.lock()?;
+        let before = queue.len();
+        queue.retain(|item| !pred(item));
+        Ok(before - queue.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fifo_order() {
+        let buffer = ExternalBufferVecDeque::new();
+
+        for i in [3, 1, 4, 1, 5] {
+            buffer.push(i).await.unwrap();
+        }
+
+        let mut result = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            result.push(item);
+        }
+        assert_eq!(result, vec![3, 1, 4, 1, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_shift_from_both_ends() {
+        let buffer = ExternalBufferVecDeque::new();
+        for i in [1, 2, 3] {
+            buffer.push(i).await.unwrap();
+        }
+
+        assert_eq!(buffer.shift().await.unwrap(), Some(1));
+        assert_eq!(buffer.shift_back().unwrap(), Some(3));
+        assert_eq!(buffer.shift().await.unwrap(), Some(2));
+        assert_eq!(buffer.shift_back().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_collect_and_extend() {
+        let mut buffer: ExternalBufferVecDeque<i32> = (1..=3).collect();
+        buffer.extend([4, 5]);
+
+        let mut result = Vec::new();
+        while let Some(item) = buffer.shift().await.unwrap() {
+            result.push(item);
+        }
+        assert_eq!(result, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_durability() {
+        let buffer = ExternalBufferVecDeque::<i32>::new();
+        assert_eq!(buffer.durability(), Durability::Volatile);
+    }
+}
@@ -0,0 +1,219 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+
+use crate::pressure::DEFAULT_PRESSURE_THRESHOLDS;
+use crate::runtime::{self, Spawner};
+use crate::DEFAULT_YIELD_AFTER;
+use crate::{Error, ExternalBuffer, ExternalBufferedStream};
+
+pub(crate) type ErrorHandler = Box<dyn Fn(&Error) + Send + Sync>;
+pub(crate) type SharedErrorHandler = std::sync::Arc<dyn Fn(&Error) + Send + Sync>;
+pub(crate) type PollHandler = Box<dyn Fn(usize, usize) + Send + Sync>;
+/// Pushes the items of a source off the ingest task, see `ingest_concurrency`
+pub(crate) type OffloadedPushes<B, S> =
+    fn(S, Arc<B>, usize) -> BoxStream<'static, Result<(), Error>>;
+
+/// Builder to configure an `ExternalBufferedStream` before the source
+/// starts being consumed.
+pub struct ExternalBufferedStreamBuilder<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    pub(crate) source: S,
+    pub(crate) buffer: B,
+    pub(crate) on_error: Option<ErrorHandler>,
+    pub(crate) on_poll: Option<PollHandler>,
+    pub(crate) name: Option<String>,
+    pub(crate) pressure_thresholds: Vec<f32>,
+    pub(crate) greedy: bool,
+    pub(crate) notify_capacity: Option<usize>,
+    pub(crate) buffer_capacity: Option<usize>,
+    pub(crate) ingest_concurrency: Option<(usize, OffloadedPushes<B, S>)>,
+    pub(crate) deadline: Option<std::time::Duration>,
+    pub(crate) yield_after: usize,
+    #[cfg(feature = "rt-tokio")]
+    pub(crate) cancellation: Option<tokio_util::sync::CancellationToken>,
+    pub(crate) spawner: Spawner,
+    _item: PhantomData<T>,
+}
+
+impl<T, B, S> ExternalBufferedStreamBuilder<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    pub fn new(source: S, buffer: B) -> Self {
+        Self {
+            source,
+            buffer,
+            on_error: None,
+            on_poll: None,
+            name: None,
+            pressure_thresholds: DEFAULT_PRESSURE_THRESHOLDS.to_vec(),
+            greedy: false,
+            notify_capacity: None,
+            buffer_capacity: None,
+            ingest_concurrency: None,
+            deadline: None,
+            yield_after: DEFAULT_YIELD_AFTER,
+            #[cfg(feature = "rt-tokio")]
+            cancellation: None,
+            spawner: runtime::spawn_boxed,
+            _item: PhantomData,
+        }
+    }
+
+    /// Called whenever a push or shift error occurs, before the stream stops
+    /// ingesting or terminates because of it.
+    pub fn on_error<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(handler));
+        self
+    }
+
+    /// Called at the end of every poll of the stream, except those only
+    /// yielding to the executor, with the number of items `available`,
+    /// buffered or shifted ahead, and the number `drained` by the poll, e.g.
+    /// to tune batch sizes. Runs on the consumer's task, so keep it cheap.
+    pub fn on_poll<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(usize, usize) + Send + Sync + 'static,
+    {
+        self.on_poll = Some(Box::new(handler));
+        self
+    }
+
+    /// Name used to tell streams apart in logs and `Debug` output
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Fill ratios (0.0 to 1.0) of a bounded buffer at which a new value is
+    /// published to `ExternalBufferedStream::pressure` receivers, defaults to
+    /// 0.5, 0.8 and 1.0.
+    pub fn pressure_thresholds(mut self, thresholds: impl IntoIterator<Item = f32>) -> Self {
+        self.pressure_thresholds = thresholds.into_iter().collect();
+        self
+    }
+
+    /// When set, every poll that gets an item also shifts whatever else is
+    /// immediately available (up to a small cap) into a local queue, so bulk
+    /// consumers are woken up less often. The default is one item per poll,
+    /// which keeps latency fair between streams sharing an executor.
+    pub fn greedy(mut self, greedy: bool) -> Self {
+        self.greedy = greedy;
+        self
+    }
+
+    /// Let at most `capacity` push notifications wait for the consumer, the
+    /// ingest task then stops pulling from the source until the consumer
+    /// catches up, so a slow consumer slows the source down. By default
+    /// notifications are coalesced and ingestion never waits.
+    ///
+    /// This only gates wakeup signaling, a notification is taken once the
+    /// consumer finds the buffer empty, so up to a whole backlog may be
+    /// stored per notification. Use `buffer_capacity` to bound storage.
+    pub fn notify_capacity(mut self, capacity: usize) -> Self {
+        self.notify_capacity = Some(capacity.max(1));
+        self
+    }
+
+    /// Hold at most `capacity` items in the buffer, the ingest task waits
+    /// for the consumer to shift one before pushing more. Unlike a
+    /// `BoundedBuffer`, which rejects a push and so ends ingestion, nothing
+    /// is lost. It can be combined with `notify_capacity`, the ingest task
+    /// waits for room before a push and for the notification after it, and
+    /// the consumer frees both as it drains, so the two never wait on each
+    /// other.
+    pub fn buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_capacity = Some(capacity.max(1));
+        self
+    }
+
+    /// Prepare up to `n` items at once on blocking threads, for buffers
+    /// spending much time per push, e.g. sled serializing with compression,
+    /// see `ExternalBuffer::prepare_push`. The prepared items are still
+    /// pushed one at a time in source order. 1 is the default, pushing
+    /// each item on the ingest task as it is.
+    pub fn ingest_concurrency(mut self, n: usize) -> Self
+    where
+        T: 'static,
+    {
+        self.ingest_concurrency = match n {
+            0 | 1 => None,
+            n => Some((n, offload_pushes::<T, B, S>)),
+        };
+        self
+    }
+
+    /// Longest time `ExternalBufferedStream::deadline_events` waits for an
+    /// item before it yields a `DeadlineEvent::Missed`
+    pub fn deadline(mut self, deadline: std::time::Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Yield back to the executor after this many items were delivered
+    /// without the stream ever being pending, so draining a large backlog
+    /// does not starve other tasks on the same thread. Defaults to 128.
+    pub fn yield_after(mut self, items: usize) -> Self {
+        self.yield_after = items.max(1);
+        self
+    }
+
+    /// Stop ingesting once `token` is cancelled, the stream then delivers
+    /// what is already buffered and finishes
+    #[cfg(feature = "rt-tokio")]
+    pub fn cancellation_token(mut self, token: tokio_util::sync::CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Build the stream, if the ingest task cannot be started it ends
+    /// right away with the failure in `ExternalBufferedStream::health`
+    pub fn build(self) -> ExternalBufferedStream<T, B, S> {
+        ExternalBufferedStream::from_builder(self).0
+    }
+
+    /// Build the stream, failing if the ingest task cannot be started
+    pub fn try_build(self) -> Result<ExternalBufferedStream<T, B, S>, Error> {
+        let (stream, spawned) = ExternalBufferedStream::from_builder(self);
+        spawned.map(|_| stream)
+    }
+}
+
+fn offload_pushes<T, B, S>(
+    source: S,
+    buffer: Arc<B>,
+    n: usize,
+) -> BoxStream<'static, Result<(), Error>>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    let pool = Arc::new(runtime::BlockingPool::new(n));
+    let prepare_buffer = buffer.clone();
+    source
+        .map(move |item| {
+            let buffer = prepare_buffer.clone();
+            let pool = pool.clone();
+            async move { pool.run(move || buffer.prepare_push(item)).await }
+        })
+        // prepared ahead in parallel, handed on in source order
+        .buffered(n)
+        .then(move |prepared| {
+            let buffer = buffer.clone();
+            async move { buffer.push_prepared(prepared?).await }
+        })
+        .boxed()
+}
@@ -0,0 +1,97 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+
+use crate::{ExternalBuffer, ExternalBufferedStream};
+
+/// Stream returned by `ExternalBufferedStream::chunk_bytes`
+pub struct ChunkedStream<B, S>
+where
+    B: ExternalBuffer<Vec<u8>>,
+    S: Stream<Item = Vec<u8>>,
+{
+    inner: ExternalBufferedStream<Vec<u8>, B, S>,
+    chunk_size: usize,
+    // item being handed out and how much of it is handed out already
+    current: Vec<u8>,
+    offset: usize,
+}
+
+impl<B, S> ExternalBufferedStream<Vec<u8>, B, S>
+where
+    B: ExternalBuffer<Vec<u8>> + 'static,
+    S: Stream<Item = Vec<u8>> + Send + 'static,
+{
+    /// Yield every item in slices of `n` bytes, the last one of an item may
+    /// be shorter, before shifting the next item. For consumers that can't
+    /// take a large item at once. Empty items yield nothing.
+    ///
+    /// Panics if `n` is 0.
+    pub fn chunk_bytes(self, n: usize) -> ChunkedStream<B, S> {
+        assert!(n > 0, "chunk size must be greater than 0");
+        ChunkedStream {
+            inner: self,
+            chunk_size: n,
+            current: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl<B, S> Stream for ChunkedStream<B, S>
+where
+    B: ExternalBuffer<Vec<u8>> + 'static,
+    S: Stream<Item = Vec<u8>> + Send + 'static,
+{
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // S is PhantomData, so here is safe to get mut
+        let this = unsafe { self.get_unchecked_mut() };
+
+        while this.offset >= this.current.len() {
+            // inner is never moved out of the pinned `this`
+            let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+            match futures::ready!(inner.poll_next(cx)) {
+                Some(item) => {
+                    this.current = item;
+                    this.offset = 0;
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+
+        let start = this.offset;
+        if start == 0 && this.current.len() <= this.chunk_size {
+            // a small item is handed out as is
+            this.offset = this.current.len();
+            return Poll::Ready(Some(std::mem::take(&mut this.current)));
+        }
+        let end = this.current.len().min(start + this.chunk_size);
+        this.offset = end;
+        Poll::Ready(Some(this.current[start..end].to_vec()))
+    }
+}
+
+#[cfg(all(test, feature = "queue"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferVecDeque;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_large_item_is_yielded_in_chunks() {
+        let items = vec![vec![1u8; 2500], vec![2u8; 300]];
+        let stream = ExternalBufferedStream::new(
+            futures::stream::iter(items),
+            ExternalBufferVecDeque::new(),
+        );
+
+        let chunks: Vec<Vec<u8>> = stream.chunk_bytes(1000).collect().await;
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1000, 1000, 500, 300]);
+        assert!(chunks[..3].iter().flatten().all(|byte| *byte == 1));
+        assert_eq!(chunks[3], vec![2u8; 300]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+
+use crate::runtime::Sleep;
+use crate::{ExternalBuffer, ExternalBufferedStream};
+
+/// Item of `ExternalBufferedStream::deadline_events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeadlineEvent<T> {
+    Item(T),
+    /// No item arrived within the configured deadline
+    Missed,
+}
+
+/// Stream returned by `ExternalBufferedStream::deadline_events`
+pub struct DeadlineStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    inner: ExternalBufferedStream<T, B, S>,
+    deadline: Option<Duration>,
+    // reset when the inner stream has nothing to deliver, one for all waits
+    timer: Option<Sleep>,
+    waiting: bool,
+}
+
+impl<T, B, S> ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    /// Deliver items as `DeadlineEvent::Item`, and a `DeadlineEvent::Missed`
+    /// every time the buffer stays empty for the deadline set with
+    /// `ExternalBufferedStreamBuilder::deadline`, so a real-time consumer
+    /// never waits longer than that. Without a deadline no event is missed.
+    pub fn deadline_events(self) -> DeadlineStream<T, B, S> {
+        DeadlineStream {
+            deadline: self.deadline,
+            inner: self,
+            timer: None,
+            waiting: false,
+        }
+    }
+}
+
+impl<T, B, S> Stream for DeadlineStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    type Item = DeadlineEvent<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // S is PhantomData, so here is safe to get mut
+        let this = unsafe { self.get_unchecked_mut() };
+
+        // inner is never moved out of the pinned `this`
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        match inner.poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                this.waiting = false;
+                return Poll::Ready(Some(DeadlineEvent::Item(item)));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+
+        let Some(deadline) = this.deadline else {
+            return Poll::Pending;
+        };
+        let missed_at = Instant::now() + deadline;
+        let timer = match &mut this.timer {
+            Some(timer) if this.waiting => timer,
+            Some(timer) => {
+                timer.reset(missed_at);
+                timer
+            }
+            None => this.timer.insert(Sleep::new(missed_at)),
+        };
+        this.waiting = true;
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(()) => {
+                // the next wait starts a new deadline
+                this.waiting = false;
+                Poll::Ready(Some(DeadlineEvent::Missed))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_missed_deadlines_during_gap() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let stream = ExternalBufferedStream::builder(rx, MemoryBuffer::default())
+            .deadline(Duration::from_millis(100))
+            .build();
+        let mut events = stream.deadline_events();
+
+        tx.unbounded_send(1).unwrap();
+        assert_eq!(events.next().await, Some(DeadlineEvent::Item(1)));
+
+        let feeder = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            tx.unbounded_send(2).unwrap();
+        });
+
+        let mut missed = 0;
+        loop {
+            match events.next().await.unwrap() {
+                DeadlineEvent::Missed => missed += 1,
+                DeadlineEvent::Item(item) => {
+                    assert_eq!(item, 2);
+                    break;
+                }
+            }
+        }
+        // one event per 100ms of the 250ms gap
+        assert_eq!(missed, 2);
+        feeder.await.unwrap();
+    }
+}
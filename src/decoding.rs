@@ -0,0 +1,106 @@
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+
+use crate::{Error, ExternalBuffer, ExternalBufferedStream};
+
+/// Buffer the raw items of `source` as they are and only `decode` them when
+/// they are delivered, so ingest keeps up with the source no matter how
+/// costly decoding is. A failed decode is delivered as an `Err` and the
+/// stream goes on with the next item.
+pub fn create_decoding_stream<Raw, T, B, S, F>(
+    source: S,
+    buffer: B,
+    decode: F,
+) -> DecodingStream<Raw, T, B, S, F>
+where
+    Raw: Send + 'static,
+    B: ExternalBuffer<Raw> + 'static,
+    S: Stream<Item = Raw> + Send + 'static,
+    F: Fn(Raw) -> Result<T, Error>,
+{
+    DecodingStream {
+        inner: ExternalBufferedStream::new(source, buffer),
+        decode,
+        _item: PhantomData,
+    }
+}
+
+/// The stream returned by `create_decoding_stream`
+pub struct DecodingStream<Raw, T, B, S, F>
+where
+    Raw: Send,
+    B: ExternalBuffer<Raw>,
+    S: Stream<Item = Raw>,
+{
+    inner: ExternalBufferedStream<Raw, B, S>,
+    decode: F,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<Raw, T, B, S, F> Stream for DecodingStream<Raw, T, B, S, F>
+where
+    Raw: Send + 'static,
+    B: ExternalBuffer<Raw> + 'static,
+    S: Stream<Item = Raw> + Send + 'static,
+    F: Fn(Raw) -> Result<T, Error>,
+{
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = unsafe { self.get_unchecked_mut() };
+        // inner is never moved out of the pinned `this`
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        inner
+            .poll_next(cx)
+            .map(|raw| raw.map(|raw| (this.decode)(raw)))
+    }
+}
+
+#[cfg(all(test, feature = "sled", feature = "bincode"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferSled;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: u8,
+        y: u8,
+    }
+
+    #[tokio::test]
+    async fn test_decodes_lazily() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        let source = futures::stream::iter(vec![vec![1u8, 2], vec![3], vec![5, 6]]);
+
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let decoded_clone = decoded.clone();
+        let mut stream = create_decoding_stream(source, buffer, move |raw: Vec<u8>| {
+            decoded_clone.fetch_add(1, Ordering::Relaxed);
+            match raw[..] {
+                [x, y] => Ok(Point { x, y }),
+                _ => Err(Error::Custom(format!("bad point {:?}", raw).into())),
+            }
+        });
+
+        // everything is ingested but nothing decoded until delivered
+        while stream.inner.buffer.len() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(decoded.load(Ordering::Relaxed), 0);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), Point { x: 1, y: 2 });
+        assert_eq!(decoded.load(Ordering::Relaxed), 1);
+        assert!(stream.next().await.unwrap().is_err());
+        assert_eq!(stream.next().await.unwrap().unwrap(), Point { x: 5, y: 6 });
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -8,13 +8,60 @@ pub enum Error {
     EncodeError(bincode::error::EncodeError),
     #[cfg(feature = "bincode")]
     DecodeError(bincode::error::DecodeError),
+    // Failed to decode the item stored under `key` of a sled buffer
+    #[cfg(all(feature = "sled", feature = "bincode"))]
+    ItemDecode {
+        key: u64,
+        source: bincode::error::DecodeError,
+    },
     #[cfg(feature = "sled")]
     SledError(sled::Error),
     #[cfg(feature = "sled")]
     InvalidSledKeyFormat,
+    // An item is already stored under the key given to `push_with_key`
+    #[cfg(feature = "sled")]
+    DuplicateKey(u64),
+    // A serialized item is larger than `ExternalBufferSled::max_value_bytes`
+    #[cfg(feature = "sled")]
+    ItemTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    // The sled path is already opened by another buffer or process
+    #[cfg(feature = "sled")]
+    DatabaseLocked {
+        path: std::path::PathBuf,
+    },
+    #[cfg(feature = "large-values")]
+    MissingValueChunk,
+    // No queue of a `MultiQueueBuffer` has the given name
+    #[cfg(feature = "sled")]
+    UnknownQueue(String),
+
+    // An item could not be encrypted
+    #[cfg(feature = "encryption")]
+    EncryptionFailed,
+    // An encrypted item was tampered with, moved or the key is wrong
+    #[cfg(feature = "encryption")]
+    DecryptionFailed,
+
+    // A value carries a format tag no reader knows
+    UnknownFormat(u8),
 
     // Failed to accquire a mutex lock
     MutexError,
+
+    // A bounded buffer has no room for more items
+    BufferFull,
+
+    // Shutdown did not complete in the given time
+    ShutdownTimeout,
+
+    // The source stream panicked while polled, with the panic message
+    SourcePanicked(String),
+
+    // No buffer of a `StreamSet` is registered under the given id
+    UnknownStream(usize),
 }
 
 impl core::fmt::Display for Error {
@@ -26,13 +73,47 @@ impl core::fmt::Display for Error {
             Error::EncodeError(e) => write!(f, "Encode error: {}", e),
             #[cfg(feature = "bincode")]
             Error::DecodeError(e) => write!(f, "Decode error: {}", e),
+            #[cfg(all(feature = "sled", feature = "bincode"))]
+            Error::ItemDecode { key, source } => {
+                write!(f, "Decode error of item at key {}: {}", key, source)
+            }
 
             #[cfg(feature = "sled")]
             Error::SledError(e) => write!(f, "Sled error: {}", e),
             #[cfg(feature = "sled")]
             Error::InvalidSledKeyFormat => write!(f, "Invalid key format"),
+            #[cfg(feature = "sled")]
+            Error::DuplicateKey(key) => write!(f, "Key {} is already in use", key),
+            #[cfg(feature = "sled")]
+            Error::ItemTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "Item of {} bytes exceeds the limit of {} bytes",
+                    size, limit
+                )
+            }
+            #[cfg(feature = "sled")]
+            Error::DatabaseLocked { path } => write!(
+                f,
+                "Sled db at {} is locked, only one buffer in one process may open a path at a time",
+                path.display()
+            ),
+            #[cfg(feature = "large-values")]
+            Error::MissingValueChunk => write!(f, "Chunk of a large value is missing"),
+            #[cfg(feature = "sled")]
+            Error::UnknownQueue(name) => write!(f, "Unknown queue: {}", name),
+
+            #[cfg(feature = "encryption")]
+            Error::EncryptionFailed => write!(f, "Failed to encrypt item"),
+            #[cfg(feature = "encryption")]
+            Error::DecryptionFailed => write!(f, "Failed to decrypt item"),
 
+            Error::UnknownFormat(tag) => write!(f, "Unknown format tag: {}", tag),
             Error::MutexError => write!(f, "Failed to acquire mutex lock"),
+            Error::BufferFull => write!(f, "Buffer is full"),
+            Error::ShutdownTimeout => write!(f, "Shutdown timed out"),
+            Error::SourcePanicked(message) => write!(f, "Source stream panicked: {}", message),
+            Error::UnknownStream(id) => write!(f, "No stream registered under id {}", id),
         }
     }
 }
@@ -44,6 +125,7 @@ pub fn make_custom_error(err: impl std::error::Error + Send + Sync + 'static) ->
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module, clippy::io_other_error)]
 mod tests {
     use super::make_custom_error;
 
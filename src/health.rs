@@ -0,0 +1,39 @@
+use std::sync::Mutex;
+
+use crate::Error;
+
+/// Snapshot of a stream for health checks, see `ExternalBufferedStream::health`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether the ingest task is still pulling from the source
+    pub ingest_alive: bool,
+    /// Items buffered but not yet delivered
+    pub backlog: usize,
+    /// The most recent ingest or shift error
+    pub last_error: Option<String>,
+}
+
+/// The most recent error of either side of a stream
+#[derive(Default)]
+pub(crate) struct LastError(Mutex<Option<String>>);
+
+impl LastError {
+    pub(crate) fn record(&self, err: &Error) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(err.to_string());
+    }
+
+    pub(crate) fn get(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// The message a panic was started with, if it is a string
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "unknown panic payload".to_string()),
+    }
+}
@@ -0,0 +1,102 @@
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use futures::{FutureExt, Stream, StreamExt};
+
+use crate::health::panic_message;
+use crate::notify::Notify;
+use crate::pressure::Pressure;
+use crate::trace::event;
+use crate::{buffer, Error, ExternalBuffer};
+
+/// What the ingest task pulls from, the source or the pushes of its items
+/// prepared ahead, see `ExternalBufferedStreamBuilder::ingest_concurrency`
+pub(crate) enum Ingest<S> {
+    Source(Pin<Box<S>>),
+    Offloaded(BoxStream<'static, Result<(), Error>>),
+}
+
+/// Limits and signals of the ingest loop besides pushing and notifying,
+/// none by default
+#[derive(Default)]
+pub(crate) struct IngestOptions {
+    // wait for the buffer to hold fewer items before pushing
+    pub(crate) buffer_capacity: Option<usize>,
+    // wait for the consumer to take notifications before notifying again
+    pub(crate) notify_capacity: Option<usize>,
+    pub(crate) pressure: Option<Arc<Pressure>>,
+}
+
+/// Why `ingest` stopped
+pub(crate) enum IngestEnd {
+    /// The source has no more items
+    Exhausted,
+    /// The consumer of the stream is dropped
+    Closed,
+    /// A push failed or the source panicked, already logged
+    Failed(Error),
+}
+
+/// Push the items of `source` into `buffer` one at a time and notify the
+/// consumer after each, the loop of every ingest task. A panicking source
+/// ends it, what it produced so far is still delivered.
+pub(crate) async fn ingest<T, B, S>(
+    source: &mut Ingest<S>,
+    buffer: &B,
+    notify: &Notify,
+    options: &IngestOptions,
+) -> IngestEnd
+where
+    B: ExternalBuffer<T> + ?Sized,
+    S: Stream<Item = T>,
+{
+    let source_panicked = |panic: Box<dyn std::any::Any + Send>| {
+        let e = Error::SourcePanicked(panic_message(&*panic));
+        event!(error, buffer_len = buffer.len(); "{}", e);
+        IngestEnd::Failed(e)
+    };
+    loop {
+        let pushed = match source {
+            Ingest::Source(source) => {
+                let item = match AssertUnwindSafe(source.next()).catch_unwind().await {
+                    Ok(Some(item)) => item,
+                    Ok(None) => return IngestEnd::Exhausted,
+                    Err(panic) => return source_panicked(panic),
+                };
+                if let Some(capacity) = options.buffer_capacity {
+                    notify.wait_for_room(|| buffer.len() < capacity).await;
+                }
+                buffer::push_item(buffer, item).await
+            }
+            // items are pulled and prepared ahead while waiting here
+            Ingest::Offloaded(pushes) => {
+                if let Some(capacity) = options.buffer_capacity {
+                    notify.wait_for_room(|| buffer.len() < capacity).await;
+                }
+                match AssertUnwindSafe(pushes.next()).catch_unwind().await {
+                    Ok(Some(pushed)) => pushed,
+                    Ok(None) => return IngestEnd::Exhausted,
+                    Err(panic) => return source_panicked(panic),
+                }
+            }
+        };
+        if let Err(e) = pushed {
+            event!(error, buffer_len = buffer.len(); "Failed to push item to buffer: {:?}", e);
+            return IngestEnd::Failed(e);
+        }
+
+        if let Some(capacity) = options.notify_capacity {
+            notify.wait_below(capacity).await;
+        }
+        notify.notify();
+        if let Some(pressure) = &options.pressure {
+            pressure.update(buffer.len(), buffer.capacity());
+        }
+        if notify.is_closed() {
+            event!(debug; "Consumer of external buffer stream is dropped.");
+            return IngestEnd::Closed;
+        }
+    }
+}
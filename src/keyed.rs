@@ -0,0 +1,91 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+
+use crate::{ExternalBuffer, ExternalBufferedStream};
+
+/// Stream returned by `ExternalBufferedStream::with_keys`
+pub struct KeyedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    inner: ExternalBufferedStream<T, B, S>,
+    // key handed out with the next item of a buffer without keys
+    next_key: u64,
+}
+
+impl<T, B, S> ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    /// Deliver every item with the key it was stored under, e.g. the sled
+    /// key to checkpoint for `ExternalBufferSled::seek_to`. Buffers without
+    /// keys, such as the in-memory ones, get a counter from 0 in delivery
+    /// order instead.
+    pub fn with_keys(self) -> KeyedStream<T, B, S> {
+        KeyedStream {
+            inner: self,
+            next_key: 0,
+        }
+    }
+}
+
+impl<T, B, S> Stream for KeyedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    type Item = (u64, T);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // S is PhantomData, so here is safe to get mut
+        let this = unsafe { self.get_unchecked_mut() };
+        let item = futures::ready!(this.inner.poll_keyed(cx));
+        Poll::Ready(item.map(|(key, item)| {
+            let key = key.unwrap_or(this.next_key);
+            this.next_key = key + 1;
+            (key, item)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_in_memory_keys_count_deliveries() {
+        let stream = ExternalBufferedStream::new(
+            futures::stream::iter(vec![7, 8, 9]),
+            MemoryBuffer::default(),
+        );
+        let items: Vec<(u64, i32)> = stream.with_keys().collect().await;
+        assert_eq!(items, vec![(0, 7), (1, 8), (2, 9)]);
+    }
+
+    #[cfg(all(feature = "sled", feature = "bincode"))]
+    #[tokio::test]
+    async fn test_sled_keys_follow_delivery() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let buffer = crate::ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+        buffer.push_with_key(100, 0u32).unwrap();
+        buffer.seek_to(50).unwrap();
+        let stream = ExternalBufferedStream::new(futures::stream::iter(1..=5u32), buffer);
+
+        let items: Vec<(u64, u32)> = stream.with_keys().collect().await;
+        assert_eq!(
+            items.iter().map(|(_, item)| *item).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(items[0].0, 100);
+        assert!(items.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+}
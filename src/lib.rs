@@ -1,20 +1,79 @@
+#[cfg(feature = "rt-tokio")]
+mod broadcast;
 mod buffer;
+mod builder;
+mod chunk_bytes;
+mod deadline;
+mod decoding;
 mod error;
+mod health;
+mod ingest;
+mod keyed;
+mod notify;
+mod owned;
+mod pressure;
+mod reconnect;
+mod retry;
 mod runtime;
 mod serde;
+mod sink;
+#[cfg(feature = "stats")]
+mod stats;
+mod stream_set;
+#[cfg(test)]
+mod test_util;
+mod trace;
+mod try_stream;
+mod writer;
 
+#[cfg(feature = "rt-tokio")]
+pub use broadcast::BroadcastReceiver;
 pub use buffer::*;
+pub use builder::*;
+pub use chunk_bytes::ChunkedStream;
+pub use deadline::{DeadlineEvent, DeadlineStream};
+pub use decoding::{create_decoding_stream, DecodingStream};
 pub use error::*;
+pub use health::HealthStatus;
+pub use keyed::KeyedStream;
+pub use owned::{IntoOwned, IntoOwnedStream};
+pub use pressure::PressureReceiver;
+pub use reconnect::{create_reconnecting_stream, ReconnectPolicy};
+pub use retry::{RetryGuard, RetryPosition, RetryStream};
 pub use serde::*;
+pub use sink::{buffer_channel, BufferSink};
+#[cfg(feature = "stats")]
+pub use stats::LatencyStats;
+pub use stream_set::{StreamSet, StreamSetHandle};
+pub use try_stream::{create_buffered_try_stream, TryBufferedStream};
+pub use writer::{buffer_writer, BufferWriter, BufferWriterStream};
 
 use std::{
+    collections::VecDeque,
+    fmt,
     marker::PhantomData,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 
-use futures::{channel::mpsc, Future, SinkExt, Stream, StreamExt};
+#[cfg(feature = "rt-tokio")]
+use futures::StreamExt;
+use futures::{stream::FusedStream, Future, Stream};
+
+use health::LastError;
+use ingest::{Ingest, IngestEnd, IngestOptions};
+use notify::{Notify, StopGuard};
+use pressure::Pressure;
+use trace::event;
+
+type ShiftFuture<T> = Pin<Box<dyn Future<Output = Result<Option<KeyedItem<T>>, Error>> + Send>>;
+
+/// Max items shifted ahead of the consumer in greedy mode
+const GREEDY_BATCH_CAP: usize = 64;
+
+/// Items delivered in a row before the stream yields to the executor
+pub(crate) const DEFAULT_YIELD_AFTER: usize = 128;
 
 pub struct ExternalBufferedStream<T, B, S>
 where
@@ -24,10 +83,38 @@ where
 {
     buffer: Arc<B>,
     _source: PhantomData<S>,
-    notify: mpsc::UnboundedReceiver<()>,
+    notify: Arc<Notify>,
+    pressure: Arc<Pressure>,
+    on_error: Option<SharedErrorHandler>,
+    on_poll: Option<PollHandler>,
+    last_error: Arc<LastError>,
+    name: Option<String>,
+    greedy: bool,
+    deadline: Option<std::time::Duration>,
 
     // the pending future that be polled by the stream consumer
-    pending: Option<Pin<Box<dyn Future<Output = Result<Option<T>, Error>> + Send>>>,
+    pending: Option<ShiftFuture<T>>,
+    // items shifted ahead in greedy mode, handed out before polling again
+    ready: VecDeque<KeyedItem<T>>,
+    // shift error hit while shifting ahead, reported once `ready` is empty
+    deferred_error: Option<Error>,
+    // set once `None` is returned, later polls do not touch the buffer
+    terminated: bool,
+    // set once the stop is seen, the next empty shift ends the stream
+    stop_seen: bool,
+    // wakes the consumer once the next delayed item is ready, reset for
+    // every item, and the ready time it is set to
+    ready_timer: Option<runtime::Sleep>,
+    ready_timer_at: Option<std::time::SystemTime>,
+    // items handed out in a row before yielding, and how many are left
+    yield_after: usize,
+    budget: usize,
+    // created by the first `subscribe`
+    #[cfg(feature = "rt-tokio")]
+    broadcaster: std::sync::OnceLock<tokio::sync::broadcast::Sender<T>>,
+    // ingest and drain events of this stream nest under it
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl<T, B, S> ExternalBufferedStream<T, B, S>
@@ -37,109 +124,595 @@ where
     S: Stream<Item = T> + Send + 'static,
 {
     pub fn new(source: S, buffer: B) -> Self {
-        let source = Box::pin(source);
+        ExternalBufferedStreamBuilder::new(source, buffer).build()
+    }
+
+    /// Same as `new`, but fails if the ingest task cannot be started
+    /// instead of returning a stream that never ingests
+    pub fn try_new(source: S, buffer: B) -> Result<Self, Error> {
+        ExternalBufferedStreamBuilder::new(source, buffer).try_build()
+    }
 
+    pub fn builder(source: S, buffer: B) -> ExternalBufferedStreamBuilder<T, B, S> {
+        ExternalBufferedStreamBuilder::new(source, buffer)
+    }
+
+    /// Build the stream and start its ingest task, also returns whether
+    /// the task could be started
+    pub(crate) fn from_builder(
+        builder: ExternalBufferedStreamBuilder<T, B, S>,
+    ) -> (Self, Result<(), Error>) {
+        let ExternalBufferedStreamBuilder {
+            source,
+            buffer,
+            on_error,
+            on_poll,
+            name,
+            pressure_thresholds,
+            greedy,
+            notify_capacity,
+            buffer_capacity,
+            ingest_concurrency,
+            deadline,
+            yield_after,
+            #[cfg(feature = "rt-tokio")]
+            cancellation,
+            spawner,
+            ..
+        } = builder;
         let buffer = Arc::new(buffer);
         let buffer_clone = buffer.clone();
 
-        let (notify_tx, notify_rx) = mpsc::unbounded::<()>();
+        #[cfg(feature = "rt-tokio")]
+        let cancelled = async move {
+            match cancellation {
+                Some(token) => token.cancelled_owned().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let source = match ingest_concurrency {
+            Some((n, offload)) => {
+                let pushes = offload(source, buffer.clone(), n);
+                #[cfg(feature = "rt-tokio")]
+                let pushes = pushes.take_until(cancelled).boxed();
+                Ingest::Offloaded(pushes)
+            }
+            None => {
+                #[cfg(feature = "rt-tokio")]
+                let source = source.take_until(cancelled);
+                Ingest::Source(Box::pin(source))
+            }
+        };
+        let on_error: Option<SharedErrorHandler> = on_error.map(Arc::from);
+        let on_error_clone = on_error.clone();
+
+        let notify = Arc::new(Notify::default());
+        let notify_clone = notify.clone();
+        let pressure = Arc::new(Pressure::new(pressure_thresholds));
+        let pressure_clone = pressure.clone();
+        let last_error = Arc::new(LastError::default());
+        let last_error_clone = last_error.clone();
 
         let handle_source = async move {
             let mut source = source;
-            let mut notify_tx = notify_tx;
-            while let Some(item) = source.next().await {
-                match buffer_clone.push(item).await {
-                    Ok(()) => match notify_tx.send(()).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            log::error!("Failed to notify: {:?}", e);
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        log::error!("Failed to push item to buffer: {:?}", e);
-                        break;
-                    }
+            let notify = notify_clone;
+            let _stop = StopGuard(&notify);
+            let options = IngestOptions {
+                buffer_capacity,
+                notify_capacity,
+                pressure: Some(pressure_clone),
+            };
+            if let IngestEnd::Failed(e) =
+                ingest::ingest(&mut source, &*buffer_clone, &notify, &options).await
+            {
+                last_error_clone.record(&e);
+                if let Some(on_error) = &on_error_clone {
+                    on_error(&e);
                 }
             }
-            log::info!("Source of external buffer stream is ended.");
+            event!(info, buffer_len = buffer_clone.len(); "Source of external buffer stream is ended.");
         };
-        runtime::spawn(handle_source);
+        // a child of the caller's span, so both sides nest where it is built
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("external_buffered_stream", name = name.as_deref());
+        #[cfg(feature = "tracing")]
+        let handle_source = tracing::Instrument::instrument(handle_source, span.clone());
+        let spawned = spawner(Box::pin(handle_source));
+        if let Err(e) = &spawned {
+            event!(error; "Failed to start the ingest task: {}", e);
+            last_error.record(e);
+            if let Some(on_error) = &on_error {
+                on_error(e);
+            }
+            // the task never runs to stop it, end the stream right away
+            notify.stop();
+        }
+
+        let mut stream = Self::from_parts(buffer, notify);
+        stream.pressure = pressure;
+        stream.on_error = on_error;
+        stream.on_poll = on_poll;
+        stream.last_error = last_error;
+        stream.name = name;
+        stream.greedy = greedy;
+        stream.deadline = deadline;
+        stream.yield_after = yield_after;
+        stream.budget = yield_after;
+        #[cfg(feature = "tracing")]
+        {
+            stream.span = span;
+        }
+        (stream, spawned)
+    }
 
+    /// Create the consumer side over a buffer fed by someone else
+    pub(crate) fn from_parts(buffer: Arc<B>, notify: Arc<Notify>) -> Self {
         ExternalBufferedStream {
             buffer,
             _source: PhantomData,
-            notify: notify_rx,
+            notify,
+            pressure: Arc::new(Pressure::new(Vec::new())),
+            on_error: None,
+            on_poll: None,
+            last_error: Arc::default(),
+            name: None,
+            greedy: false,
+            deadline: None,
             pending: None,
+            ready: VecDeque::new(),
+            deferred_error: None,
+            terminated: false,
+            stop_seen: false,
+            ready_timer: None,
+            ready_timer_at: None,
+            yield_after: DEFAULT_YIELD_AFTER,
+            budget: DEFAULT_YIELD_AFTER,
+            #[cfg(feature = "rt-tokio")]
+            broadcaster: std::sync::OnceLock::new(),
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
         }
     }
-}
 
-impl<T, B, S> Stream for ExternalBufferedStream<T, B, S>
-where
-    T: Send,
-    B: ExternalBuffer<T> + 'static,
-    S: Stream<Item = T> + Send + 'static,
-{
-    type Item = T;
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // S is PhantomData, so here is safe to get mut
-        let this = unsafe { self.get_unchecked_mut() };
+    /// Receive the fill ratio (0.0 to 1.0) of a bounded buffer whenever it
+    /// crosses one of the configured thresholds, so a producer can throttle
+    /// itself before the buffer is full. Nothing is published for unbounded
+    /// buffers.
+    pub fn pressure(&self) -> PressureReceiver {
+        self.pressure.subscribe()
+    }
+
+    /// Force items already pushed into the buffer to be durably persisted,
+    /// e.g. before a planned restart.
+    pub async fn flush(&self) -> Result<(), Error> {
+        if self.buffer.durability() == Durability::Volatile {
+            event!(warn; "Flush requested on a volatile buffer, items are not persisted.");
+        }
+        self.buffer.flush().await
+    }
+
+    /// Push-to-shift latency of the items delivered so far, if the buffer
+    /// tracks it, e.g. `ExternalBufferSled::with_latency_stats`
+    #[cfg(feature = "stats")]
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        self.buffer.latency_stats()
+    }
+
+    /// Next item, or `None` once the stream ended. The same as
+    /// `StreamExt::next` for imperative `while let` loops.
+    pub async fn recv(&mut self) -> Option<T> {
+        futures::future::poll_fn(|cx| {
+            // nothing is pinned in place, S is PhantomData
+            unsafe { Pin::new_unchecked(&mut *self) }.poll_next(cx)
+        })
+        .await
+    }
+
+    /// Whether ingest is still running, how much is buffered and the most
+    /// recent error, e.g. for a health check endpoint
+    pub fn health(&self) -> HealthStatus {
+        HealthStatus {
+            ingest_alive: !self.notify.is_stopped(),
+            backlog: self.buffer.len() + self.ready.len(),
+            last_error: self.last_error.get(),
+        }
+    }
+
+    /// Stream of at most the next `n` items borrowing `self`, unlike
+    /// `StreamExt::take` the stream is still usable once it is done, e.g. to
+    /// process the buffer in phases.
+    pub fn take_n(&mut self, n: usize) -> impl Stream<Item = T> + '_ {
+        let mut remaining = n;
+        futures::stream::poll_fn(move |cx| {
+            if remaining == 0 {
+                return Poll::Ready(None);
+            }
+            // nothing is pinned in place, S is PhantomData
+            let item = futures::ready!(unsafe { Pin::new_unchecked(&mut *self) }.poll_next(cx));
+            if item.is_some() {
+                remaining -= 1;
+            }
+            Poll::Ready(item)
+        })
+    }
+
+    /// Push `items` into the buffer ahead of the source, e.g. to warm start
+    /// from a snapshot. The consumer is notified once after all of them.
+    pub async fn prefill(&self, items: impl IntoIterator<Item = T>) -> Result<(), Error> {
+        let mut pushed = false;
+        for item in items {
+            self.buffer.push(item).await?;
+            pushed = true;
+        }
+        if pushed {
+            self.notify.notify();
+        }
+        Ok(())
+    }
+
+    /// Whether an item can be delivered right away, cheap enough to call
+    /// before every poll
+    pub fn has_pending(&self) -> bool {
+        !self.ready.is_empty() || !self.buffer.is_empty()
+    }
+
+    /// Resolve once an item can be delivered or the source has ended, so a
+    /// consumer can wait on several streams and only poll one with data.
+    pub async fn ready(&self) {
+        futures::future::poll_fn(|cx| {
+            // register before checking, same as `poll_next`
+            self.notify.register(cx.waker());
+            if self.has_pending() || self.notify.is_stopped() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Stop ingesting, put items taken out but not yet delivered back into
+    /// the buffer and flush it, so a restart continues where this left off.
+    ///
+    /// Unlike a plain drop, which never waits on the buffer, this also
+    /// waits for a shift still in flight and for buffers that can only
+    /// take items back asynchronously.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        let buffer = self.buffer.clone();
+        let (pending, items) = self.restore_front();
+        // dropping closes the notify
+        drop(self);
+        Self::restore(buffer.clone(), pending, items).await;
+        buffer.flush().await
+    }
+
+    /// Push the items `restore_front` left, after an in flight shift
+    /// finished, to the tail of the buffer
+    async fn restore(buffer: Arc<B>, pending: Option<ShiftFuture<T>>, items: Vec<T>) {
+        let shifted = match pending {
+            Some(pending) => match pending.await {
+                Ok(Some((_, item))) => Some(item),
+                _ => None,
+            },
+            None => None,
+        };
+        for item in items.into_iter().chain(shifted) {
+            if let Err(e) = buffer.push(item).await {
+                event!(error; "Failed to restore an undelivered item: {:?}", e);
+            }
+        }
+    }
+
+    /// Same as `shutdown`, but gives up with `Error::ShutdownTimeout` if
+    /// it does not complete within `timeout`. Whatever is in the buffer is
+    /// left there for the next run.
+    pub async fn shutdown_with_timeout(self, timeout: std::time::Duration) -> Result<(), Error> {
+        let shutdown = Box::pin(self.shutdown());
+        let timer = Box::pin(runtime::sleep(timeout));
+        match futures::future::select(shutdown, timer).await {
+            futures::future::Either::Left((result, _)) => result,
+            futures::future::Either::Right(_) => {
+                event!(warn; "Shutdown of external buffer stream timed out.");
+                Err(Error::ShutdownTimeout)
+            }
+        }
+    }
+
+    /// Drain the stream into `sink` and close it once the stream ends.
+    /// Each item is only taken once the sink accepted the previous one, so a
+    /// slow sink slows the consumer down, and with `notify_capacity` set
+    /// also the source.
+    pub async fn forward_to<Sk>(mut self, sink: Sk) -> Result<(), Error>
+    where
+        Sk: futures::Sink<T>,
+        Sk::Error: Into<Error>,
+    {
+        use futures::SinkExt;
+
+        let mut sink = std::pin::pin!(sink);
+        while let Some(item) = self.recv().await {
+            sink.send(item).await.map_err(Into::into)?;
+        }
+        sink.close().await.map_err(Into::into)
+    }
+
+    /// Next item from the ready queue or the buffer, with its key
+    fn poll_shift(&mut self, cx: &mut Context<'_>) -> Poll<Option<KeyedItem<T>>> {
+        if self.terminated {
+            return Poll::Ready(None);
+        }
 
         loop {
-            if this.pending.is_none() {
-                let buffer = this.buffer.clone();
-                this.pending = Some(Box::pin(async move { buffer.shift().await }));
+            if let Some(item) = self.ready.pop_front() {
+                return Poll::Ready(Some(item));
             }
 
-            if let Some(pending) = this.pending.as_mut() {
+            let result = if let Some(pending) = self.pending.as_mut() {
                 match pending.as_mut().poll(cx) {
                     Poll::Ready(result) => {
-                        this.pending = None;
-
-                        match result {
-                            Ok(Some(item)) => {
-                                return Poll::Ready(Some(item));
-                            }
-                            Ok(None) => {
-                                let mut has_new = false;
-                                let is_end = loop {
-                                    // wait notify and consume all
-                                    match (&mut this.notify).poll_next_unpin(cx) {
-                                        Poll::Ready(Some(_)) => {
-                                            has_new = true;
-                                            // 消费所有通知
-                                            continue;
-                                        }
-                                        Poll::Ready(None) => break true,
-                                        Poll::Pending => break false,
-                                    }
-                                };
-                                if has_new {
-                                    continue;
-                                } else if is_end {
-                                    return Poll::Ready(None);
-                                } else {
-                                    return Poll::Pending;
-                                }
-                            }
-                            Err(err) => {
-                                log::error!("external buffer shift return error: {}", err);
-                                return Poll::Ready(None);
-                            }
-                        }
+                        self.pending = None;
+                        result
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            } else if let Some(e) = self.deferred_error.take() {
+                Err(e)
+            } else if let Some(result) = self.buffer.shift_now_keyed() {
+                // no future to box for buffers that never block
+                result
+            } else {
+                let buffer = self.buffer.clone();
+                self.pending = Some(Box::pin(async move { buffer.shift_keyed().await }));
+                continue;
+            };
+
+            match result {
+                Ok(Some(item)) => {
+                    if self.greedy {
+                        self.prefetch(cx);
                     }
-                    Poll::Pending => {
+                    self.notify.shifted();
+                    self.pressure
+                        .update(self.buffer.len(), self.buffer.capacity());
+                    return Poll::Ready(Some(item));
+                }
+                Ok(None) => {
+                    // register before checking, so a push racing
+                    // with this poll still wakes us up
+                    self.notify.register(cx.waker());
+                    // read the stop flag first, every notify
+                    // before the stop is visible once it is set
+                    let is_end = self.notify.is_stopped();
+                    if self.notify.take() > 0 {
+                        continue;
+                    } else if let Some(ready_at) = self.buffer.next_ready_at() {
+                        // items are buffered but not ready yet
+                        if self.poll_ready_timer(ready_at, cx).is_ready() {
+                            continue;
+                        }
+                        return Poll::Pending;
+                    } else if is_end && !self.stop_seen {
+                        // the ingest task may have died between a
+                        // push and its notify, shift once more for
+                        // what it left, `len` is not trusted for it
+                        self.stop_seen = true;
+                        continue;
+                    } else if is_end {
+                        self.terminated = true;
+                        return Poll::Ready(None);
+                    } else {
                         return Poll::Pending;
                     }
                 }
+                Err(err) => {
+                    event!(error, buffer_len = self.buffer.len(); "external buffer shift return error: {}", err);
+                    self.last_error.record(&err);
+                    if let Some(on_error) = &self.on_error {
+                        on_error(&err);
+                    }
+                    self.terminated = true;
+                    return Poll::Ready(None);
+                }
             }
         }
     }
+
+    /// What `poll_next` delivers, with the buffer key of the item
+    pub(crate) fn poll_keyed(&mut self, cx: &mut Context<'_>) -> Poll<Option<KeyedItem<T>>> {
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        if self.budget == 0 {
+            // let other tasks run before handing out more items
+            self.budget = self.yield_after;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        let available = match self.on_poll {
+            Some(_) => self.buffer.len() + self.ready.len(),
+            None => 0,
+        };
+        let poll = self.poll_shift(cx);
+        match poll {
+            Poll::Ready(Some(_)) => self.budget -= 1,
+            Poll::Pending => self.budget = self.yield_after,
+            Poll::Ready(None) => {}
+        }
+        if let Some(on_poll) = &self.on_poll {
+            on_poll(available, matches!(poll, Poll::Ready(Some(_))) as usize);
+        }
+        poll
+    }
+
+    /// Wait until `ready_at`, the timer is only restarted when it changes
+    fn poll_ready_timer(
+        &mut self,
+        ready_at: std::time::SystemTime,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        if self.ready_timer_at != Some(ready_at) {
+            let delay = ready_at
+                .duration_since(std::time::SystemTime::now())
+                .unwrap_or_default();
+            let deadline = std::time::Instant::now() + delay;
+            match &mut self.ready_timer {
+                Some(timer) => timer.reset(deadline),
+                None => self.ready_timer = Some(runtime::Sleep::new(deadline)),
+            }
+            self.ready_timer_at = Some(ready_at);
+        }
+        let timer = self.ready_timer.as_mut().unwrap();
+        let poll = Pin::new(timer).poll(cx);
+        if poll.is_ready() {
+            self.ready_timer_at = None;
+        }
+        poll
+    }
+
+    /// Shift items that are immediately available into `ready`, stopping at
+    /// the first one that is not so it is picked up by the next poll.
+    fn prefetch(&mut self, cx: &mut Context<'_>) {
+        while self.ready.len() < GREEDY_BATCH_CAP {
+            if let Some(result) = self.buffer.shift_now_keyed() {
+                match result {
+                    Ok(Some(item)) => {
+                        self.ready.push_back(item);
+                        continue;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        self.deferred_error = Some(e);
+                        break;
+                    }
+                }
+            }
+            let buffer = self.buffer.clone();
+            let mut shift: ShiftFuture<T> = Box::pin(async move { buffer.shift_keyed().await });
+            match shift.as_mut().poll(cx) {
+                Poll::Ready(Ok(Some(item))) => self.ready.push_back(item),
+                Poll::Ready(Ok(None)) => break,
+                Poll::Ready(Err(e)) => {
+                    self.deferred_error = Some(e);
+                    break;
+                }
+                Poll::Pending => {
+                    self.pending = Some(shift);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<T, B, S> ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    /// Put items taken out of the buffer but never delivered back in front
+    /// of it, without waiting on the buffer: the ones prefetched in greedy
+    /// mode, then the one an in flight shift may have taken. Returns the
+    /// shift still in flight and the items the buffer could not take back
+    /// right away, in order.
+    fn restore_front(&mut self) -> (Option<ShiftFuture<T>>, Vec<T>) {
+        let mut items: Vec<T> = self.ready.drain(..).map(|(_, item)| item).collect();
+        let mut pending = self.pending.take();
+        if let Some(shift) = pending.as_mut() {
+            let waker = futures::task::noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            if let Poll::Ready(result) = shift.as_mut().poll(&mut cx) {
+                items.extend(result.ok().flatten().map(|(_, item)| item));
+                pending = None;
+            }
+        }
+
+        (pending, buffer::push_front_now(&*self.buffer, items))
+    }
+}
+
+impl<T, B, S> Drop for ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    fn drop(&mut self) {
+        self.notify.close();
+
+        // Nothing here may wait on the buffer, it could block the executor
+        // dropping the stream. What can not go back in front is pushed to
+        // the tail if the buffer takes it without waiting.
+        let (pending, items) = self.restore_front();
+        for item in items {
+            buffer::push_now(&*self.buffer, item);
+        }
+        if pending.is_some() {
+            event!(warn; "Stream dropped while a shift is still in flight, use shutdown to wait for it.");
+        }
+    }
+}
+
+impl<T, B, S> fmt::Debug for ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.pending.is_some() {
+            "shifting"
+        } else if self.notify.is_stopped() {
+            "source ended"
+        } else {
+            "waiting"
+        };
+        f.debug_struct("ExternalBufferedStream")
+            .field("name", &self.name)
+            .field("state", &state)
+            .field("buffered", &(self.buffer.len() + self.ready.len()))
+            .finish()
+    }
 }
 
-#[cfg(feature = "default")]
+impl<T, B, S> Stream for ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // S is PhantomData, so here is safe to get mut
+        let this = unsafe { self.get_unchecked_mut() };
+        this.poll_keyed(cx).map(|item| item.map(|(_, item)| item))
+    }
+}
+
+impl<T, B, S> FusedStream for ExternalBufferedStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    /// True once the source ended and everything buffered was delivered
+    fn is_terminated(&self) -> bool {
+        self.terminated
+            || (self.pending.is_none()
+                && self.ready.is_empty()
+                && self.notify.is_stopped()
+                && self.buffer.is_empty())
+    }
+}
+
+#[cfg(all(feature = "sled", feature = "bincode"))]
 pub fn create_external_buffered_stream<T, S, P>(
     stream: S,
     path: P,
@@ -155,6 +728,24 @@ where
     ))
 }
 
+/// Same as `create_external_buffered_stream`, but sled is opened on a
+/// blocking thread so the calling executor is not stalled.
+#[cfg(all(feature = "sled", feature = "bincode"))]
+pub async fn create_external_buffered_stream_async<T, S, P>(
+    stream: S,
+    path: P,
+) -> Result<ExternalBufferedStream<T, ExternalBufferSled, S>, Error>
+where
+    T: ExternalBufferSerde + Send + 'static,
+    S: Stream<Item = T> + Send + Sync + 'static,
+    P: AsRef<std::path::Path>,
+{
+    Ok(ExternalBufferedStream::new(
+        stream,
+        ExternalBufferSled::open_async(path).await?,
+    ))
+}
+
 #[cfg(feature = "queue")]
 pub fn create_queued_stream<T, S>(
     stream: S,
@@ -163,8 +754,942 @@ where
     T: Ord + Send + 'static,
     S: Stream<Item = T> + Send + Sync + 'static,
 {
-    Ok(ExternalBufferedStream::new(
-        stream,
-        ExternalBufferQueue::new(),
-    ))
+    create_queued_stream_with(stream, ExternalBufferQueue::new())
+}
+
+/// Same as `create_queued_stream` over a queue the caller already filled,
+/// e.g. with `ExternalBufferQueue::from`. Its items are drained along with
+/// the sourced ones in priority order.
+#[cfg(feature = "queue")]
+pub fn create_queued_stream_with<T, S>(
+    stream: S,
+    buffer: ExternalBufferQueue<T>,
+) -> Result<ExternalBufferedStream<T, ExternalBufferQueue<T>, S>, Error>
+where
+    T: Ord + Send + 'static,
+    S: Stream<Item = T> + Send + Sync + 'static,
+{
+    Ok(ExternalBufferedStream::new(stream, buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+    use std::sync::Mutex;
+
+    /// A buffer that fails every push
+    struct FailingBuffer;
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for FailingBuffer {
+        async fn push(&self, _item: i32) -> Result<(), Error> {
+            Err(Error::MutexError)
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            Ok(None)
+        }
+
+        fn len(&self) -> usize {
+            0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_error_called_on_push_failure() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let errors_clone = errors.clone();
+
+        let mut stream =
+            ExternalBufferedStream::builder(futures::stream::iter(vec![1, 2, 3]), FailingBuffer)
+                .on_error(move |err| {
+                    errors_clone.lock().unwrap().push(format!("{:?}", err));
+                })
+                .build();
+
+        // Ingest stops on the first failed push, so the stream just ends
+        assert_eq!(stream.next().await, None);
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0], format!("{:?}", Error::MutexError));
+    }
+
+    #[tokio::test]
+    async fn test_health_after_ingest_error() {
+        let mut stream =
+            ExternalBufferedStream::new(futures::stream::iter(vec![1, 2, 3]), FailingBuffer);
+        let health = stream.health();
+        assert_eq!(health.backlog, 0);
+
+        assert_eq!(stream.next().await, None);
+        let health = stream.health();
+        assert!(!health.ingest_alive);
+        assert_eq!(health.last_error, Some(Error::MutexError.to_string()));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_ingest_error_event_fields() {
+        let mut stream =
+            ExternalBufferedStream::builder(futures::stream::iter(vec![1, 2, 3]), FailingBuffer)
+                .name("orders")
+                .build();
+        assert_eq!(stream.next().await, None);
+
+        assert!(logs_contain("external_buffered_stream{name=\"orders\"}"));
+        assert!(logs_contain("buffer_len=0"));
+        assert!(logs_contain("Failed to push item to buffer: MutexError"));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_dropped_consumer_ends_ingest_quietly() {
+        let source = Box::pin(futures::stream::iter(0..).then(|i| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            i
+        }));
+        let mut stream = ExternalBufferedStream::new(source, MemoryBuffer::default());
+        assert_eq!(stream.next().await, Some(0));
+        drop(stream);
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(logs_contain(
+            "Consumer of external buffer stream is dropped."
+        ));
+        assert!(logs_contain("Source of external buffer stream is ended."));
+        assert!(!logs_contain("ERROR"));
+    }
+
+    #[cfg(feature = "queue")]
+    #[tokio::test]
+    async fn test_queued_stream_with_seeded_queue() {
+        let seeded = ExternalBufferQueue::from(vec![90, 100]);
+        let stream =
+            create_queued_stream_with(futures::stream::iter(vec![1, 3, 2]), seeded).unwrap();
+
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(&items[..2], &[100, 90]);
+        let mut rest = items[2..].to_vec();
+        rest.sort();
+        assert_eq!(rest, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_spawn_is_surfaced() {
+        fn failing_spawner(_: futures::future::BoxFuture<'static, ()>) -> Result<(), Error> {
+            Err(make_custom_error(std::io::Error::other("no threads left")))
+        }
+
+        let mut builder = ExternalBufferedStream::builder(
+            futures::stream::iter(vec![1]),
+            MemoryBuffer::default(),
+        );
+        builder.spawner = failing_spawner;
+        assert!(builder.try_build().is_err());
+
+        let mut builder = ExternalBufferedStream::builder(
+            futures::stream::iter(vec![1]),
+            MemoryBuffer::default(),
+        );
+        builder.spawner = failing_spawner;
+        let mut stream = builder.build();
+        let health = stream.health();
+        assert!(!health.ingest_alive);
+        assert!(health.last_error.unwrap().contains("no threads left"));
+        assert_eq!(stream.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_notify_and_buffer_with_slow_consumer() {
+        let mut stream =
+            ExternalBufferedStream::builder(futures::stream::iter(0..30), MemoryBuffer::default())
+                .notify_capacity(1)
+                .buffer_capacity(5)
+                .build();
+
+        let drained = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            let mut items = Vec::new();
+            while let Some(item) = stream.next().await {
+                assert!(stream.health().backlog <= 5);
+                items.push(item);
+                tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+            }
+            items
+        })
+        .await
+        .expect("ingest and consumer deadlocked");
+        assert_eq!(drained, (0..30).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_source_panic_ends_stream() {
+        let source = futures::stream::iter(0..5).map(|i| {
+            if i == 3 {
+                panic!("source broke at {}", i);
+            }
+            i
+        });
+        let mut stream = ExternalBufferedStream::new(source, MemoryBuffer::default());
+
+        let items = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            let mut items = Vec::new();
+            while let Some(item) = stream.next().await {
+                items.push(item);
+            }
+            items
+        })
+        .await
+        .expect("stream hangs after the source panicked");
+        assert_eq!(items, vec![0, 1, 2]);
+        assert_eq!(
+            stream.health().last_error.as_deref(),
+            Some("Source stream panicked: source broke at 3")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debug_output() {
+        let stream = ExternalBufferedStream::builder(
+            futures::stream::pending(),
+            MemoryBuffer::with_items([1, 2, 3]),
+        )
+        .name("orders")
+        .build();
+
+        assert_eq!(stream.name(), Some("orders"));
+        let output = format!("{:?}", stream);
+        assert!(output.contains("\"orders\""), "{}", output);
+        assert!(output.contains("buffered: 3"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn test_burst_delivers_all_items() {
+        let buffer = MemoryBuffer::default();
+        let stream = ExternalBufferedStream::new(futures::stream::iter(0..1000), buffer);
+
+        let notify = stream.notify.clone();
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+
+        // notifications are coalesced into a single counter, nothing piles up
+        assert_eq!(notify.take(), 0);
+        assert!(notify.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn test_pressure_published_when_filling() {
+        let (start_tx, start_rx) = futures::channel::oneshot::channel::<()>();
+        let source =
+            futures::FutureExt::into_stream(start_rx).flat_map(|_| futures::stream::iter(0..8));
+
+        let stream =
+            ExternalBufferedStream::new(source, BoundedBuffer::new(MemoryBuffer::default(), 10));
+        let mut pressure = stream.pressure();
+        start_tx.send(()).unwrap();
+
+        let mut published = Vec::new();
+        let reached = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while let Some(ratio) = pressure.next().await {
+                published.push(ratio);
+                if ratio >= 0.8 {
+                    return ratio;
+                }
+            }
+            0.0
+        })
+        .await
+        .unwrap();
+
+        assert!(reached >= 0.8);
+        // a ratio the receiver did not get to in time is replaced, not queued
+        assert!(published == vec![0.5, 0.8] || published == vec![0.8]);
+        assert_eq!(pressure.latest(), 0.8);
+    }
+
+    /// A buffer whose shift yields once after taking the item out
+    struct YieldingBuffer {
+        inner: MemoryBuffer,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for YieldingBuffer {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            self.inner.push(item).await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            let item = self.inner.shift().await?;
+            let mut yielded = false;
+            futures::future::poll_fn(|cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            Ok(item)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_restores_item_of_unfinished_shift() {
+        let buffer = YieldingBuffer {
+            inner: MemoryBuffer::with_items([1]),
+        };
+        let mut stream = ExternalBufferedStream::new(futures::stream::pending(), buffer);
+
+        // Start a shift, the item is taken out but not returned yet
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(stream.buffer.len(), 0);
+
+        let buffer = stream.buffer.clone();
+        drop(stream);
+        assert_eq!(buffer.inner.items.lock().unwrap().pop_front(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_drop_restores_prefetched_items_in_front() {
+        let mut stream = ExternalBufferedStream::builder(
+            futures::stream::pending(),
+            MemoryBuffer::with_items(0..5),
+        )
+        .greedy(true)
+        .build();
+        assert_eq!(stream.next().await, Some(0));
+        assert_eq!(stream.buffer.len(), 0);
+
+        let buffer = stream.buffer.clone();
+        buffer.push(9).await.unwrap();
+        drop(stream);
+        let items: Vec<i32> = buffer.items.lock().unwrap().drain(..).collect();
+        assert_eq!(items, vec![1, 2, 3, 4, 9]);
+    }
+
+    /// A buffer whose shift takes a while after taking the item out
+    struct SlowShiftBuffer {
+        inner: MemoryBuffer,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for SlowShiftBuffer {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            self.inner.push(item).await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            let item = self.inner.shift().await?;
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            Ok(item)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_unfinished_shift() {
+        let buffer = SlowShiftBuffer {
+            inner: MemoryBuffer::with_items([1, 2]),
+        };
+        let mut stream = ExternalBufferedStream::new(futures::stream::pending(), buffer);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(stream.buffer.len(), 1);
+
+        let buffer = stream.buffer.clone();
+        stream.shutdown().await.unwrap();
+        let items: Vec<i32> = buffer.inner.items.lock().unwrap().drain(..).collect();
+        assert_eq!(items, vec![2, 1]);
+    }
+
+    /// Poll a stream of a pre-filled buffer to the end, counting the times
+    /// the consumer has to wait for a wakeup
+    fn count_wakeups(
+        mut stream: ExternalBufferedStream<i32, YieldingBuffer, futures::stream::Empty<i32>>,
+    ) -> (Vec<i32>, usize) {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut items = Vec::new();
+        let mut wakeups = 0;
+        loop {
+            match stream.poll_next_unpin(&mut cx) {
+                Poll::Ready(Some(item)) => items.push(item),
+                Poll::Ready(None) => break,
+                Poll::Pending => wakeups += 1,
+            }
+        }
+        (items, wakeups)
+    }
+
+    #[tokio::test]
+    async fn test_greedy_mode_needs_fewer_wakeups() {
+        let stream = |greedy| {
+            let buffer = YieldingBuffer {
+                inner: MemoryBuffer::with_items(0..10),
+            };
+            ExternalBufferedStream::builder(futures::stream::empty(), buffer)
+                .greedy(greedy)
+                .build()
+        };
+
+        // let the source end so the streams finish once drained
+        let default_stream = stream(false);
+        let greedy_stream = stream(true);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let (items, default_wakeups) = count_wakeups(default_stream);
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        let (items, greedy_wakeups) = count_wakeups(greedy_stream);
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+
+        assert!(
+            greedy_wakeups < default_wakeups,
+            "greedy {} vs default {}",
+            greedy_wakeups,
+            default_wakeups
+        );
+    }
+
+    /// A buffer whose flush never completes
+    struct StuckFlushBuffer {
+        inner: MemoryBuffer,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for StuckFlushBuffer {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            self.inner.push(item).await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            self.inner.shift().await
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        async fn flush(&self) -> Result<(), Error> {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_with_timeout() {
+        let stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            MemoryBuffer::with_items([1, 2]),
+        );
+        let buffer = stream.buffer.clone();
+        stream
+            .shutdown_with_timeout(std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(buffer.len(), 2);
+
+        let buffer = StuckFlushBuffer {
+            inner: MemoryBuffer::with_items([1]),
+        };
+        let stream = ExternalBufferedStream::new(futures::stream::pending(), buffer);
+        let result = stream
+            .shutdown_with_timeout(std::time::Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::ShutdownTimeout)));
+    }
+
+    /// A buffer whose pushes never complete
+    struct StuckPushBuffer {
+        inner: MemoryBuffer,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for StuckPushBuffer {
+        async fn push(&self, _item: i32) -> Result<(), Error> {
+            futures::future::pending().await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            self.inner.shift().await
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_times_out_on_stuck_restore() {
+        let buffer = StuckPushBuffer {
+            inner: MemoryBuffer::with_items([1, 2, 3]),
+        };
+        let mut stream = ExternalBufferedStream::builder(futures::stream::pending(), buffer)
+            .greedy(true)
+            .build();
+        assert_eq!(stream.next().await, Some(1));
+
+        // the prefetched items can only go back with a push
+        let result = stream
+            .shutdown_with_timeout(std::time::Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::ShutdownTimeout)));
+    }
+
+    /// A buffer counting its shifts
+    #[derive(Default)]
+    struct CountingBuffer {
+        inner: MemoryBuffer,
+        shifts: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for CountingBuffer {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            self.inner.push(item).await
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            self.shifts
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.inner.shift().await
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    /// A buffer blocking for `delay` to prepare a push, like a heavy
+    /// serialization
+    #[derive(Default)]
+    struct SlowPushBuffer {
+        inner: MemoryBuffer,
+        delay: std::time::Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl ExternalBuffer<i32> for SlowPushBuffer {
+        async fn push(&self, item: i32) -> Result<(), Error> {
+            std::thread::sleep(self.delay);
+            self.inner.push(item).await
+        }
+
+        fn prepare_push(&self, item: i32) -> Result<PreparedPush<i32>, Error> {
+            std::thread::sleep(self.delay);
+            Ok(PreparedPush::Item(item))
+        }
+
+        async fn push_prepared(&self, prepared: PreparedPush<i32>) -> Result<(), Error> {
+            match prepared {
+                PreparedPush::Item(item) => self.inner.push(item).await,
+                PreparedPush::Serialized(_) => unreachable!(),
+            }
+        }
+
+        async fn shift(&self) -> Result<Option<i32>, Error> {
+            self.inner.shift().await
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ingest_concurrency_prepares_in_parallel() {
+        async fn ingest(concurrency: usize) -> std::time::Duration {
+            let buffer = SlowPushBuffer {
+                delay: std::time::Duration::from_millis(25),
+                ..Default::default()
+            };
+            let started = std::time::Instant::now();
+            let stream = ExternalBufferedStream::builder(futures::stream::iter(0..8), buffer)
+                .ingest_concurrency(concurrency)
+                .build();
+            let items: Vec<i32> = stream.collect().await;
+            assert_eq!(items, (0..8).collect::<Vec<_>>());
+            started.elapsed()
+        }
+
+        let sequential = ingest(1).await;
+        let concurrent = ingest(4).await;
+        assert!(
+            concurrent * 2 < sequential,
+            "{:?} with 4 concurrent pushes, {:?} with one",
+            concurrent,
+            sequential
+        );
+    }
+
+    #[tokio::test]
+    async fn test_on_poll_observes_drain() {
+        let polls = Arc::new(Mutex::new(Vec::new()));
+        let polls_clone = polls.clone();
+        let stream = ExternalBufferedStream::builder(
+            futures::stream::empty(),
+            MemoryBuffer::with_items([1, 2, 3]),
+        )
+        .on_poll(move |available, drained| {
+            polls_clone.lock().unwrap().push((available, drained));
+        })
+        .build();
+
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![1, 2, 3]);
+
+        let polls = polls.lock().unwrap();
+        assert_eq!(polls[..3], [(3, 1), (2, 1), (1, 1)]);
+        // waiting for the source to end and the final `None`
+        assert!(polls[3..].iter().all(|poll| *poll == (0, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_fused_after_completion() {
+        let mut stream = ExternalBufferedStream::new(
+            futures::stream::iter(vec![1, 2]),
+            CountingBuffer::default(),
+        );
+
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            items.push(item);
+        }
+        assert_eq!(items, vec![1, 2]);
+        assert!(stream.is_terminated());
+
+        let shifts = stream
+            .buffer
+            .shifts
+            .load(std::sync::atomic::Ordering::Relaxed);
+        for _ in 0..3 {
+            assert_eq!(stream.next().await, None);
+        }
+        assert_eq!(
+            stream
+                .buffer
+                .shifts
+                .load(std::sync::atomic::Ordering::Relaxed),
+            shifts
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notify_capacity_blocks_ingest() {
+        let pulled = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let pulled_clone = pulled.clone();
+        let source = futures::stream::iter(0..10).inspect(move |_| {
+            pulled_clone.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let mut stream = ExternalBufferedStream::builder(source, MemoryBuffer::default())
+            .notify_capacity(1)
+            .build();
+
+        // nobody consumes, ingestion stops once the notification is not taken
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(stream.buffer.len(), 2);
+        assert_eq!(pulled.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            items.push(item);
+        }
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_ready_resolves_for_stream_with_data() {
+        let empty =
+            ExternalBufferedStream::new(futures::stream::pending(), MemoryBuffer::default());
+        let populated =
+            ExternalBufferedStream::new(futures::stream::pending(), MemoryBuffer::with_items([1]));
+        assert!(!empty.has_pending());
+        assert!(populated.has_pending());
+
+        let which = tokio::select! {
+            _ = empty.ready() => "empty",
+            _ = populated.ready() => "populated",
+        };
+        assert_eq!(which, "populated");
+
+        let waited =
+            tokio::time::timeout(std::time::Duration::from_millis(50), empty.ready()).await;
+        assert!(waited.is_err());
+    }
+
+    #[cfg(feature = "stats")]
+    #[tokio::test]
+    async fn test_latency_stats() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_latency_stats();
+        let mut stream = ExternalBufferedStream::new(futures::stream::iter(0..5u32), buffer);
+
+        // items are pushed right away but only shifted after the delay
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            items.push(item);
+        }
+        assert_eq!(items.len(), 5);
+
+        let stats = stream.latency_stats().unwrap();
+        assert_eq!(stats.count, 5);
+        assert!(
+            stats.p50 >= std::time::Duration::from_millis(90),
+            "{:?}",
+            stats
+        );
+        assert!(stats.p50 < std::time::Duration::from_secs(2), "{:?}", stats);
+    }
+
+    #[tokio::test]
+    async fn test_prefill() {
+        let mut stream =
+            ExternalBufferedStream::new(futures::stream::empty(), MemoryBuffer::default());
+        stream.prefill(1..=5).await.unwrap();
+
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            items.push(item);
+        }
+        assert_eq!(items, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_drains_buffer_after_ingest_died() {
+        let buffer = Arc::new(MemoryBuffer::default());
+        let notify = Arc::new(Notify::default());
+        let mut stream = ExternalBufferedStream::<i32, _, futures::stream::Empty<i32>>::from_parts(
+            buffer.clone(),
+            notify.clone(),
+        );
+        assert!(futures::poll!(stream.next()).is_pending());
+
+        // pushed but never notified, then the ingest task is gone
+        for item in 1..=3 {
+            buffer.push(item).await.unwrap();
+        }
+        notify.stop();
+
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_ends_when_len_overcounts() {
+        // claims an item more than it holds and counts its empty shifts
+        #[derive(Default)]
+        struct Overcounting {
+            inner: MemoryBuffer,
+            empty_shifts: std::sync::atomic::AtomicUsize,
+        }
+
+        #[async_trait::async_trait]
+        impl ExternalBuffer<i32> for Overcounting {
+            async fn push(&self, item: i32) -> Result<(), Error> {
+                self.inner.push(item).await
+            }
+
+            async fn shift(&self) -> Result<Option<i32>, Error> {
+                let item = self.inner.shift().await?;
+                if item.is_none() {
+                    let empty = self
+                        .empty_shifts
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    assert!(empty < 10, "the stream keeps shifting an empty buffer");
+                }
+                Ok(item)
+            }
+
+            fn len(&self) -> usize {
+                self.inner.len() + 1
+            }
+        }
+
+        let stream =
+            ExternalBufferedStream::new(futures::stream::iter(1..=3), Overcounting::default());
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![1, 2, 3]);
+    }
+
+    #[cfg(feature = "queue")]
+    #[tokio::test]
+    async fn test_queue_shifts_without_allocating() {
+        let mut stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            ExternalBufferQueue::from((0..100).collect::<Vec<_>>()),
+        );
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        // the first poll sets the stream up
+        assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Ready(Some(99)));
+
+        let (items, allocations) = crate::test_util::count_allocations(|| {
+            let mut items = [0; 99];
+            for item in &mut items {
+                let Poll::Ready(Some(next)) = stream.poll_next_unpin(&mut cx) else {
+                    panic!("item not delivered right away");
+                };
+                *item = next;
+            }
+            items
+        });
+        // no shift future is boxed per item
+        assert_eq!(allocations, 0);
+        assert!(items.iter().rev().copied().eq(0..99));
+    }
+
+    #[tokio::test]
+    async fn test_prefilled_buffer_drains_in_one_burst() {
+        struct CountingWaker(std::sync::atomic::AtomicUsize);
+
+        impl futures::task::ArcWake for CountingWaker {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self
+                    .0
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        let mut stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            MemoryBuffer::with_items(0..100),
+        );
+        let counter = Arc::new(CountingWaker(Default::default()));
+        let waker = futures::task::waker(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // every buffered item is shifted right away, nothing waits on notify
+        for expected in 0..100 {
+            assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Ready(Some(expected)));
+        }
+        assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Pending);
+        assert_eq!(counter.0.load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_yields_during_large_drain() {
+        let mut stream =
+            ExternalBufferedStream::new(futures::stream::empty(), MemoryBuffer::default());
+        stream.prefill(0..10_000).await.unwrap();
+
+        let progressed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let progressed_clone = progressed.clone();
+        tokio::spawn(async move {
+            progressed_clone.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let mut delivered = 0;
+        while stream.next().await.is_some() {
+            delivered += 1;
+            if progressed.load(std::sync::atomic::Ordering::Relaxed) {
+                break;
+            }
+        }
+        // the other task ran once the stream yielded after the first batch
+        assert!(delivered <= DEFAULT_YIELD_AFTER + 1);
+    }
+
+    #[tokio::test]
+    async fn test_recv() {
+        let mut by_recv = ExternalBufferedStream::new(
+            futures::stream::iter(vec![1, 2, 3]),
+            MemoryBuffer::default(),
+        );
+        let mut by_next = ExternalBufferedStream::new(
+            futures::stream::iter(vec![1, 2, 3]),
+            MemoryBuffer::default(),
+        );
+
+        let mut received = Vec::new();
+        while let Some(item) = by_recv.recv().await {
+            received.push(item);
+        }
+        let mut nexted = Vec::new();
+        while let Some(item) = by_next.next().await {
+            nexted.push(item);
+        }
+        assert_eq!(received, nexted);
+        assert_eq!(by_recv.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_take_n_keeps_stream_usable() {
+        let mut stream =
+            ExternalBufferedStream::new(futures::stream::iter(1..=5), MemoryBuffer::default());
+
+        let first: Vec<_> = stream.take_n(3).collect().await;
+        assert_eq!(first, vec![1, 2, 3]);
+
+        let mut rest = Vec::new();
+        while let Some(item) = stream.next().await {
+            rest.push(item);
+        }
+        assert_eq!(rest, vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_sink() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let sink = futures::sink::unfold((), move |_, item: i32| {
+            let received = received_clone.clone();
+            async move {
+                // a slow downstream
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                received.lock().unwrap().push(item);
+                Ok::<_, Error>(())
+            }
+        });
+
+        let stream =
+            ExternalBufferedStream::new(futures::stream::iter(1..=10), MemoryBuffer::default());
+        let started = std::time::Instant::now();
+        stream.forward_to(sink).await.unwrap();
+
+        assert_eq!(*received.lock().unwrap(), (1..=10).collect::<Vec<_>>());
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "rt-tokio")]
+    #[tokio::test]
+    async fn test_cancellation_token_drains_then_finishes() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let token = tokio_util::sync::CancellationToken::new();
+        let mut stream = ExternalBufferedStream::builder(rx, MemoryBuffer::default())
+            .cancellation_token(token.clone())
+            .build();
+
+        for item in 1..=3 {
+            tx.unbounded_send(item).unwrap();
+        }
+        assert_eq!(stream.next().await, Some(1));
+        while stream.buffer.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        token.cancel();
+
+        // the sender is still alive, the stream ends because of the token
+        let rest: Vec<i32> = stream.collect().await;
+        assert_eq!(rest, vec![2, 3]);
+        drop(tx);
+    }
 }
@@ -0,0 +1,226 @@
+use std::task::{Poll, Waker};
+
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use futures::task::AtomicWaker;
+
+/// Signals between the ingest task and the stream consumer.
+///
+/// Pushes are coalesced into a single pending count instead of one message
+/// per item, so the notify state stays the same size however many items
+/// are buffered.
+#[derive(Default)]
+pub(crate) struct Notify {
+    // items pushed since the consumer last looked
+    pending: AtomicUsize,
+    waker: AtomicWaker,
+    // the ingest task waiting in `wait_below`
+    ingest_waker: AtomicWaker,
+    // set once the ingest task stopped pushing items
+    stop_flag: AtomicBool,
+    // set once the consumer side is dropped
+    closed: AtomicBool,
+}
+
+impl Notify {
+    /// Called by the ingest task after an item is pushed
+    pub(crate) fn notify(&self) {
+        // Release publishes the push to whoever takes this count
+        self.pending.fetch_add(1, Ordering::AcqRel);
+        self.waker.wake();
+    }
+
+    /// Called when no more items will be pushed
+    pub(crate) fn stop(&self) {
+        // Release makes every notify before the stop visible to a consumer
+        // that observes the flag
+        self.stop_flag.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stop_flag.load(Ordering::Acquire)
+    }
+
+    /// Take all pending notifications, returns how many there were
+    pub(crate) fn take(&self) -> usize {
+        // Acquire pairs with the release in `notify`, the swap is a
+        // read-modify-write so no count is lost between take and notify
+        let taken = self.pending.swap(0, Ordering::AcqRel);
+        self.ingest_waker.wake();
+        taken
+    }
+
+    /// Called by the ingest task to wait until fewer than `capacity`
+    /// notifications are pending, or the consumer is gone
+    pub(crate) async fn wait_below(&self, capacity: usize) {
+        futures::future::poll_fn(|cx| {
+            self.ingest_waker.register(cx.waker());
+            if self.pending.load(Ordering::Acquire) < capacity || self.is_closed() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Called by the consumer after shifting items, the ingest task may be
+    /// waiting in `wait_for_room` for the buffer to shrink
+    pub(crate) fn shifted(&self) {
+        self.ingest_waker.wake();
+    }
+
+    /// Called by the ingest task to wait until `has_room` holds, or the
+    /// consumer is gone
+    pub(crate) async fn wait_for_room(&self, has_room: impl Fn() -> bool) {
+        futures::future::poll_fn(|cx| {
+            self.ingest_waker.register(cx.waker());
+            if has_room() || self.is_closed() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    pub(crate) fn register(&self, waker: &Waker) {
+        self.waker.register(waker);
+    }
+
+    pub(crate) fn close(&self) {
+        // only tells the ingest task to stop early, no data depends on it
+        self.closed.store(true, Ordering::Release);
+        self.ingest_waker.wake();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+/// Stops the notify when dropped, so consumers are not left waiting if the
+/// ingest task ends in any way, including a panic.
+pub(crate) struct StopGuard<'a>(pub(crate) &'a Notify);
+
+impl Drop for StopGuard<'_> {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+/// Run with `RUSTFLAGS="--cfg loom" cargo test --lib loom`
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::atomic::AtomicU64;
+    use loom::sync::{Arc, Mutex, RwLock};
+    use loom::thread;
+
+    #[test]
+    fn test_no_push_lost_before_stop() {
+        loom::model(|| {
+            let notify = Arc::new(Notify::default());
+            // stands in for the buffer, counts pushed items
+            let pushed = Arc::new(AtomicUsize::new(0));
+
+            let producer = {
+                let notify = notify.clone();
+                let pushed = pushed.clone();
+                thread::spawn(move || {
+                    for _ in 0..2 {
+                        pushed.fetch_add(1, Ordering::Release);
+                        notify.notify();
+                    }
+                    notify.stop();
+                })
+            };
+
+            // same steps as the stream consumer once the buffer is empty
+            let shifted = loop {
+                let shifted = pushed.load(Ordering::Acquire);
+                let is_end = notify.is_stopped();
+                if notify.take() > 0 {
+                    continue;
+                } else if is_end {
+                    break shifted;
+                }
+                thread::yield_now();
+            };
+
+            assert_eq!(shifted, 2);
+            producer.join().unwrap();
+        });
+    }
+
+    /// The key protocol of `ExternalBufferSled`: a push takes its key from
+    /// the tail and stores the item after, a shift removes the item at the
+    /// head and moves the head past it. A missing head key is only skipped
+    /// once the pushes in flight are done, the `appending` lock.
+    struct SledKeys {
+        head: AtomicU64,
+        tail: AtomicU64,
+        // stands in for the db, whether the item of a key is stored
+        stored: Mutex<[bool; 2]>,
+        appending: RwLock<()>,
+    }
+
+    impl SledKeys {
+        fn push(&self) {
+            let _appending = self.appending.read().unwrap();
+            let key = self.tail.fetch_add(1, Ordering::AcqRel);
+            self.stored.lock().unwrap()[key as usize] = true;
+        }
+
+        fn shift(&self) -> Option<u64> {
+            loop {
+                let head = self.head.load(Ordering::Acquire);
+                let tail = self.tail.load(Ordering::Acquire);
+                if head >= tail {
+                    return None;
+                }
+                let removed = std::mem::take(&mut self.stored.lock().unwrap()[head as usize]);
+                if removed {
+                    self.head.fetch_max(head + 1, Ordering::AcqRel);
+                    return Some(head);
+                }
+                drop(self.appending.write().unwrap());
+                if self.stored.lock().unwrap()[head as usize] {
+                    continue;
+                }
+                self.head.fetch_max(head + 1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shift_racing_push_loses_no_key() {
+        loom::model(|| {
+            let keys = Arc::new(SledKeys {
+                head: AtomicU64::new(0),
+                tail: AtomicU64::new(0),
+                stored: Mutex::new([false; 2]),
+                appending: RwLock::new(()),
+            });
+
+            let producer = {
+                let keys = keys.clone();
+                thread::spawn(move || {
+                    keys.push();
+                    keys.push();
+                })
+            };
+
+            // a shift while the pushes run, then whatever is left
+            let mut shifted: Vec<u64> = keys.shift().into_iter().collect();
+            producer.join().unwrap();
+            shifted.extend(std::iter::from_fn(|| keys.shift()));
+
+            assert_eq!(shifted, vec![0, 1]);
+        });
+    }
+}
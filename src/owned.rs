@@ -0,0 +1,91 @@
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+
+use crate::{ExternalBuffer, ExternalBufferedStreamBuilder};
+
+/// Turn a borrowed source item into the owned item the buffer stores, see
+/// `ExternalBufferedStreamBuilder::from_borrowed`
+pub trait IntoOwned<T> {
+    fn into_owned(self) -> T;
+}
+
+impl<T: Clone> IntoOwned<T> for &T {
+    fn into_owned(self) -> T {
+        self.clone()
+    }
+}
+
+impl<T: Clone> IntoOwned<Vec<T>> for &[T] {
+    fn into_owned(self) -> Vec<T> {
+        self.to_vec()
+    }
+}
+
+impl IntoOwned<String> for &str {
+    fn into_owned(self) -> String {
+        self.to_owned()
+    }
+}
+
+/// Source returned by `ExternalBufferedStreamBuilder::from_borrowed`, yields
+/// the owned form of every item of `S`
+pub struct IntoOwnedStream<S, T> {
+    source: S,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<S, T> Stream for IntoOwnedStream<S, T>
+where
+    S: Stream + Unpin,
+    S::Item: IntoOwned<T>,
+{
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let item = futures::ready!(Pin::new(&mut self.source).poll_next(cx));
+        Poll::Ready(item.map(IntoOwned::into_owned))
+    }
+}
+
+impl<T, B, S> ExternalBufferedStreamBuilder<T, B, IntoOwnedStream<S, T>>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream + Unpin + Send + 'static,
+    S::Item: IntoOwned<T>,
+{
+    /// Builder for a source of borrowed items, e.g. slices of a shared
+    /// arena. The ingest task turns each into the owned `T` with `IntoOwned`
+    /// right before the push, so the source does not have to be mapped.
+    pub fn from_borrowed(source: S, buffer: B) -> Self {
+        let source = IntoOwnedStream {
+            source,
+            _item: PhantomData,
+        };
+        Self::new(source, buffer)
+    }
+}
+
+#[cfg(all(test, feature = "queue"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferVecDeque;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_borrowed_slices_are_buffered_owned() {
+        let arena: &'static [u8] = Box::leak(vec![1, 2, 3, 4, 5, 6, 7].into_boxed_slice());
+
+        let stream = ExternalBufferedStreamBuilder::from_borrowed(
+            futures::stream::iter(arena.chunks(3)),
+            ExternalBufferVecDeque::<Vec<u8>>::new(),
+        )
+        .build();
+
+        let items: Vec<Vec<u8>> = stream.collect().await;
+        assert_eq!(items, vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+    }
+}
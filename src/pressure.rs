@@ -0,0 +1,143 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+use futures::Stream;
+
+pub(crate) const DEFAULT_PRESSURE_THRESHOLDS: [f32; 3] = [0.5, 0.8, 1.0];
+
+/// Receiver of `ExternalBufferedStream::pressure`. Like a watch channel it
+/// only keeps the latest ratio, a receiver that falls behind skips the
+/// values in between instead of piling them up. Ends once the stream and
+/// its ingest task are gone.
+pub struct PressureReceiver {
+    slot: Arc<Slot>,
+}
+
+struct Slot {
+    // the latest ratio not received yet
+    value: Mutex<Option<f32>>,
+    // the latest ratio published, received or not
+    latest: Mutex<f32>,
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl PressureReceiver {
+    /// The latest ratio published, 0.0 before the first one
+    pub fn latest(&self) -> f32 {
+        *self.slot.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Stream for PressureReceiver {
+    type Item = f32;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<f32>> {
+        let slot = &self.slot;
+        // register first, so a value published in between is not missed
+        slot.waker.register(cx.waker());
+        let value = slot.value.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match value {
+            Some(ratio) => Poll::Ready(Some(ratio)),
+            None if slot.closed.load(Ordering::Acquire) => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Publishes the fill ratio of a bounded buffer to subscribers whenever it
+/// crosses one of the thresholds, in either direction.
+pub(crate) struct Pressure {
+    thresholds: Vec<f32>,
+    // number of thresholds reached by the last published ratio
+    level: AtomicUsize,
+    subscribers: Mutex<Vec<Weak<Slot>>>,
+}
+
+impl Pressure {
+    pub(crate) fn new(mut thresholds: Vec<f32>) -> Self {
+        thresholds.sort_by(f32::total_cmp);
+        Self {
+            thresholds,
+            level: AtomicUsize::new(0),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> PressureReceiver {
+        let slot = Arc::new(Slot {
+            value: Mutex::new(None),
+            latest: Mutex::new(0.0),
+            closed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(Arc::downgrade(&slot));
+        }
+        PressureReceiver { slot }
+    }
+
+    /// Check the current fill ratio, buffers without capacity never publish
+    pub(crate) fn update(&self, len: usize, capacity: Option<usize>) {
+        let Some(capacity) = capacity.filter(|capacity| *capacity > 0) else {
+            return;
+        };
+        let ratio = (len as f32 / capacity as f32).min(1.0);
+        let level = self.thresholds.iter().filter(|t| ratio >= **t).count();
+        if self.level.swap(level, Ordering::AcqRel) == level {
+            return;
+        }
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|slot| {
+                let Some(slot) = slot.upgrade() else {
+                    return false;
+                };
+                *slot.latest.lock().unwrap_or_else(|e| e.into_inner()) = ratio;
+                // overwrites a ratio the receiver did not get to yet
+                *slot.value.lock().unwrap_or_else(|e| e.into_inner()) = Some(ratio);
+                slot.waker.wake();
+                true
+            });
+        }
+    }
+}
+
+impl Drop for Pressure {
+    fn drop(&mut self) {
+        let subscribers = self
+            .subscribers
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner());
+        for slot in subscribers.drain(..).filter_map(|slot| slot.upgrade()) {
+            slot.closed.store(true, Ordering::Release);
+            slot.waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_receiver_only_gets_the_latest_ratio() {
+        let pressure = Pressure::new(DEFAULT_PRESSURE_THRESHOLDS.to_vec());
+        let mut receiver = pressure.subscribe();
+
+        // nobody receives while the buffer fills and drains again
+        for len in [5, 8, 10, 8, 5, 0, 8] {
+            pressure.update(len, Some(10));
+        }
+        assert_eq!(receiver.latest(), 0.8);
+
+        drop(pressure);
+        let received: Vec<f32> = receiver.by_ref().collect().await;
+        assert_eq!(received, vec![0.8]);
+        assert_eq!(receiver.latest(), 0.8);
+    }
+}
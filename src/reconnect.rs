@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{stream, Future, Stream};
+
+use crate::ingest::{ingest, Ingest, IngestEnd, IngestOptions};
+use crate::notify::{Notify, StopGuard};
+use crate::trace::event;
+use crate::{runtime, ExternalBuffer, ExternalBufferedStream};
+
+/// When `create_reconnecting_stream` opens a new source after one ended
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    backoff: Duration,
+    max_reconnects: Option<usize>,
+}
+
+impl ReconnectPolicy {
+    /// Reconnect `backoff` after a source ended, without limit
+    pub fn new(backoff: Duration) -> Self {
+        Self {
+            backoff,
+            max_reconnects: None,
+        }
+    }
+
+    /// Give up after `max` reconnects, the stream then ends once the buffer
+    /// is drained
+    pub fn max_reconnects(mut self, max: usize) -> Self {
+        self.max_reconnects = Some(max);
+        self
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1))
+    }
+}
+
+/// Buffer a source that is opened again by `make_source` whenever it ends,
+/// e.g. a network connection, instead of ending the stream with it. The
+/// stream only ends when `make_source` returns `None` or `policy` gives up.
+pub fn create_reconnecting_stream<T, S, B, F, Fut>(
+    make_source: F,
+    buffer: B,
+    policy: ReconnectPolicy,
+) -> ExternalBufferedStream<T, B, stream::Empty<T>>
+where
+    T: Send + 'static,
+    S: Stream<Item = T> + Send,
+    B: ExternalBuffer<T> + 'static,
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Option<S>> + Send,
+{
+    let buffer = Arc::new(buffer);
+    let notify = Arc::new(Notify::default());
+
+    let ingest_buffer = buffer.clone();
+    let ingest_notify = notify.clone();
+    let spawned = runtime::spawn(async move {
+        let notify = ingest_notify;
+        let _stop = StopGuard(&notify);
+        let mut reconnects = 0;
+        while let Some(source) = make_source().await {
+            let mut source = Ingest::Source(Box::pin(source));
+            let options = IngestOptions::default();
+            if !matches!(
+                ingest(&mut source, &*ingest_buffer, &notify, &options).await,
+                IngestEnd::Exhausted
+            ) {
+                break;
+            }
+
+            if policy.max_reconnects.is_some_and(|max| reconnects >= max) {
+                event!(warn; "Source ended {} times, giving up.", reconnects + 1);
+                break;
+            }
+            reconnects += 1;
+            event!(info; "Source ended, reconnecting in {:?}.", policy.backoff);
+            runtime::sleep(policy.backoff).await;
+            if notify.is_closed() {
+                break;
+            }
+        }
+        event!(info; "Source of external buffer stream is ended.");
+    });
+    if let Err(e) = spawned {
+        event!(error; "Failed to start the ingest task: {}", e);
+        notify.stop();
+    }
+
+    ExternalBufferedStream::from_parts(buffer, notify)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_items_of_every_session_are_buffered() {
+        let sessions = Arc::new(AtomicUsize::new(0));
+        let make_source = move || {
+            let session = sessions.fetch_add(1, Ordering::Relaxed) as i32;
+            async move {
+                // two sessions, then the source is gone for good
+                (session < 2).then(|| futures::stream::iter(session * 10..session * 10 + 3))
+            }
+        };
+
+        let stream = create_reconnecting_stream(
+            make_source,
+            MemoryBuffer::default(),
+            ReconnectPolicy::new(Duration::from_millis(10)),
+        );
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![0, 1, 2, 10, 11, 12]);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_reconnects() {
+        let stream = create_reconnecting_stream(
+            || async { Some(futures::stream::iter(vec![1])) },
+            MemoryBuffer::default(),
+            ReconnectPolicy::new(Duration::from_millis(10)).max_reconnects(2),
+        );
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![1, 1, 1]);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{Future, Stream};
+
+use crate::notify::Notify;
+use crate::trace::event;
+use crate::{buffer, Error, ExternalBuffer, ExternalBufferedStream};
+
+type PushFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// Where an item whose `RetryGuard` is dropped unfinished goes back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryPosition {
+    /// Pushed to the buffer again, after everything buffered meanwhile
+    Tail,
+    /// Delivered again before anything else in the buffer
+    Front,
+}
+
+/// Stream returned by `ExternalBufferedStream::with_retry_sink`
+pub struct RetryStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    inner: ExternalBufferedStream<T, B, S>,
+    position: RetryPosition,
+    // items given back by dropped guards, delivered or pushed by the next
+    // poll depending on `position`
+    retries: Arc<Mutex<VecDeque<T>>>,
+    // push of an item given back with `RetryPosition::Tail`
+    pushing: Option<PushFuture>,
+}
+
+/// Gives the item it was delivered with back to the stream when dropped,
+/// unless `done` is called once the item is processed.
+pub struct RetryGuard<T, B>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+{
+    item: Option<T>,
+    notify: Arc<Notify>,
+    retries: Arc<Mutex<VecDeque<T>>>,
+    _buffer: PhantomData<fn() -> B>,
+}
+
+impl<T, B, S> ExternalBufferedStream<T, B, S>
+where
+    T: Clone + Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    /// Deliver every item together with a `RetryGuard`, so an item whose
+    /// processing failed is retried instead of lost. Items go back to the
+    /// tail of the buffer by default, see `RetryStream::retry_position`.
+    pub fn with_retry_sink(self) -> RetryStream<T, B, S> {
+        RetryStream {
+            inner: self,
+            position: RetryPosition::Tail,
+            retries: Default::default(),
+            pushing: None,
+        }
+    }
+}
+
+impl<T, B, S> RetryStream<T, B, S>
+where
+    T: Clone + Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    pub fn retry_position(mut self, position: RetryPosition) -> Self {
+        self.position = position;
+        self
+    }
+
+    fn guard(&self, item: &T) -> RetryGuard<T, B> {
+        RetryGuard {
+            item: Some(item.clone()),
+            notify: self.inner.notify.clone(),
+            retries: self.retries.clone(),
+            _buffer: PhantomData,
+        }
+    }
+
+    /// Push the items given back for the tail, one at a time in the order
+    /// they were given back
+    fn poll_push_retries(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(pushing) = self.pushing.as_mut() {
+                let pushed = futures::ready!(pushing.as_mut().poll(cx));
+                self.pushing = None;
+                if let Err(e) = pushed {
+                    event!(error; "Failed to push back an unfinished item: {:?}", e);
+                }
+            }
+            let Some(item) = self.take_retry() else {
+                return Poll::Ready(());
+            };
+            let buffer = self.inner.buffer.clone();
+            self.pushing = Some(Box::pin(
+                async move { buffer::push_item(&*buffer, item).await },
+            ));
+        }
+    }
+
+    fn take_retry(&self) -> Option<T> {
+        self.retries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front()
+    }
+}
+
+impl<T, B, S> Stream for RetryStream<T, B, S>
+where
+    T: Clone + Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    type Item = (T, RetryGuard<T, B>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // S is PhantomData, so here is safe to get mut
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let retried = match this.position {
+            RetryPosition::Front => this.take_retry(),
+            RetryPosition::Tail => {
+                futures::ready!(this.poll_push_retries(cx));
+                None
+            }
+        };
+        let item = match retried {
+            Some(item) => item,
+            None => {
+                // inner is never moved out of the pinned `this`
+                let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+                match futures::ready!(inner.poll_next(cx)) {
+                    Some(item) => item,
+                    None => return Poll::Ready(None),
+                }
+            }
+        };
+        let guard = this.guard(&item);
+        Poll::Ready(Some((item, guard)))
+    }
+}
+
+impl<T, B> RetryGuard<T, B>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+{
+    /// Mark the item as processed, it will not be delivered again
+    pub fn done(mut self) {
+        self.item = None;
+    }
+}
+
+impl<T, B> Drop for RetryGuard<T, B>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+{
+    fn drop(&mut self) {
+        let Some(item) = self.item.take() else {
+            return;
+        };
+
+        // handed to the stream, which may be polled on this very thread,
+        // so nothing here waits on the buffer
+        self.retries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_back(item);
+        self.notify.notify();
+    }
+}
+
+impl<T, B, S> Drop for RetryStream<T, B, S>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+    S: Stream<Item = T>,
+{
+    fn drop(&mut self) {
+        let retries: Vec<T> = self
+            .retries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain(..)
+            .collect();
+        if self.pushing.is_some() {
+            event!(warn; "Retry stream dropped while pushing back an unfinished item.");
+        }
+        let retries = match self.position {
+            RetryPosition::Front => {
+                // the stream's undelivered items first, the retries were
+                // delivered before them so they go in front of those
+                let (pending, rest) = self.inner.restore_front();
+                self.inner.pending = pending;
+                let buffer = &*self.inner.buffer;
+                let mut retries = buffer::push_front_now(buffer, retries);
+                retries.extend(rest);
+                retries
+            }
+            RetryPosition::Tail => retries,
+        };
+        for item in retries {
+            buffer::push_now(&*self.inner.buffer, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_dropped_guard_delivers_item_again() {
+        let stream =
+            ExternalBufferedStream::new(futures::stream::pending(), MemoryBuffer::with_items([1]));
+        let mut stream = stream.with_retry_sink();
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        drop(guard);
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        guard.done();
+        assert_eq!(stream.inner.buffer.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_at_front() {
+        let stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            MemoryBuffer::with_items([1, 2]),
+        );
+        let mut stream = stream
+            .with_retry_sink()
+            .retry_position(RetryPosition::Front);
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        drop(guard);
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        guard.done();
+        let (item, _guard) = stream.next().await.unwrap();
+        assert_eq!(item, 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_at_tail_goes_behind_buffered_items() {
+        let stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            MemoryBuffer::with_items([1, 2]),
+        );
+        let mut stream = stream.with_retry_sink();
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        drop(guard);
+
+        let (item, _guard) = stream.next().await.unwrap();
+        assert_eq!(item, 2);
+        let (item, _guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_stream_keeps_unpushed_retries() {
+        let stream = ExternalBufferedStream::new(
+            futures::stream::pending(),
+            MemoryBuffer::with_items([1, 2]),
+        );
+        let buffer = stream.buffer.clone();
+        let mut stream = stream
+            .with_retry_sink()
+            .retry_position(RetryPosition::Front);
+
+        let (item, guard) = stream.next().await.unwrap();
+        assert_eq!(item, 1);
+        drop(guard);
+        drop(stream);
+
+        let items: Vec<i32> = buffer.items.lock().unwrap().drain(..).collect();
+        assert_eq!(items, vec![1, 2]);
+    }
+}
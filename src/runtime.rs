@@ -1,15 +1,298 @@
-pub fn spawn(fut: impl futures::Future<Output = ()> + Send + 'static) {
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::task::AtomicWaker;
+use futures::Future;
+
+use crate::trace::event;
+use crate::{make_custom_error, Error};
+
+/// Spawns the ingest task, replaceable in tests to simulate a failure
+pub(crate) type Spawner = fn(futures::future::BoxFuture<'static, ()>) -> Result<(), Error>;
+
+/// Run `fut` on the current tokio runtime, or on a new thread without one.
+/// Fails if the thread cannot be created, e.g. when resources run out.
+pub fn spawn(fut: impl futures::Future<Output = ()> + Send + 'static) -> Result<(), Error> {
     #[cfg(feature = "rt-tokio")]
     {
         if tokio::runtime::Handle::try_current().is_ok() {
             tokio::spawn(fut);
-            return;
+            return Ok(());
         }
     }
 
+    std::thread::Builder::new()
+        .spawn(move || {
+            futures::executor::block_on(fut);
+        })
+        .map(|_| ())
+        .map_err(make_custom_error)
+}
+
+pub(crate) fn spawn_boxed(fut: futures::future::BoxFuture<'static, ()>) -> Result<(), Error> {
+    spawn(fut)
+}
+
+/// Run blocking work off the async executor and wait for its result
+#[cfg(any(feature = "sled", feature = "rt-tokio"))]
+pub(crate) async fn spawn_blocking<R: Send + 'static>(f: impl FnOnce() -> R + Send + 'static) -> R {
+    #[cfg(feature = "rt-tokio")]
+    {
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return tokio::task::spawn_blocking(f)
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        }
+    }
+
+    let (tx, rx) = futures::channel::oneshot::channel();
     std::thread::spawn(move || {
-        futures::executor::block_on(fut);
+        let _ = tx.send(f());
     });
+    rx.await.expect("blocking task panicked")
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs blocking work off the async executor, for work repeated per item.
+/// Without a tokio runtime it uses `threads` threads of its own instead of
+/// one per call, started on first use and ended with the pool.
+pub(crate) struct BlockingPool {
+    threads: usize,
+    jobs: OnceLock<mpsc::Sender<Job>>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            jobs: OnceLock::new(),
+        }
+    }
+
+    /// Run `f` on the pool and wait for its result
+    pub(crate) async fn run<R: Send + 'static>(&self, f: impl FnOnce() -> R + Send + 'static) -> R {
+        #[cfg(feature = "rt-tokio")]
+        {
+            if tokio::runtime::Handle::try_current().is_ok() {
+                return spawn_blocking(f).await;
+            }
+        }
+
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = tx.send(f());
+        });
+        // no thread left to take it, run it here rather than never
+        if let Err(mpsc::SendError(job)) = self.jobs().send(job) {
+            job();
+        }
+        rx.await.expect("blocking task panicked")
+    }
+
+    fn jobs(&self) -> &mpsc::Sender<Job> {
+        self.jobs.get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..self.threads {
+                let rx = rx.clone();
+                let spawned = std::thread::Builder::new().spawn(move || loop {
+                    let job = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    match job {
+                        Ok(job) => job(),
+                        // the pool is gone
+                        Err(_) => break,
+                    }
+                });
+                if let Err(e) = spawned {
+                    event!(warn; "Failed to start a blocking pool thread: {}", e);
+                }
+            }
+            tx
+        })
+    }
+}
+
+/// Resolve after `duration` without depending on a runtime's timer
+pub async fn sleep(duration: Duration) {
+    Sleep::new(Instant::now() + duration).await
+}
+
+/// A sleep until a deadline that can be moved, woken by the one timer
+/// thread all sleeps of this crate share
+pub(crate) struct Sleep {
+    entry: Arc<Entry>,
+}
+
+struct Entry {
+    state: Mutex<EntryState>,
+    waker: AtomicWaker,
+}
+
+struct EntryState {
+    deadline: Instant,
+    // when the timer thread looks at the entry next, `None` once fired
+    scheduled: Option<Instant>,
+}
+
+impl Sleep {
+    pub(crate) fn new(deadline: Instant) -> Self {
+        let entry = Arc::new(Entry {
+            state: Mutex::new(EntryState {
+                deadline,
+                scheduled: Some(deadline),
+            }),
+            waker: AtomicWaker::new(),
+        });
+        timer().schedule(deadline, &entry);
+        Self { entry }
+    }
+
+    /// Sleep until `deadline` instead, also after the sleep is over
+    pub(crate) fn reset(&mut self, deadline: Instant) {
+        let mut state = self.entry.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.deadline = deadline;
+        // a later deadline is picked up when the timer looks at the entry
+        if state
+            .scheduled
+            .is_some_and(|scheduled| scheduled <= deadline)
+        {
+            return;
+        }
+        state.scheduled = Some(deadline);
+        drop(state);
+        timer().schedule(deadline, &self.entry);
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.entry.waker.register(cx.waker());
+        let state = self.entry.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.scheduled {
+            Some(_) => Poll::Pending,
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+/// An entry to look at, at `at`, entries of dropped sleeps are skipped
+struct Slot {
+    at: Instant,
+    seq: u64,
+    entry: Weak<Entry>,
+}
+
+impl PartialEq for Slot {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Slot {}
+
+impl PartialOrd for Slot {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Slot {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+#[derive(Default)]
+struct Timer {
+    slots: Mutex<(BinaryHeap<Reverse<Slot>>, u64)>,
+    changed: Condvar,
+}
+
+/// The timer, its thread is started by the first sleep and runs for the
+/// rest of the process
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let timer: &'static Timer = Box::leak(Box::default());
+        std::thread::Builder::new()
+            .name("external-buffer-timer".into())
+            .spawn(move || timer.run())
+            .expect("failed to start the timer thread");
+        timer
+    })
+}
+
+impl Timer {
+    fn schedule(&self, at: Instant, entry: &Arc<Entry>) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let (heap, seq) = &mut *slots;
+        *seq += 1;
+        heap.push(Reverse(Slot {
+            at,
+            seq: *seq,
+            entry: Arc::downgrade(entry),
+        }));
+        // the thread may be waiting for a later slot
+        if heap.peek().is_some_and(|first| first.0.seq == *seq) {
+            self.changed.notify_one();
+        }
+    }
+
+    fn run(&self) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let now = Instant::now();
+            let mut rescheduled = Vec::new();
+            while slots.0.peek().is_some_and(|first| first.0.at <= now) {
+                let Some(Reverse(slot)) = slots.0.pop() else {
+                    break;
+                };
+                let Some(entry) = slot.entry.upgrade() else {
+                    continue;
+                };
+                let mut state = entry.state.lock().unwrap_or_else(|e| e.into_inner());
+                // a reset to an earlier deadline scheduled it again
+                if state.scheduled != Some(slot.at) {
+                    continue;
+                }
+                if state.deadline > now {
+                    state.scheduled = Some(state.deadline);
+                    rescheduled.push((state.deadline, slot.entry));
+                } else {
+                    state.scheduled = None;
+                    drop(state);
+                    entry.waker.wake();
+                }
+            }
+            let (heap, seq) = &mut *slots;
+            for (at, entry) in rescheduled {
+                *seq += 1;
+                heap.push(Reverse(Slot {
+                    at,
+                    seq: *seq,
+                    entry,
+                }));
+            }
+
+            slots = match heap.peek() {
+                Some(first) => {
+                    let wait = first.0.at.saturating_duration_since(now);
+                    self.changed
+                        .wait_timeout(slots, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.changed.wait(slots).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
 }
 
 #[cfg(test)]
@@ -26,7 +309,8 @@ mod tests {
 
         spawn(async move {
             *executed_clone.lock().unwrap() = true;
-        });
+        })
+        .unwrap();
 
         // 等待一下让任务执行完成
         std::thread::sleep(Duration::from_millis(100));
@@ -43,7 +327,8 @@ mod tests {
 
         spawn(async move {
             *executed_clone.lock().unwrap() = true;
-        });
+        })
+        .unwrap();
 
         // 等待一下让任务执行完成
         tokio::time::sleep(Duration::from_millis(100)).await;
@@ -71,7 +356,8 @@ mod tests {
 
         spawn(async move {
             *executed_clone.lock().unwrap() = true;
-        });
+        })
+        .unwrap();
 
         // 等待一下让任务执行完成
         std::thread::sleep(Duration::from_millis(100));
@@ -94,7 +380,8 @@ mod tests {
                 // 模拟一些异步工作，使用标准库的 sleep 而不是 tokio::time::sleep
                 std::thread::sleep(Duration::from_millis(10 * (i + 1) as u64));
                 *counter_clone.lock().unwrap() += 1;
-            });
+            })
+            .unwrap();
         }
 
         // 等待所有任务完成
@@ -108,6 +395,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_blocking_pool_reuses_its_threads() {
+        let pool = BlockingPool::new(2);
+        let threads: std::collections::HashSet<_> = (0..8)
+            .map(|_| futures::executor::block_on(pool.run(|| std::thread::current().id())))
+            .collect();
+        assert!(threads.len() <= 2, "ran on {} threads", threads.len());
+        assert!(!threads.contains(&std::thread::current().id()));
+    }
+
+    #[test]
+    fn test_sleeps_wake_in_deadline_order() {
+        let woken = Arc::new(Mutex::new(Vec::new()));
+        let sleeps = [30u64, 10, 20].map(|ms| {
+            let woken = woken.clone();
+            async move {
+                sleep(Duration::from_millis(ms)).await;
+                woken.lock().unwrap().push(ms);
+            }
+        });
+        let started = std::time::Instant::now();
+        futures::executor::block_on(futures::future::join_all(sleeps));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(*woken.lock().unwrap(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_reset_sleep() {
+        let started = Instant::now();
+        let mut sleep = Sleep::new(started + Duration::from_secs(60));
+        sleep.reset(started + Duration::from_millis(20));
+        futures::executor::block_on(&mut sleep);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // moved out again after it is over
+        sleep.reset(Instant::now() + Duration::from_millis(20));
+        sleep.reset(Instant::now() + Duration::from_millis(60));
+        let restarted = Instant::now();
+        futures::executor::block_on(&mut sleep);
+        assert!(restarted.elapsed() >= Duration::from_millis(50));
+    }
+
     #[test]
     fn test_spawn_with_panic_handling() {
         // 测试任务中的 panic 不会影响主线程
@@ -117,13 +446,15 @@ mod tests {
         // 启动一个会 panic 的任务
         spawn(async {
             panic!("This should not crash the main thread");
-        });
+        })
+        .unwrap();
 
         // 启动另一个正常的任务
         spawn(async move {
             std::thread::sleep(Duration::from_millis(50));
             *executed_clone.lock().unwrap() = true;
-        });
+        })
+        .unwrap();
 
         // 等待一下
         std::thread::sleep(Duration::from_millis(100));
@@ -1,5 +1,17 @@
+// A backend implements `ExternalBufferSerde` for every type of its format,
+// so two of them can't be enabled at once. A new one has to come with a
+// `compile_error!` for enabling it along with `bincode`, naming the wrapper
+// types such as `MultiFormat` as the way to mix formats.
 #[cfg(feature = "bincode")]
 pub mod bincode;
+#[cfg(feature = "encryption")]
+mod encrypted;
+#[cfg(feature = "encryption")]
+pub use encrypted::EncryptionKey;
+mod multi_format;
+pub use multi_format::{ItemFormat, MultiFormat, SerdeFormat};
+mod pool;
+pub use pool::BufferPool;
 
 use crate::Error;
 
@@ -9,5 +21,12 @@ use crate::Error;
 pub trait ExternalBufferSerde: Sized {
     fn into_external_buffer(self) -> Result<Vec<u8>, Error>;
 
+    /// Same as `into_external_buffer`, but may serialize into a buffer
+    /// taken from `pool`. The default ignores the pool.
+    fn into_external_buffer_pooled(self, pool: &BufferPool) -> Result<Vec<u8>, Error> {
+        let _ = pool;
+        self.into_external_buffer()
+    }
+
     fn from_external_buffer(value: &[u8]) -> Result<Self, Error>;
 }
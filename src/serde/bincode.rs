@@ -1,10 +1,13 @@
-use bincode::{config, decode_from_slice, encode_to_vec};
+use bincode::{config, decode_from_slice, encode_into_std_write, encode_to_vec};
 pub use bincode::{Decode, Encode};
 
 use crate::Error;
 
-use super::ExternalBufferSerde;
+use super::{BufferPool, ExternalBufferSerde};
 
+/// Any bincode type can be buffered. Unit and other field-less types such
+/// as `()` encode to no bytes at all, so a sled buffer keeps them as keys
+/// with empty values, which makes counting/signaling streams cheap.
 impl<T> ExternalBufferSerde for T
 where
     T: Encode + Decode<()>,
@@ -13,6 +16,12 @@ where
         Ok(encode_to_vec(self, config::standard())?)
     }
 
+    fn into_external_buffer_pooled(self, pool: &BufferPool) -> Result<Vec<u8>, Error> {
+        let mut buffer = pool.take();
+        encode_into_std_write(self, &mut buffer, config::standard())?;
+        Ok(buffer)
+    }
+
     fn from_external_buffer(buffer: &[u8]) -> Result<T, Error> {
         Ok(decode_from_slice(buffer, config::standard()).map(|(u, _)| u)?)
     }
@@ -174,6 +183,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_roundtrip_multiple_times() {
         let mut current = TestStruct {
             id: 1,
@@ -0,0 +1,190 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+use crate::Error;
+
+// the random nonce is stored in front of the ciphertext
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The 256 bit key `ExternalBufferSled::with_encryption` seals items with,
+/// using ChaCha20-Poly1305, so items are encrypted at rest. Each item gets
+/// its own random nonce, and the tree and key the item is stored under are
+/// authenticated along with it: an item moved to another position fails
+/// to decrypt like a tampered one.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+        }
+    }
+
+    /// Bytes a sealed value is larger than its plaintext
+    pub(crate) const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+    /// Encrypt `plaintext` stored at `position`, the bytes naming where
+    /// it is stored
+    pub(crate) fn seal(&self, position: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: position,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::EncryptionFailed)?;
+
+        let mut value = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        value.extend_from_slice(&nonce);
+        value.extend_from_slice(&ciphertext);
+        Ok(value)
+    }
+
+    /// Decrypt a value `seal` stored at `position`, fails with
+    /// `Error::DecryptionFailed` if it was tampered with, sealed with
+    /// another key or for another position
+    pub(crate) fn open(&self, position: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+        if value.len() < NONCE_LEN {
+            return Err(Error::DecryptionFailed);
+        }
+        let (nonce, ciphertext) = value.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: position,
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| Error::DecryptionFailed)
+    }
+}
+
+// never print the key
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(all(test, feature = "bincode"))]
+mod tests {
+    use super::*;
+    use crate::{ExternalBuffer, ExternalBufferSled};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_encrypted_at_rest() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_encryption(EncryptionKey::new([42; 32]));
+        let plaintext = "alice@example.com".to_string();
+
+        buffer.push(plaintext.clone()).await.unwrap();
+        buffer.push(plaintext.clone()).await.unwrap();
+
+        let key = 0u64.to_be_bytes();
+        let raw = buffer.db().get(key).unwrap().unwrap();
+        assert!(!raw
+            .windows(plaintext.len())
+            .any(|window| window == plaintext.as_bytes()));
+
+        let item: Option<String> = buffer.shift().await.unwrap();
+        assert_eq!(item.unwrap(), plaintext);
+
+        // flip a byte of the second item's ciphertext
+        let key = 1u64.to_be_bytes();
+        let mut raw = buffer.db().get(key).unwrap().unwrap().to_vec();
+        let last = raw.len() - 1;
+        raw[last] ^= 1;
+        buffer.db().insert(key, raw).unwrap();
+
+        let result: Result<Option<String>, Error> = buffer.shift().await;
+        assert!(matches!(result, Err(Error::DecryptionFailed)));
+    }
+
+    #[tokio::test]
+    async fn test_items_swapped_on_disk_fail_to_decrypt() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_encryption(EncryptionKey::new([42; 32]));
+        buffer.push("first".to_string()).await.unwrap();
+        buffer.push("second".to_string()).await.unwrap();
+
+        let (first, second) = (0u64.to_be_bytes(), 1u64.to_be_bytes());
+        let first_value = buffer.db().get(first).unwrap().unwrap();
+        let second_value = buffer.db().get(second).unwrap().unwrap();
+        buffer.db().insert(first, second_value).unwrap();
+        buffer.db().insert(second, first_value).unwrap();
+
+        let result: Result<Option<String>, Error> = buffer.shift().await;
+        assert!(matches!(result, Err(Error::DecryptionFailed)));
+    }
+
+    #[tokio::test]
+    async fn test_push_front_reseals_moved_items() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_encryption(EncryptionKey::new([42; 32]));
+        buffer.push(1).await.unwrap();
+        buffer.push(2).await.unwrap();
+
+        // the head is at key 0, the items are moved up to make room
+        buffer.push_front(0).unwrap();
+
+        let items: Vec<i32> = buffer.drain_all().unwrap();
+        assert_eq!(items, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_partitions_encrypted_at_rest() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db"))
+            .unwrap()
+            .with_encryption(EncryptionKey::new([42; 32]));
+        let plaintext = "alice@example.com".to_string();
+        buffer.push_partition(1, plaintext.clone()).unwrap();
+        buffer.push_partition(1, plaintext.clone()).unwrap();
+
+        let partitions = buffer
+            .db()
+            .open_tree(b"__external_buffer_partitions")
+            .unwrap();
+        assert_eq!(partitions.len(), 2);
+        for value in partitions.iter().values() {
+            assert!(!value
+                .unwrap()
+                .windows(plaintext.len())
+                .any(|window| window == plaintext.as_bytes()));
+        }
+        let item: Option<String> = buffer.shift_partition(1).unwrap();
+        assert_eq!(item.unwrap(), plaintext);
+
+        // a partition item copied into the items does not decrypt there
+        let value = partitions.iter().values().next().unwrap().unwrap();
+        buffer.db().insert(0u64.to_be_bytes(), value).unwrap();
+        let result: Result<Option<String>, Error> = buffer.peek_at(0);
+        assert!(matches!(result, Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_wrong_key_fails_to_open() {
+        let sealed = EncryptionKey::new([1; 32]).seal(b"7", b"secret").unwrap();
+        assert_eq!(sealed.len(), b"secret".len() + EncryptionKey::OVERHEAD);
+        assert_eq!(
+            EncryptionKey::new([1; 32]).open(b"7", &sealed).unwrap(),
+            b"secret"
+        );
+        assert!(matches!(
+            EncryptionKey::new([2; 32]).open(b"7", &sealed),
+            Err(Error::DecryptionFailed)
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+use std::marker::PhantomData;
+
+use crate::Error;
+
+use super::ExternalBufferSerde;
+
+/// A serialization format values of `MultiFormat` can be written in
+pub trait ItemFormat<T> {
+    /// Stored in front of every value written in this format, must be
+    /// unique among the formats of one buffer
+    const TAG: u8;
+
+    fn encode(item: T) -> Result<Vec<u8>, Error>;
+
+    fn decode(value: &[u8]) -> Result<T, Error>;
+}
+
+/// The `ExternalBufferSerde` impl of the item as a format, bincode for
+/// bincode types, tagged with 0
+pub struct SerdeFormat;
+
+impl<T: ExternalBufferSerde> ItemFormat<T> for SerdeFormat {
+    const TAG: u8 = 0;
+
+    fn encode(item: T) -> Result<Vec<u8>, Error> {
+        item.into_external_buffer()
+    }
+
+    fn decode(value: &[u8]) -> Result<T, Error> {
+        T::from_external_buffer(value)
+    }
+}
+
+/// Serde wrapper for migrating a buffer from one format to another. Values
+/// are written in `W` behind a 1 byte format tag, and read with `W` or `R`
+/// depending on their tag, so a buffer can hold entries of both while the
+/// old ones drain.
+pub struct MultiFormat<T, W, R> {
+    inner: T,
+    _formats: PhantomData<fn() -> (W, R)>,
+}
+
+impl<T, W, R> MultiFormat<T, W, R> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            _formats: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, W, R> ExternalBufferSerde for MultiFormat<T, W, R>
+where
+    W: ItemFormat<T>,
+    R: ItemFormat<T>,
+{
+    fn into_external_buffer(self) -> Result<Vec<u8>, Error> {
+        let encoded = W::encode(self.inner)?;
+        let mut value = Vec::with_capacity(1 + encoded.len());
+        value.push(W::TAG);
+        value.extend_from_slice(&encoded);
+        Ok(value)
+    }
+
+    fn from_external_buffer(value: &[u8]) -> Result<Self, Error> {
+        let inner = match value.split_first() {
+            Some((&tag, encoded)) if tag == W::TAG => W::decode(encoded)?,
+            Some((&tag, encoded)) if tag == R::TAG => R::decode(encoded)?,
+            Some((&tag, _)) => return Err(Error::UnknownFormat(tag)),
+            None => return Err(Error::UnknownFormat(0)),
+        };
+        Ok(Self::new(inner))
+    }
+}
+
+#[cfg(all(test, feature = "sled", feature = "bincode"))]
+mod tests {
+    use super::*;
+    use crate::{ExternalBuffer, ExternalBufferSled};
+    use bincode::{Decode, Encode};
+    use tempfile::TempDir;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct Reading {
+        id: u32,
+        name: String,
+    }
+
+    struct JsonFormat;
+
+    impl ItemFormat<Reading> for JsonFormat {
+        const TAG: u8 = 1;
+
+        fn encode(item: Reading) -> Result<Vec<u8>, Error> {
+            let value = serde_json::json!({ "id": item.id, "name": item.name });
+            Ok(value.to_string().into_bytes())
+        }
+
+        fn decode(value: &[u8]) -> Result<Reading, Error> {
+            let value: serde_json::Value =
+                serde_json::from_slice(value).map_err(crate::make_custom_error)?;
+            Ok(Reading {
+                id: value["id"].as_u64().unwrap() as u32,
+                name: value["name"].as_str().unwrap().to_string(),
+            })
+        }
+    }
+
+    type Before = MultiFormat<Reading, SerdeFormat, JsonFormat>;
+    type After = MultiFormat<Reading, JsonFormat, SerdeFormat>;
+
+    #[tokio::test]
+    async fn test_reads_mixed_formats() {
+        let temp_dir = TempDir::new().unwrap();
+        let buffer = ExternalBufferSled::new(temp_dir.path().join("test_db")).unwrap();
+
+        let old = Reading {
+            id: 1,
+            name: "bincode".to_string(),
+        };
+        let new = Reading {
+            id: 2,
+            name: "json".to_string(),
+        };
+        buffer.push(Before::new(old.clone())).await.unwrap();
+        buffer.push(After::new(new.clone())).await.unwrap();
+
+        let first: After = buffer.shift().await.unwrap().unwrap();
+        let second: After = buffer.shift().await.unwrap().unwrap();
+        assert_eq!(first.into_inner(), old);
+        assert_eq!(second.into_inner(), new);
+
+        assert!(matches!(
+            After::from_external_buffer(&[7, 1, 2]),
+            Err(Error::UnknownFormat(7))
+        ));
+    }
+}
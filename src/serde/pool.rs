@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Reusable serialization buffers, so pushing many items does not allocate
+/// and free a `Vec<u8>` per item. See
+/// `ExternalBufferSerde::into_external_buffer_pooled`.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    // most buffers kept around for reuse
+    max_buffers: usize,
+    allocations: AtomicUsize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            allocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// An empty buffer, reused from the pool if one is available
+    pub fn take(&self) -> Vec<u8> {
+        let reused = self.buffers.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        reused.unwrap_or_else(|| {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            Vec::new()
+        })
+    }
+
+    /// Give a buffer back, it is dropped if the pool is full already
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    /// Number of buffers `take` had to create instead of reusing one
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuses_returned_buffers() {
+        let pool = BufferPool::new(2);
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(b"hello");
+        pool.put(buffer);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 5);
+        assert_eq!(pool.allocations(), 1);
+    }
+}
@@ -0,0 +1,111 @@
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::{stream, Future, Sink};
+
+use crate::notify::Notify;
+use crate::{Error, ExternalBuffer, ExternalBufferedStream};
+
+type PushFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// Create a durable channel: items sent into the `BufferSink` are pushed
+/// into `buffer` and delivered by the stream. Once the sink is closed (or
+/// dropped), the stream drains the buffer and then finishes.
+pub fn buffer_channel<T, B>(
+    buffer: B,
+) -> (
+    BufferSink<T, B>,
+    ExternalBufferedStream<T, B, stream::Empty<T>>,
+)
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    let buffer = Arc::new(buffer);
+    let notify = Arc::new(Notify::default());
+    let sink = BufferSink {
+        buffer: buffer.clone(),
+        notify: notify.clone(),
+        pending: None,
+        _item: PhantomData,
+    };
+    (sink, ExternalBufferedStream::from_parts(buffer, notify))
+}
+
+/// The sending half of `buffer_channel`
+pub struct BufferSink<T, B> {
+    buffer: Arc<B>,
+    notify: Arc<Notify>,
+    // the push in progress
+    pending: Option<PushFuture>,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T, B> Sink<T> for BufferSink<T, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let buffer = this.buffer.clone();
+        let notify = this.notify.clone();
+        this.pending = Some(Box::pin(async move {
+            buffer.push(item).await?;
+            notify.notify();
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if let Some(pending) = this.pending.as_mut() {
+            let result = futures::ready!(pending.as_mut().poll(cx));
+            this.pending = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        futures::ready!(Pin::new(&mut *this).poll_flush(cx))?;
+        this.notify.stop();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T, B> Drop for BufferSink<T, B> {
+    fn drop(&mut self) {
+        self.notify.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::{SinkExt, StreamExt};
+
+    #[tokio::test]
+    async fn test_close_finishes_stream() {
+        let (mut sink, stream) = buffer_channel(MemoryBuffer::default());
+
+        for i in 1..=3 {
+            sink.send(i).await.unwrap();
+        }
+        sink.close().await.unwrap();
+
+        let items: Vec<i32> = stream.collect().await;
+        assert_eq!(items, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hdrhistogram::Histogram;
+
+/// How long items stayed buffered, from push to shift, over the lifetime
+/// of a buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Number of items shifted
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+/// Records push-to-shift latencies in microseconds
+pub(crate) struct LatencyRecorder {
+    histogram: Mutex<Histogram<u64>>,
+}
+
+impl LatencyRecorder {
+    pub(crate) fn new() -> Self {
+        // 3 significant digits, auto resized for outliers
+        let histogram = Histogram::new(3).expect("valid histogram precision");
+        Self {
+            histogram: Mutex::new(histogram),
+        }
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let mut histogram = self.histogram.lock().unwrap_or_else(|e| e.into_inner());
+        // grows to fit the value, only clamps if it can not
+        if histogram.record(micros).is_err() {
+            histogram.saturating_record(micros);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyStats {
+        let histogram = self.histogram.lock().unwrap_or_else(|e| e.into_inner());
+        LatencyStats {
+            count: histogram.len(),
+            min: Duration::from_micros(histogram.min()),
+            max: Duration::from_micros(histogram.max()),
+            mean: Duration::from_micros(histogram.mean() as u64),
+            p50: Duration::from_micros(histogram.value_at_quantile(0.5)),
+            p90: Duration::from_micros(histogram.value_at_quantile(0.9)),
+            p99: Duration::from_micros(histogram.value_at_quantile(0.99)),
+        }
+    }
+}
+
+/// Microseconds since the unix epoch, what pushed values are stamped with
+pub(crate) fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+use futures::{Future, Stream};
+
+use crate::trace::event;
+use crate::{Error, ExternalBuffer};
+
+type ShiftFuture<T> = Pin<Box<dyn Future<Output = Result<Option<T>, Error>> + Send>>;
+
+/// Many buffers consumed as one stream of `(id, item)`, for when thousands
+/// of small streams would each cost a notify and an ingest task. Items are
+/// pushed straight into the buffers through a `StreamSetHandle`, which marks
+/// the buffer ready on a single shared readiness queue and wakes the one
+/// consumer. Ready buffers are served round robin, one item at a time.
+///
+/// A shift error is logged and that buffer is skipped until its next push,
+/// the other buffers keep being served.
+pub struct StreamSet<T, B> {
+    shared: Arc<Shared<B>>,
+    // the shift in flight and the buffer it is for
+    pending: Option<(usize, ShiftFuture<T>)>,
+}
+
+/// Registers buffers of a `StreamSet` and pushes into them, cheap to clone
+pub struct StreamSetHandle<T, B> {
+    shared: Arc<Shared<B>>,
+    _item: PhantomData<fn(T)>,
+}
+
+struct Shared<B> {
+    buffers: RwLock<Vec<Arc<B>>>,
+    ready: Mutex<ReadySet>,
+    waker: AtomicWaker,
+    closed: AtomicBool,
+}
+
+/// Ids of buffers that may have items, each queued at most once
+#[derive(Default)]
+struct ReadySet {
+    queue: VecDeque<usize>,
+    queued: Vec<bool>,
+}
+
+impl<B> Shared<B> {
+    fn mark_ready(&self, id: usize) {
+        let mut ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        if !ready.queued[id] {
+            ready.queued[id] = true;
+            ready.queue.push_back(id);
+        }
+        drop(ready);
+        self.waker.wake();
+    }
+
+    fn next_ready(&self) -> Option<usize> {
+        let mut ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        let id = ready.queue.pop_front()?;
+        // unmark before shifting, so a push racing with it queues it again
+        ready.queued[id] = false;
+        Some(id)
+    }
+
+    fn buffer(&self, id: usize) -> Result<Arc<B>, Error> {
+        let buffers = self.buffers.read().unwrap_or_else(|e| e.into_inner());
+        buffers.get(id).cloned().ok_or(Error::UnknownStream(id))
+    }
+}
+
+impl<T, B> StreamSet<T, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                buffers: Default::default(),
+                ready: Default::default(),
+                waker: AtomicWaker::new(),
+                closed: AtomicBool::new(false),
+            }),
+            pending: None,
+        }
+    }
+
+    pub fn handle(&self) -> StreamSetHandle<T, B> {
+        StreamSetHandle {
+            shared: self.shared.clone(),
+            _item: PhantomData,
+        }
+    }
+
+    /// Same as `StreamSetHandle::register`
+    pub fn register(&self, buffer: B) -> usize {
+        self.handle().register(buffer)
+    }
+}
+
+impl<T, B> Default for StreamSet<T, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, B> StreamSetHandle<T, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    /// Add a buffer to the set and return its id, items it already holds
+    /// are delivered too
+    pub fn register(&self, buffer: B) -> usize {
+        let has_items = !buffer.is_empty();
+        let mut buffers = self
+            .shared
+            .buffers
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let id = buffers.len();
+        buffers.push(Arc::new(buffer));
+        // grow the marks before the id can be marked ready
+        self.shared
+            .ready
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .queued
+            .push(false);
+        drop(buffers);
+        if has_items {
+            self.shared.mark_ready(id);
+        }
+        id
+    }
+
+    /// Push `item` into the buffer registered as `id`, fails with
+    /// `Error::UnknownStream` if there is none.
+    pub async fn push(&self, id: usize, item: T) -> Result<(), Error> {
+        let buffer = self.shared.buffer(id)?;
+        crate::buffer::push_item(&*buffer, item).await?;
+        self.shared.mark_ready(id);
+        Ok(())
+    }
+
+    /// No more items will be pushed, the set ends once every buffer is
+    /// drained
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+impl<T, B> Clone for StreamSetHandle<T, B> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T, B> Stream for StreamSet<T, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    type Item = (usize, T);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let (id, result) = match this.pending.as_mut() {
+                Some((id, pending)) => match pending.as_mut().poll(cx) {
+                    Poll::Ready(result) => {
+                        let id = *id;
+                        this.pending = None;
+                        (id, result)
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => {
+                    // register before checking, so a push racing with this
+                    // poll still wakes us up
+                    this.shared.waker.register(cx.waker());
+                    // read the flag first, every push before the close is
+                    // queued by the time it is seen
+                    let closed = this.shared.closed.load(Ordering::Acquire);
+                    let Some(id) = this.shared.next_ready() else {
+                        return if closed {
+                            Poll::Ready(None)
+                        } else {
+                            Poll::Pending
+                        };
+                    };
+                    let buffer = match this.shared.buffer(id) {
+                        Ok(buffer) => buffer,
+                        Err(e) => {
+                            event!(error; "Stream set marked an unknown buffer ready: {}", e);
+                            continue;
+                        }
+                    };
+                    match buffer.shift_now() {
+                        Some(result) => (id, result),
+                        None => {
+                            this.pending =
+                                Some((id, Box::pin(async move { buffer.shift().await })));
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            match result {
+                Ok(Some(item)) => {
+                    // may hold more, go to the back of the line
+                    this.shared.mark_ready(id);
+                    return Poll::Ready(Some((id, item)));
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    event!(error, buffer_id = id; "Shift of buffer {} in stream set failed: {}", id, e);
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+    use futures::StreamExt;
+    use rand::seq::IteratorRandom;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[tokio::test]
+    async fn test_yields_items_with_their_ids() {
+        let set = StreamSet::new();
+        let handle = set.handle();
+        for _ in 0..100 {
+            handle.register(MemoryBuffer::default());
+        }
+
+        let mut rng = rand::rng();
+        let subset: BTreeSet<usize> = (0..100).choose_multiple(&mut rng, 20).into_iter().collect();
+        for &id in &subset {
+            for n in 0..3 {
+                handle.push(id, (id * 10 + n) as i32).await.unwrap();
+            }
+        }
+        handle.close();
+
+        let mut received: BTreeMap<usize, Vec<i32>> = BTreeMap::new();
+        let items: Vec<(usize, i32)> = set.collect().await;
+        for (id, item) in items {
+            received.entry(id).or_default().push(item);
+        }
+
+        assert_eq!(received.keys().copied().collect::<BTreeSet<_>>(), subset);
+        for (id, items) in received {
+            let base = (id * 10) as i32;
+            assert_eq!(items, vec![base, base + 1, base + 2]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_to_unknown_id_fails() {
+        let set = StreamSet::new();
+        let handle = set.handle();
+        let id = handle.register(MemoryBuffer::default());
+
+        let result = handle.push(id + 1, 1).await;
+        assert!(matches!(result, Err(Error::UnknownStream(unknown)) if unknown == id + 1));
+
+        handle.push(id, 2).await.unwrap();
+        handle.close();
+        let items: Vec<(usize, i32)> = set.collect().await;
+        assert_eq!(items, vec![(id, 2)]);
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::{Error, ExternalBuffer, SyncExternalBuffer};
+
+/// A plain FIFO buffer so the stream can be tested without features
+#[derive(Default)]
+pub(crate) struct MemoryBuffer {
+    pub(crate) items: Mutex<VecDeque<i32>>,
+}
+
+impl MemoryBuffer {
+    pub(crate) fn with_items(items: impl IntoIterator<Item = i32>) -> Self {
+        Self {
+            items: Mutex::new(items.into_iter().collect()),
+        }
+    }
+}
+
+impl SyncExternalBuffer<i32> for MemoryBuffer {
+    fn push_sync(&self, item: i32) -> Result<(), Error> {
+        self.items.lock()?.push_back(item);
+        Ok(())
+    }
+
+    fn shift_sync(&self) -> Result<Option<i32>, Error> {
+        Ok(self.items.lock()?.pop_front())
+    }
+
+    fn len_sync(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+}
+
+#[async_trait::async_trait]
+impl ExternalBuffer<i32> for MemoryBuffer {
+    async fn push(&self, item: i32) -> Result<(), Error> {
+        self.push_sync(item)
+    }
+
+    async fn shift(&self) -> Result<Option<i32>, Error> {
+        self.shift_sync()
+    }
+
+    fn as_sync(&self) -> Option<&dyn SyncExternalBuffer<i32>> {
+        Some(self)
+    }
+
+    fn push_front_now(&self, item: i32) -> Result<Result<(), Error>, i32> {
+        Ok(self
+            .items
+            .lock()
+            .map(|mut items| items.push_front(item))
+            .map_err(Error::from))
+    }
+
+    fn len(&self) -> usize {
+        self.len_sync()
+    }
+}
+
+/// Global allocator counting the allocations of each thread, so a test can
+/// measure its own without seeing the ones of tests running beside it
+pub(crate) struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run `f` and count the allocations it made on this thread
+#[cfg(feature = "queue")]
+pub(crate) fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(|count| count.get());
+    let result = f();
+    (result, ALLOCATIONS.with(|count| count.get()) - before)
+}
@@ -0,0 +1,15 @@
+/// Emit an internal log event, through `tracing` with its structured fields
+/// when the `tracing` feature is on, otherwise through `log` with the
+/// message only.
+///
+/// `event!(error, buffer_len = len; "Failed to push item: {:?}", e)`
+macro_rules! event {
+    ($level:ident $(, $field:ident = $value:expr)* ; $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($field = $value,)* $($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::$level!($($arg)+);
+    }};
+}
+
+pub(crate) use event;
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{future, stream, Stream, StreamExt};
+
+use crate::ingest::{ingest, Ingest, IngestOptions};
+use crate::notify::{Notify, StopGuard};
+use crate::trace::event;
+use crate::{runtime, ExternalBuffer, ExternalBufferedStream};
+
+/// Errors of the source waiting for the consumer, each with the number of
+/// items pushed before it
+type ErrorQueue<E> = Arc<Mutex<VecDeque<(u64, E)>>>;
+
+/// Buffer the `Ok` items of a fallible source and forward its `Err`s to the
+/// consumer. An error does not end the source, the stream finishes once the
+/// source is exhausted and everything buffered was delivered.
+///
+/// Errors are delivered after the items the source yielded before them, as
+/// long as the buffer is FIFO.
+pub fn create_buffered_try_stream<T, E, S, B>(source: S, buffer: B) -> TryBufferedStream<T, E, B>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    let buffer = Arc::new(buffer);
+    let notify = Arc::new(Notify::default());
+    let errors: ErrorQueue<E> = Default::default();
+
+    let ingest_buffer = buffer.clone();
+    let ingest_notify = notify.clone();
+    let ingest_errors = errors.clone();
+    let spawned = runtime::spawn(async move {
+        let notify = ingest_notify;
+        let _stop = StopGuard(&notify);
+        // every `Ok` before an error is pushed by the time it is pulled
+        let mut pushed = 0;
+        let source = source.filter_map(|result| {
+            let item = match result {
+                Ok(item) => {
+                    pushed += 1;
+                    Some(item)
+                }
+                Err(e) => {
+                    ingest_errors
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push_back((pushed, e));
+                    notify.notify();
+                    None
+                }
+            };
+            future::ready(item)
+        });
+        let mut source = Ingest::Source(Box::pin(source));
+        ingest(
+            &mut source,
+            &*ingest_buffer,
+            &notify,
+            &IngestOptions::default(),
+        )
+        .await;
+        event!(info; "Source of external buffer stream is ended.");
+    });
+    if let Err(e) = spawned {
+        event!(error; "Failed to start the ingest task: {}", e);
+        notify.stop();
+    }
+
+    TryBufferedStream {
+        inner: ExternalBufferedStream::from_parts(buffer, notify),
+        errors,
+        delivered: 0,
+    }
+}
+
+/// The stream returned by `create_buffered_try_stream`
+pub struct TryBufferedStream<T, E, B>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+{
+    inner: ExternalBufferedStream<T, B, stream::Empty<T>>,
+    errors: ErrorQueue<E>,
+    delivered: u64,
+}
+
+impl<T, E, B> TryBufferedStream<T, E, B>
+where
+    T: Send,
+    B: ExternalBuffer<T>,
+{
+    /// Pop the next error, only if every item before it was delivered
+    /// unless `all` is set
+    fn pop_error(&self, all: bool) -> Option<E> {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        match errors.front() {
+            Some((pushed, _)) if all || *pushed <= self.delivered => {
+                errors.pop_front().map(|(_, e)| e)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T, E, B> Stream for TryBufferedStream<T, E, B>
+where
+    T: Send + 'static,
+    B: ExternalBuffer<T> + 'static,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(e) = this.pop_error(false) {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        // inner is never moved out of the pinned `this`
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        match inner.poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                this.delivered += 1;
+                Poll::Ready(Some(Ok(item)))
+            }
+            // the source ended, whatever errors are left come last
+            Poll::Ready(None) => Poll::Ready(this.pop_error(true).map(Err)),
+            // an error may have been queued before the notify the inner
+            // stream just took
+            Poll::Pending => match this.pop_error(false) {
+                Some(e) => Poll::Ready(Some(Err(e))),
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryBuffer;
+
+    #[tokio::test]
+    async fn test_forwards_source_errors() {
+        let source = stream::iter(vec![Ok(1), Err("read failed"), Ok(2)]);
+        let stream = create_buffered_try_stream(source, MemoryBuffer::default());
+
+        let results: Vec<Result<i32, &str>> = stream.collect().await;
+        assert_eq!(results, vec![Ok(1), Err("read failed"), Ok(2)]);
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::sync::Arc;
+
+use futures::stream;
+
+use crate::notify::Notify;
+use crate::{ExternalBuffer, ExternalBufferedStream};
+
+/// The stream returned along with a `BufferWriter`
+pub type BufferWriterStream<B> = ExternalBufferedStream<Vec<u8>, B, stream::Empty<Vec<u8>>>;
+
+/// Create a `std::io::Write` feeding `buffer`: the bytes of each `write`
+/// are pushed as one item and delivered by the stream, so code writing to
+/// a generic writer can fill a durable buffer. Once the writer is dropped,
+/// the stream drains the buffer and then finishes.
+pub fn buffer_writer<B>(buffer: B) -> (BufferWriter<B>, BufferWriterStream<B>)
+where
+    B: ExternalBuffer<Vec<u8>> + 'static,
+{
+    let buffer = Arc::new(buffer);
+    let notify = Arc::new(Notify::default());
+    let writer = BufferWriter {
+        buffer: buffer.clone(),
+        notify: notify.clone(),
+    };
+    (writer, ExternalBufferedStream::from_parts(buffer, notify))
+}
+
+/// The writing half of `buffer_writer`. Buffers without a synchronous side
+/// are pushed to by blocking on their future, so only use it with those
+/// from synchronous code.
+pub struct BufferWriter<B> {
+    buffer: Arc<B>,
+    notify: Arc<Notify>,
+}
+
+impl<B> io::Write for BufferWriter<B>
+where
+    B: ExternalBuffer<Vec<u8>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let item = buf.to_vec();
+        match self.buffer.as_sync() {
+            Some(buffer) => buffer.push_sync(item),
+            None => futures::executor::block_on(self.buffer.push(item)),
+        }
+        .map_err(io::Error::other)?;
+        self.notify.notify();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        futures::executor::block_on(self.buffer.flush()).map_err(io::Error::other)
+    }
+}
+
+impl<B> Drop for BufferWriter<B> {
+    fn drop(&mut self) {
+        self.notify.stop();
+    }
+}
+
+#[cfg(all(test, feature = "queue"))]
+mod tests {
+    use super::*;
+    use crate::ExternalBufferVecDeque;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_writes_are_streamed() {
+        let (mut writer, stream) = buffer_writer(ExternalBufferVecDeque::new());
+        for chunk in [&b"one"[..], b"two", b"three"] {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let items: Vec<Vec<u8>> = stream.collect().await;
+        assert_eq!(
+            items,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+}
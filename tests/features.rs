@@ -0,0 +1,65 @@
+//! Every backend, serde and runtime is an optional dependency behind its own
+//! feature, so `default-features = false` leaves only the core stream.
+
+use std::process::Command;
+
+#[test]
+fn test_backends_are_optional() {
+    let output = Command::new(env!("CARGO"))
+        .args([
+            "metadata",
+            "--no-deps",
+            "--offline",
+            "--format-version",
+            "1",
+        ])
+        .arg("--manifest-path")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let package = &metadata["packages"][0];
+
+    for dependency in package["dependencies"].as_array().unwrap() {
+        let name = dependency["name"].as_str().unwrap();
+        // normal dependencies only, dev dependencies are never pulled by users
+        if dependency["kind"].is_null()
+            && [
+                "sled",
+                "bincode",
+                "tokio",
+                "tokio-util",
+                "chacha20poly1305",
+                "hdrhistogram",
+                "tracing",
+                "spin",
+            ]
+            .contains(&name)
+        {
+            assert_eq!(dependency["optional"], true, "{} must be optional", name);
+        }
+    }
+
+    // the in-memory queue needs no dependency at all
+    assert_eq!(package["features"]["queue"], serde_json::json!([]));
+}
+
+/// Run with `cargo test --no-default-features --features queue`
+#[cfg(all(
+    feature = "queue",
+    not(feature = "sled"),
+    not(feature = "bincode"),
+    not(feature = "rt-tokio")
+))]
+#[tokio::test]
+async fn test_queue_only_build() {
+    use futures::StreamExt;
+
+    let stream =
+        external_buffered_stream::create_queued_stream(futures::stream::iter(1..=3)).unwrap();
+    let mut items: Vec<i32> = stream.collect().await;
+    items.sort();
+    assert_eq!(items, vec![1, 2, 3]);
+}